@@ -0,0 +1,602 @@
+use game::map::{Map, MapLoader};
+use game::map_generator::{MapGenerator, GeneratorPreset, PRESET_NAMES};
+use game::ascii::AsciiRenderer;
+use game::image_export::{ImageExporter, ColorRamp, RAMP_NAMES};
+use game::map_generator::Noise;
+use game::image_import::{ImageImporter, Palette};
+use game::tileset::Tileset;
+use game::tiled::TiledMap;
+use game::connectivity::ConnectivityMap;
+use game::world::World;
+use game::simulation::Simulation;
+use game::replay::{Replay, HASH_INTERVAL};
+use game::Game;
+use std::time::Instant;
+use std::process;
+
+pub fn usage() -> String {
+	format!("Usage:\n\
+		\tzombie                   start the game\n\
+		\tzombie editor            start the editor\n\
+		\tzombie generate --seed <s> --size <w>x<h> --preset <name> --out <file>\n\
+		\tzombie preview <file> [--layer <n> | --collision] [--scale <n>] [--color]\n\
+		\tzombie export <file> --out <png> [--scale <pixels per tile>] [--tileset-image] [--overlay roads,buildings,objects,collision]\n\
+		\tzombie export-noise --seed <s> --size <w>x<h> --out <png> [--octaves <n>] [--persistance <p>] [--ramp <name>] [--scale <n>]\n\
+		\tzombie import <png> --out <file> [--palette <mapping.json> | --heightmap] [--name <name>]\n\
+		\tzombie convert <file> --out <file>\n\
+		\tzombie tiled-export <file> --out <tiled.tmx|tiled.json>\n\
+		\tzombie tiled-import <tiled.tmx|tiled.json> --out <file>\n\
+		\tzombie simulate [<file>] [--ticks <n>] [--report <every n ticks>]   run without a window\n\
+		\tzombie record --out <replay> [<file>] [--seed <s>]\n\
+		\tzombie replay <replay> [--speed <n>] [--headless]   space pauses, 1 to 4 set the speed\n\
+		\nMap files ending in .zmap are binary, anything else is JSON\n\
+		\nPresets: {}\nRamps: {}", PRESET_NAMES.join(", "), RAMP_NAMES.join(", "))
+}
+
+/// Runs a subcommand with its parsed options. Errors and the usage go to stderr,
+/// so they do not end up in output that is piped on
+pub fn run<O>(parsed: Result<O, String>, command: fn(&O)) {
+	try_run(parsed, |options| {
+		command(options);
+		Ok(())
+	});
+}
+
+/// Like run, for subcommands that can fail after parsing
+pub fn try_run<O, F: Fn(&O) -> Result<(), String>>(parsed: Result<O, String>, command: F) {
+	let options = match parsed {
+		Ok(options) => options,
+		Err(e) => {
+			eprintln!("{}\n\n{}", e, usage());
+			process::exit(1);
+		},
+	};
+	if let Err(e) = command(&options) {
+		eprintln!("{}", e);
+		process::exit(1);
+	}
+}
+
+pub struct GenerateOptions {
+	pub seed: Vec<usize>,
+	pub seed_text: String,
+	pub width: usize,
+	pub height: usize,
+	pub preset: GeneratorPreset,
+	pub out: String,
+}
+
+impl GenerateOptions {
+	pub fn parse(args: &[String]) -> Result<Self, String> {
+		let mut seed_text = "0".to_string();
+		let mut size = (128, 128);
+		let mut preset = GeneratorPreset::new_default();
+		let mut out: Option<String> = None;
+		let mut i = 0;
+		while i < args.len() {
+			let value = match args.get(i + 1) {
+				Some(value) => value.clone(),
+				None => return Err(format!("Missing value for {}", args[i])),
+			};
+			match args[i].as_str() {
+				"--seed" => seed_text = value,
+				"--size" => size = parse_size(&value)?,
+				"--preset" => preset = match GeneratorPreset::from_name(&value) {
+					Some(preset) => preset,
+					None => return Err(format!("Unknown preset '{}'", value)),
+				},
+				"--out" => out = Some(value),
+				_ => return Err(format!("Unknown option '{}'", args[i])),
+			}
+			i += 2;
+		}
+		match out {
+			Some(out) => Ok(GenerateOptions {
+				seed: parse_seed(&seed_text),
+				seed_text: seed_text,
+				width: size.0,
+				height: size.1,
+				preset: preset,
+				out: out,
+			}),
+			None => Err("Missing --out".to_string()),
+		}
+	}
+}
+
+/// Numbers are used as they are, any other text is used byte by byte
+pub fn parse_seed(text: &str) -> Vec<usize> {
+	match text.parse::<usize>() {
+		Ok(n) => vec![n],
+		Err(_) => text.bytes().map(|b| b as usize).collect(),
+	}
+}
+
+fn parse_size(text: &str) -> Result<(usize, usize), String> {
+	let parts: Vec<&str> = text.split('x').collect();
+	if parts.len() != 2 {
+		return Err(format!("Size '{}' is not of the form <w>x<h>", text));
+	}
+	match (parts[0].parse::<usize>(), parts[1].parse::<usize>()) {
+		(Ok(w), Ok(h)) if w >= 16 && h >= 16 => Ok((w, h)),
+		(Ok(_), Ok(_)) => Err("Maps must be at least 16x16".to_string()),
+		_ => Err(format!("Size '{}' is not of the form <w>x<h>", text)),
+	}
+}
+
+pub fn generate(options: &GenerateOptions) {
+	let mut generator = MapGenerator::new_seed(&options.seed);
+	let map = generator.generate_map(options.width, options.height, &options.preset);
+	let name = format!("Generated {} map", options.preset.name);
+	let description = format!("seed {}, {}x{}", options.seed_text, options.width, options.height);
+	MapLoader::from_map(&map, &name, &description).save_path(&options.out);
+	println!("Wrote {}x{} map with {} objects to {}", options.width, options.height, map.get_objects().len(), options.out);
+	println!("{}", ConnectivityMap::analyze(map.get_layer(0).tiles(), Some(map.get_collision())).report());
+}
+
+pub struct PreviewOptions {
+	pub file: String,
+	pub layer: usize,
+	pub collision: bool,
+	pub renderer: AsciiRenderer,
+}
+
+impl PreviewOptions {
+	pub fn parse(args: &[String]) -> Result<Self, String> {
+		let mut file: Option<String> = None;
+		let mut layer = 0;
+		let mut collision = false;
+		let mut renderer = AsciiRenderer::new();
+		let mut i = 0;
+		while i < args.len() {
+			match args[i].as_str() {
+				"--color" => renderer.color = true,
+				"--collision" => collision = true,
+				"--layer" | "--scale" => {
+					let value = match args.get(i + 1).map(|v| v.parse::<usize>()) {
+						Some(Ok(value)) => value,
+						_ => return Err(format!("{} needs a number", args[i])),
+					};
+					if args[i] == "--layer" {
+						if value > 2 {
+							return Err("Maps only have layers 0, 1 and 2".to_string());
+						}
+						layer = value;
+					}
+					else {
+						renderer.scale = value;
+					}
+					i += 1;
+				},
+				arg if !arg.starts_with("--") && file.is_none() => file = Some(arg.to_string()),
+				_ => return Err(format!("Unknown option '{}'", args[i])),
+			}
+			i += 1;
+		}
+		match file {
+			Some(file) => Ok(PreviewOptions {
+				file: file,
+				layer: layer,
+				collision: collision,
+				renderer: renderer,
+			}),
+			None => Err("Missing map file".to_string()),
+		}
+	}
+}
+
+pub fn preview(options: &PreviewOptions) {
+	let map = MapLoader::load_path(&options.file);
+	if options.collision {
+		options.renderer.print_collision(map.get_collision());
+	}
+	else {
+		options.renderer.print_tiles(map.get_layer(options.layer).tiles());
+	}
+}
+
+/// Splits arguments into the first free argument and the value of every --option
+fn parse_flags(args: &[String], switches: &[&str]) -> Result<(Option<String>, Vec<(String, String)>), String> {
+	let mut free: Option<String> = None;
+	let mut flags: Vec<(String, String)> = Vec::new();
+	let mut i = 0;
+	while i < args.len() {
+		if switches.contains(&args[i].as_str()) {
+			flags.push((args[i].clone(), String::new()));
+		}
+		else if args[i].starts_with("--") {
+			match args.get(i + 1) {
+				Some(value) => flags.push((args[i].clone(), value.clone())),
+				None => return Err(format!("Missing value for {}", args[i])),
+			}
+			i += 1;
+		}
+		else if free.is_none() {
+			free = Some(args[i].clone());
+		}
+		else {
+			return Err(format!("Unexpected argument '{}'", args[i]));
+		}
+		i += 1;
+	}
+	Ok((free, flags))
+}
+
+fn parse_number<T: ::std::str::FromStr>(flag: &str, value: &str) -> Result<T, String> {
+	value.parse::<T>().map_err(|_| format!("{} needs a number", flag))
+}
+
+pub struct ExportOptions {
+	pub file: String,
+	pub out: String,
+	pub pixels_per_tile: u32,
+	pub tileset_image: bool,
+	pub overlays: Vec<String>,
+}
+
+impl ExportOptions {
+	pub fn parse(args: &[String]) -> Result<Self, String> {
+		let (file, flags) = parse_flags(args, &["--tileset-image"])?;
+		let mut options = ExportOptions {
+			file: file.ok_or("Missing map file".to_string())?,
+			out: String::new(),
+			pixels_per_tile: 8,
+			tileset_image: false,
+			overlays: Vec::new(),
+		};
+		for (flag, value) in flags {
+			match flag.as_str() {
+				"--out" => options.out = value,
+				"--scale" => options.pixels_per_tile = parse_number(&flag, &value)?,
+				"--tileset-image" => options.tileset_image = true,
+				"--overlay" => {
+					for overlay in value.split(',') {
+						match overlay {
+							"roads" | "buildings" | "objects" | "collision" => options.overlays.push(overlay.to_string()),
+							_ => return Err(format!("Unknown overlay '{}'", overlay)),
+						}
+					}
+				},
+				_ => return Err(format!("Unknown option '{}'", flag)),
+			}
+		}
+		if options.out.is_empty() {
+			return Err("Missing --out".to_string());
+		}
+		Ok(options)
+	}
+}
+
+pub fn export(options: &ExportOptions) {
+	let map = MapLoader::load_path(&options.file);
+	let mut exporter = ImageExporter::new(options.pixels_per_tile);
+	if options.tileset_image {
+		exporter.load_tileset_image();
+	}
+	let tiles = map.get_layer(0).tiles();
+	let mut image = exporter.render_map(&map);
+	for overlay in options.overlays.iter() {
+		match overlay.as_str() {
+			"roads" => exporter.overlay_roads(&mut image, tiles),
+			"buildings" => exporter.overlay_buildings(&mut image, tiles),
+			"objects" => exporter.overlay_objects(&mut image, map.get_objects()),
+			"collision" => exporter.overlay_collision(&mut image, map.get_collision()),
+			// Unknown names are rejected by ExportOptions::parse
+			_ => (),
+		}
+	}
+	ImageExporter::save(&image, &options.out);
+	println!("Wrote {}x{} image to {}", image.width(), image.height(), options.out);
+}
+
+pub struct NoiseExportOptions {
+	pub seed: Vec<usize>,
+	pub width: usize,
+	pub height: usize,
+	pub octaves: usize,
+	pub persistance: f32,
+	pub ramp: ColorRamp,
+	pub pixels_per_tile: u32,
+	pub out: String,
+}
+
+impl NoiseExportOptions {
+	pub fn parse(args: &[String]) -> Result<Self, String> {
+		let (free, flags) = parse_flags(args, &[])?;
+		if let Some(arg) = free {
+			return Err(format!("Unexpected argument '{}'", arg));
+		}
+		let mut options = NoiseExportOptions {
+			seed: parse_seed("0"),
+			width: 128,
+			height: 128,
+			octaves: 6,
+			persistance: 0.6,
+			ramp: ColorRamp::new_grey(),
+			pixels_per_tile: 4,
+			out: String::new(),
+		};
+		for (flag, value) in flags {
+			match flag.as_str() {
+				"--seed" => options.seed = parse_seed(&value),
+				"--size" => {
+					let (width, height) = parse_size(&value)?;
+					options.width = width;
+					options.height = height;
+				},
+				"--octaves" => options.octaves = parse_number(&flag, &value)?,
+				"--persistance" => options.persistance = parse_number(&flag, &value)?,
+				"--ramp" => options.ramp = ColorRamp::from_name(&value).ok_or(format!("Unknown ramp '{}'", value))?,
+				"--scale" => options.pixels_per_tile = parse_number(&flag, &value)?,
+				"--out" => options.out = value,
+				_ => return Err(format!("Unknown option '{}'", flag)),
+			}
+		}
+		if options.out.is_empty() {
+			return Err("Missing --out".to_string());
+		}
+		Ok(options)
+	}
+}
+
+pub fn export_noise(options: &NoiseExportOptions) {
+	let noise = Noise::new_perlin_from_seed(&options.seed, options.width, options.height, options.octaves, options.persistance);
+	let image = ImageExporter::new(options.pixels_per_tile).render_noise(&noise, &options.ramp);
+	ImageExporter::save(&image, &options.out);
+	println!("Wrote {}x{} image to {}", image.width(), image.height(), options.out);
+}
+
+pub struct ImportOptions {
+	pub image: String,
+	pub out: String,
+	pub palette: Option<String>,
+	pub heightmap: bool,
+	pub name: String,
+}
+
+impl ImportOptions {
+	pub fn parse(args: &[String]) -> Result<Self, String> {
+		let (image, flags) = parse_flags(args, &["--heightmap"])?;
+		let mut options = ImportOptions {
+			image: image.ok_or("Missing image file".to_string())?,
+			out: String::new(),
+			palette: None,
+			heightmap: false,
+			name: "Imported map".to_string(),
+		};
+		for (flag, value) in flags {
+			match flag.as_str() {
+				"--out" => options.out = value,
+				"--palette" => options.palette = Some(value),
+				"--heightmap" => options.heightmap = true,
+				"--name" => options.name = value,
+				_ => return Err(format!("Unknown option '{}'", flag)),
+			}
+		}
+		if options.out.is_empty() {
+			return Err("Missing --out".to_string());
+		}
+		if options.heightmap && options.palette.is_some() {
+			return Err("--palette and --heightmap can not be combined".to_string());
+		}
+		Ok(options)
+	}
+}
+
+pub fn import(options: &ImportOptions) {
+	let map = if options.heightmap {
+		ImageImporter::import_heightmap(&options.image)
+	}
+	else {
+		let palette = match options.palette {
+			Some(ref path) => Palette::load(path),
+			None => Palette::from_tileset(&Tileset::new_default()),
+		};
+		ImageImporter::import_palette(&options.image, &palette)
+	};
+	let description = format!("Imported from {}", options.image);
+	MapLoader::from_map(&map, &options.name, &description).save_path(&options.out);
+	println!("Wrote {}x{} map to {}", map.width(), map.height(), options.out);
+}
+
+/// Input and output file, the formats follow from the extensions
+pub struct ConvertOptions {
+	pub file: String,
+	pub out: String,
+}
+
+impl ConvertOptions {
+	pub fn parse(args: &[String]) -> Result<Self, String> {
+		let (file, flags) = parse_flags(args, &[])?;
+		let mut options = ConvertOptions {
+			file: file.ok_or("Missing input file".to_string())?,
+			out: String::new(),
+		};
+		for (flag, value) in flags {
+			match flag.as_str() {
+				"--out" => options.out = value,
+				_ => return Err(format!("Unknown option '{}'", flag)),
+			}
+		}
+		if options.out.is_empty() {
+			return Err("Missing --out".to_string());
+		}
+		Ok(options)
+	}
+}
+
+pub fn convert(options: &ConvertOptions) {
+	let loader = MapLoader::read_path(&options.file);
+	loader.save_path(&options.out);
+	let size = |path: &str| ::std::fs::metadata(path).map(|m| m.len()).unwrap_or(0);
+	println!("Converted {} ({} bytes) to {} ({} bytes)", options.file, size(&options.file), options.out, size(&options.out));
+}
+
+pub fn tiled_export(options: &ConvertOptions) {
+	let loader = MapLoader::read_path(&options.file);
+	let (name, description) = (loader.name().to_string(), loader.description().to_string());
+	let map = loader.into_map();
+	TiledMap::from_map(&map, &name, &description, &Tileset::new_default()).save(&options.out);
+	println!("Wrote {}x{} Tiled map to {}", map.width(), map.height(), options.out);
+}
+
+pub fn tiled_import(options: &ConvertOptions) -> Result<(), String> {
+	let tiled = TiledMap::load(&options.file)?;
+	for warning in tiled.warnings() {
+		println!("Warning: {}", warning);
+	}
+	let map = tiled.to_map()?;
+	let name = tiled.property("name").unwrap_or("Imported map");
+	let description = match tiled.property("description") {
+		Some(description) => description.to_string(),
+		None => format!("Imported from {}", options.file),
+	};
+	MapLoader::from_map(&map, name, &description).save_path(&options.out);
+	println!("Wrote {}x{} map with {} objects to {}", map.width(), map.height(), map.get_objects().len(), options.out);
+	Ok(())
+}
+
+/// Headless run, without a map file on the default one
+pub struct SimulateOptions {
+	pub file: Option<String>,
+	pub ticks: u64,
+	pub report: u64,
+}
+
+impl SimulateOptions {
+	pub fn parse(args: &[String]) -> Result<Self, String> {
+		let (file, flags) = parse_flags(args, &[])?;
+		let mut options = SimulateOptions {
+			file: file,
+			ticks: 3600,
+			report: 600,
+		};
+		for (flag, value) in flags {
+			match flag.as_str() {
+				"--ticks" => options.ticks = parse_number(&flag, &value)?,
+				"--report" => options.report = parse_number(&flag, &value)?,
+				_ => return Err(format!("Unknown option '{}'", flag)),
+			}
+		}
+		Ok(options)
+	}
+}
+
+pub fn simulate(options: &SimulateOptions) {
+	let world = match options.file {
+		Some(ref file) => World::from_map(MapLoader::load_path(file)),
+		None => World::new_empty(),
+	};
+	let mut simulation = Simulation::new(world);
+	let start = Instant::now();
+	let mut last = Instant::now();
+	while simulation.get_tick() < options.ticks {
+		let ticks = options.report.max(1).min(options.ticks - simulation.get_tick());
+		simulation.run(ticks);
+		let elapsed = last.elapsed();
+		last = Instant::now();
+		let world = simulation.get_world();
+		let player = world.get_components().healths.get(world.get_player()).map(|health| health.current).unwrap_or(0.0);
+		let ms = (elapsed.as_secs() as f64 * 1000.0 + elapsed.subsec_nanos() as f64 / 1e6) / ticks as f64;
+		println!("Tick {} ({:.1}s): {} entities, player health {}, {:.3} ms per tick", simulation.get_tick(), simulation.get_time(), world.get_entities().len(), player, ms);
+	}
+	let elapsed = start.elapsed();
+	println!("Ran {} ticks in {:.2}s", simulation.get_tick(), elapsed.as_secs() as f64 + elapsed.subsec_nanos() as f64 / 1e9);
+}
+
+/// Plays on the given or the default map and writes every tick to a replay when the window closes
+pub struct RecordOptions {
+	pub file: Option<String>,
+	pub seed: Option<Vec<usize>>,
+	pub out: String,
+}
+
+impl RecordOptions {
+	pub fn parse(args: &[String]) -> Result<Self, String> {
+		let (file, flags) = parse_flags(args, &[])?;
+		let mut options = RecordOptions {
+			file: file,
+			seed: None,
+			out: String::new(),
+		};
+		for (flag, value) in flags {
+			match flag.as_str() {
+				"--seed" => options.seed = Some(parse_seed(&value)),
+				"--out" => options.out = value,
+				_ => return Err(format!("Unknown option '{}'", flag)),
+			}
+		}
+		if options.out.is_empty() {
+			return Err("Missing --out".to_string());
+		}
+		Ok(options)
+	}
+}
+
+pub fn record(options: &RecordOptions) {
+	let map = match options.file {
+		Some(ref file) => MapLoader::load_path(file),
+		None => Map::new_default(),
+	};
+	let world = match options.seed {
+		Some(ref seed) => World::from_map_seed(map, seed),
+		None => World::from_map(map),
+	};
+	let mut simulation = Simulation::new(world);
+	simulation.start_recording(HASH_INTERVAL).expect("Could not start recording");
+	let mut game = Game::new_simulation(1600, 900, "Game", false, simulation);
+	game.run();
+	let replay = game.get_mut_simulation().stop_recording().expect("Could not stop recording");
+	replay.save(&options.out);
+	println!("Recorded {} ticks to {}", replay.length, options.out);
+}
+
+pub struct ReplayOptions {
+	pub file: String,
+	pub speed: f32,
+	pub headless: bool,
+}
+
+impl ReplayOptions {
+	pub fn parse(args: &[String]) -> Result<Self, String> {
+		let (file, flags) = parse_flags(args, &["--headless"])?;
+		let mut options = ReplayOptions {
+			file: file.ok_or("Missing replay file".to_string())?,
+			speed: 1.0,
+			headless: false,
+		};
+		for (flag, value) in flags {
+			match flag.as_str() {
+				"--speed" => options.speed = parse_number(&flag, &value)?,
+				"--headless" => options.headless = true,
+				_ => return Err(format!("Unknown option '{}'", flag)),
+			}
+		}
+		Ok(options)
+	}
+}
+
+/// Headless runs as fast as possible, to check that a replay still reproduces
+pub fn replay(options: &ReplayOptions) -> Result<(), String> {
+	let replay = Replay::load(&options.file)?;
+	let length = replay.length;
+	let mut simulation = Simulation::from_replay(replay);
+	if options.headless {
+		simulation.run(length);
+		return replay_result(&simulation, length);
+	}
+	simulation.set_speed(options.speed);
+	let mut game = Game::new_simulation(1600, 900, "Replay", false, simulation);
+	game.run();
+	replay_result(game.get_mut_simulation(), length)
+}
+
+fn replay_result(simulation: &Simulation, length: u64) -> Result<(), String> {
+	match simulation.get_playback().and_then(|playback| playback.get_divergence()) {
+		Some(tick) => Err(format!("Replay did not reproduce, first divergence at tick {}", tick)),
+		None => {
+			println!("Played {} of {} ticks, all state hashes matched", simulation.get_tick(), length);
+			Ok(())
+		},
+	}
+}
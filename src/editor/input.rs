@@ -6,6 +6,9 @@ use sfml::system::{Vector2i, Vector2f};
 use sfml::graphics::RenderTarget;
 
 use editor::world::World;
+use game::picking::{ScreenPoint, Click};
+
+const WHEEL_ZOOM: f32 = 0.85;		// view size factor per notch, scrolling up zooms in
 
 pub struct EditorInputHandler {
 	dummy: i32
@@ -18,21 +21,27 @@ impl EditorInputHandler {
 		}
 	}
 
-	pub fn handle_input(&mut self, world: &mut World, window: &mut RenderWindow) {
+	/// Returns the mouse clicks of the frame in order
+	pub fn handle_input(&mut self, world: &mut World, window: &mut RenderWindow) -> Vec<Click> {
+		let mut clicks = Vec::new();
 		for event in window.events() {
 			match event {
 				event::Closed => window.close(),
 				event::KeyPressed{code, ..} => self.handle_key_pressed(world, window, code),	//world, window, code
 				event::KeyReleased{code, ..} => self.handle_key_released(world, window, code),	//world, window, code
-				event::MouseButtonPressed{button, x, y} => self.handle_mouse_pressed(world, window, button, x, y), //world, window, button, x, y
+				event::MouseButtonPressed{button, x, y} => clicks.push(self.handle_mouse_pressed(world, window, button, x, y)), //world, window, button, x, y
+				event::MouseWheelMoved{delta, x, y} => self.handle_mouse_wheel(world, window, delta, x, y),
 				_ => (),
 			}
 		}
+		clicks
 	}
 
 	fn handle_key_pressed(&mut self, world: &mut World, window: &mut RenderWindow, code: Key) {
 		match code {
             Key::Escape => window.close(),
+            Key::Add   => world.get_mut_camera().zoom(0.5),
+            Key::Subtract  => world.get_mut_camera().zoom(2.0),
             Key::Right | Key::D  => world.get_mut_camera().move_right(),
             Key::Left | Key::A  => world.get_mut_camera().move_left(),
             Key::Up | Key::W  => world.get_mut_camera().move_up(),
@@ -44,17 +53,20 @@ impl EditorInputHandler {
         match code {
             Key::Right | Key::Left | Key::D | Key::A => world.get_mut_camera().stop_horizontal(),
             Key::Up | Key::Down | Key::W | Key::S    => world.get_mut_camera().stop_vertical(),
-            Key::Add               => world.get_mut_camera().zoom(0.5),
-            Key::Subtract          => world.get_mut_camera().zoom(2.0),
             _ => ()
         }
     }
 
-    fn handle_mouse_pressed(&mut self, editor: &mut World, window: &mut RenderWindow, code: MouseButton, x: i32, y: i32) {
-        /*let v = Vector2i::new(x,y);
-        let v2: Vector2f = window.map_pixel_to_coords(&v, &editor.get_view());
-        let v3 = screen_to_map(v2);
-        println!("Mouse({}, {})", v3.x, v3.y);*/
+    /// Zooms towards the point under the cursor
+    fn handle_mouse_wheel(&mut self, world: &mut World, window: &mut RenderWindow, delta: i32, x: i32, y: i32) {
+        let point = ScreenPoint::new(x, y).to_world(window, world.get_camera());
+        world.get_mut_camera().zoom_at(WHEEL_ZOOM.powi(delta), point.to_vector());
+    }
 
+    fn handle_mouse_pressed(&mut self, editor: &mut World, window: &mut RenderWindow, code: MouseButton, x: i32, y: i32) -> Click {
+        Click {
+            button: code,
+            pick: editor.pick(ScreenPoint::new(x, y).to_world(window, editor.get_camera())),
+        }
     }
 }
\ No newline at end of file
@@ -2,17 +2,19 @@ pub mod input;
 pub mod world;
 
 use sfml::graphics::{RenderWindow, Color, RenderTarget};
-use sfml::window::{VideoMode, ContextSettings, window_style};
+use sfml::window::{VideoMode, ContextSettings, window_style, MouseButton};
 use sfml::system::{Clock};
 
 use editor::world::World;
 //use game::camera::Camera;
 use editor::input::EditorInputHandler;
+use game::timestep::FixedTimestep;
 
 pub struct Editor {
 	window: RenderWindow,
 	world: World,
 	input: EditorInputHandler,
+	timestep: FixedTimestep,
 }
 
 impl Editor {
@@ -25,6 +27,7 @@ impl Editor {
 			window: window,
 			world: World::new_empty(),
 			input: EditorInputHandler::new(),
+			timestep: FixedTimestep::new_default(),
 		};
 		editor.window.set_vertical_sync_enabled(false);
 		editor.window.set_framerate_limit(60);
@@ -36,21 +39,31 @@ impl Editor {
 		let mut delta = 0.0;
 		while self.window.is_open() {
 			self.handle_input();
-			self.update(delta);
+			for _ in 0..self.timestep.advance(delta) {
+				let step = self.timestep.step();
+				self.update(step);
+			}
+			self.world.interpolate(self.timestep.alpha());
 			self.window.set_view(self.world.get_camera().get_view());
 			self.render();
 			delta = clock.restart().as_seconds();
 		}
 	}
 
+	/// Left click selects the tile under the cursor, right click clears the selection
 	fn handle_input(&mut self) {
-		self.input.handle_input(&mut self.world, &mut self.window);
+		for click in self.input.handle_input(&mut self.world, &mut self.window) {
+			match click.button {
+				MouseButton::Left => self.world.set_selection(click.pick.tile),
+				MouseButton::Right => self.world.set_selection(None),
+				_ => (),
+			}
+		}
 	}
 
 	fn render(&mut self) {
 		self.window.clear(&Color::black());
 		self.window.draw(&self.world);
-		//TODO Render game objects
 		self.window.display();
 	}
 
@@ -1,26 +1,50 @@
 use game::camera::Camera;
-use game::map::{Map, Layer, MapLoader};
+use game::map::{Map, Layer, LayerRenderer, MapLoader};
 use sfml::graphics::{Drawable, RenderStates, RenderTarget, RectangleShape, Color};
 use sfml::system::{Vector2f};
+use game::ecs::Entities;
+use game::components::{Components, EntityRenderer};
+use game::picking::{self, Pick, WorldPoint, TilePoint};
 
 pub struct World {
 	camera: Camera,
 	map: Map,
+	layer_renderer: LayerRenderer,
+	entities: Entities,
+	components: Components,
+	entity_renderer: EntityRenderer,
+	selection: Option<TilePoint>,
 }
 
 
 impl World {
 	pub fn new() -> Self {
+		let map = MapLoader::load("testmap2.json");
+		let mut camera = Camera::new(64f32, 36f32, 50f32);
+		camera.fit_to_map(&map);
 		World {
-			camera: Camera::new(64f32, 36f32, 50f32),
-			map: MapLoader::load("testmap2.json"),
+			camera: camera,
+			layer_renderer: LayerRenderer::new(map.get_layer(0)),
+			entities: Entities::new(),
+			components: Components::new(),
+			entity_renderer: EntityRenderer::new(),
+			map: map,
+			selection: None,
 		}
 	}
 
 	pub fn new_empty() -> Self {
+		let map = Map::new_default();
+		let mut camera = Camera::new(64f32, 36f32, 50f32);
+		camera.fit_to_map(&map);
 		World {
-			camera: Camera::new(64f32, 36f32, 50f32),
-			map: Map::new_default(),
+			camera: camera,
+			layer_renderer: LayerRenderer::new(map.get_layer(0)),
+			entities: Entities::new(),
+			components: Components::new(),
+			entity_renderer: EntityRenderer::new(),
+			map: map,
+			selection: None,
 		}
 	}
 
@@ -28,6 +52,35 @@ impl World {
 		self.camera.update(delta);
 	}
 
+	/// Moves the camera and all entities between their last two positions
+	pub fn interpolate(&mut self, alpha: f32) {
+		self.camera.interpolate(alpha);
+		self.entity_renderer.update(&self.components, None, alpha);
+	}
+
+	/// Tile, edge and entity under the point, the editor sees everything
+	pub fn pick(&self, point: WorldPoint) -> Pick {
+		let candidates = self.components.positions.entities().iter().cloned();
+		Pick::new(&self.map, point, picking::entity_at(&self.components, candidates, point))
+	}
+
+	/// Tile drawn with an outline, None for none
+	pub fn set_selection(&mut self, tile: Option<TilePoint>) {
+		self.selection = tile;
+	}
+
+	pub fn get_map(&self) -> &Map {
+		&self.map
+	}
+
+	pub fn get_entities(&self) -> &Entities {
+		&self.entities
+	}
+
+	pub fn get_mut_components(&mut self) -> &mut Components {
+		&mut self.components
+	}
+
 	pub fn get_mut_camera(&mut self) -> &mut Camera {
 		&mut self.camera
 	}
@@ -41,6 +94,10 @@ impl Drawable for World {
 	fn draw<RT: RenderTarget>(&self, render_target: &mut RT, _: &mut RenderStates) {
 		//render_target.draw(&self.test);
 		//render_target.draw(&self.map);
-		render_target.draw(self.map.get_layer(0));
+		render_target.draw(&self.layer_renderer);
+		render_target.draw(&self.entity_renderer);
+		if let Some(tile) = self.selection {
+			picking::draw_tile_outline(render_target, tile);
+		}
 	}
 }
\ No newline at end of file
@@ -0,0 +1,251 @@
+use std::collections::HashMap;
+use game::map::CollisionLayer;
+use game::map_generator::{self, Noise};
+use util::Vec2D;
+
+const NOISE_RAMP: &'static [u8] = b" .:-=+*#%@";
+const RESET: &'static str = "\x1b[0m";
+
+/// Glyph and ANSI 256 colour of a tile id
+fn tile_glyph(tile: u32) -> (char, u8) {
+	match tile {
+		map_generator::Grass => ('.', 34),
+		map_generator::ShallowWater => ('~', 45),
+		map_generator::Road => ('=', 250),
+		map_generator::Snow => ('*', 255),
+		map_generator::DeepWater => ('w', 21),
+		map_generator::Forest => ('T', 22),
+		map_generator::Dirt => (':', 130),
+		map_generator::Sand => (',', 229),
+		map_generator::Swamp => ('%', 64),
+		map_generator::Building => ('@', 160),
+		_ => ('?', 201),
+	}
+}
+
+/// Renders map layers, noise and collision as text for headless debugging
+pub struct AsciiRenderer {
+	pub scale: usize,		// every character covers scale x scale tiles
+	pub color: bool,
+	pub legend: bool,
+}
+
+impl AsciiRenderer {
+	pub fn new() -> Self {
+		AsciiRenderer {
+			scale: 1,
+			color: false,
+			legend: true,
+		}
+	}
+
+	pub fn render_tiles(&self, tiles: &Vec2D<u32>) -> String {
+		let mut out = String::new();
+		let mut seen: Vec<u32> = Vec::new();
+		for cy in 0..Self::cells(tiles.height(), self.scale) {
+			for cx in 0..Self::cells(tiles.width(), self.scale) {
+				let tile = self.most_common(tiles, cx, cy);
+				if !seen.contains(&tile) {
+					seen.push(tile);
+				}
+				let (glyph, color) = tile_glyph(tile);
+				self.push(&mut out, glyph, color);
+			}
+			out.push('\n');
+		}
+		if self.legend {
+			seen.sort();
+			for tile in seen {
+				let (glyph, color) = tile_glyph(tile);
+				self.push(&mut out, glyph, color);
+				out.push_str(&format!(" {} ({})\n", map_generator::tile_name(tile), tile));
+			}
+		}
+		out
+	}
+
+	pub fn render_noise(&self, noise: &Noise) -> String {
+		let mut out = String::new();
+		for cy in 0..Self::cells(noise.height(), self.scale) {
+			for cx in 0..Self::cells(noise.width(), self.scale) {
+				let mut sum = 0f32;
+				let mut count = 0;
+				for (x, y) in Self::block(noise.width(), noise.height(), cx, cy, self.scale) {
+					sum += noise[(x, y)];
+					count += 1;
+				}
+				let value = (sum / count as f32).max(0f32).min(1f32);
+				let glyph = NOISE_RAMP[((value * (NOISE_RAMP.len() - 1) as f32).round()) as usize] as char;
+				self.push(&mut out, glyph, 232 + (value * 23f32) as u8);
+			}
+			out.push('\n');
+		}
+		if self.legend {
+			out.push_str(&format!("'{}' = 0.0 .. '{}' = 1.0\n", NOISE_RAMP[0] as char, NOISE_RAMP[NOISE_RAMP.len() - 1] as char));
+		}
+		out
+	}
+
+	/// At scale 1 walls are drawn on the tile edges, otherwise a cell is marked if it contains any wall
+	pub fn render_collision(&self, collision: &CollisionLayer) -> String {
+		let vertical = collision.vertical_edges();
+		let horizontal = collision.horizontal_edges();
+		let (width, height) = (horizontal.width(), vertical.height());
+		let mut out = String::new();
+		if self.scale <= 1 {
+			for row in 0..(2 * height + 1) {
+				for col in 0..(2 * width + 1) {
+					let (x, y) = (col / 2, row / 2);
+					let glyph = match (col % 2, row % 2) {
+						(0, 0) => {
+							let wall = (y > 0 && vertical[(x, y - 1)]) || (y < height && vertical[(x, y)])
+								|| (x > 0 && horizontal[(x - 1, y)]) || (x < width && horizontal[(x, y)]);
+							if wall { '+' } else { ' ' }
+						},
+						(1, 0) => if horizontal[(x, y)] { '-' } else { ' ' },
+						(0, 1) => if vertical[(x, y)] { '|' } else { ' ' },
+						_ => ' ',
+					};
+					self.push(&mut out, glyph, 196);
+				}
+				out.push('\n');
+			}
+		}
+		else {
+			for cy in 0..Self::cells(height, self.scale) {
+				for cx in 0..Self::cells(width, self.scale) {
+					let wall = Self::block(width, height, cx, cy, self.scale).iter().any(|&(x, y)| {
+						vertical[(x, y)] || vertical[(x + 1, y)] || horizontal[(x, y)] || horizontal[(x, y + 1)]
+					});
+					self.push(&mut out, if wall { '#' } else { '.' }, 196);
+				}
+				out.push('\n');
+			}
+		}
+		if self.legend {
+			out.push_str("'|', '-' and '#' are walls\n");
+		}
+		out
+	}
+
+	pub fn print_tiles(&self, tiles: &Vec2D<u32>) {
+		print!("{}", self.render_tiles(tiles));
+	}
+
+	pub fn print_noise(&self, noise: &Noise) {
+		print!("{}", self.render_noise(noise));
+	}
+
+	pub fn print_collision(&self, collision: &CollisionLayer) {
+		print!("{}", self.render_collision(collision));
+	}
+
+	fn push(&self, out: &mut String, glyph: char, color: u8) {
+		if self.color && glyph != ' ' {
+			out.push_str(&format!("\x1b[38;5;{}m{}{}", color, glyph, RESET));
+		}
+		else {
+			out.push(glyph);
+		}
+	}
+
+	fn most_common(&self, tiles: &Vec2D<u32>, cx: usize, cy: usize) -> u32 {
+		let mut counts: HashMap<u32, usize> = HashMap::new();
+		for (x, y) in Self::block(tiles.width(), tiles.height(), cx, cy, self.scale) {
+			*counts.entry(tiles[(x, y)]).or_insert(0) += 1;
+		}
+		// Ties go to the lowest id so the output is stable
+		let mut best = (0, 0);
+		for (&tile, &count) in counts.iter() {
+			if count > best.1 || (count == best.1 && tile < best.0) {
+				best = (tile, count);
+			}
+		}
+		best.0
+	}
+
+	fn cells(size: usize, scale: usize) -> usize {
+		let scale = scale.max(1);
+		(size + scale - 1) / scale
+	}
+
+	fn block(width: usize, height: usize, cx: usize, cy: usize, scale: usize) -> Vec<(usize, usize)> {
+		let scale = scale.max(1);
+		let mut block = Vec::with_capacity(scale * scale);
+		for y in (cy * scale)..((cy + 1) * scale).min(height) {
+			for x in (cx * scale)..((cx + 1) * scale).min(width) {
+				block.push((x, y));
+			}
+		}
+		block
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use game::map_generator::{Grass, Road, Building, ShallowWater, Forest};
+
+	fn plain() -> AsciiRenderer {
+		let mut renderer = AsciiRenderer::new();
+		renderer.legend = false;
+		renderer
+	}
+
+	#[test]
+	fn renders_one_glyph_per_tile() {
+		let tiles = Vec2D::from_vec(3, 2, vec![Grass, Road, Building, ShallowWater, Grass, Forest]);
+		assert_eq!(plain().render_tiles(&tiles), ".=@\n~.T\n");
+	}
+
+	#[test]
+	fn legend_lists_the_tiles_in_use_by_id() {
+		let tiles = Vec2D::from_vec(2, 1, vec![Road, Grass]);
+		let out = AsciiRenderer::new().render_tiles(&tiles);
+		let legend: Vec<&str> = out.lines().skip(1).collect();
+		assert_eq!(legend.len(), 2);
+		assert!(legend[0].starts_with(". "));
+		assert!(legend[1].starts_with("= "));
+	}
+
+	#[test]
+	fn downsampling_picks_the_most_common_tile() {
+		let tiles = Vec2D::from_vec(4, 2, vec![
+			Road, Road, Grass, Forest,
+			Road, Grass, Forest, Forest,
+		]);
+		let mut renderer = plain();
+		renderer.scale = 2;
+		assert_eq!(renderer.render_tiles(&tiles), "=T\n");
+	}
+
+	#[test]
+	fn downsampling_covers_partial_blocks() {
+		let tiles = Vec2D::from_vec(3, 3, vec![Grass; 9]);
+		let mut renderer = plain();
+		renderer.scale = 2;
+		assert_eq!(renderer.render_tiles(&tiles), "..\n..\n");
+	}
+
+	#[test]
+	fn noise_maps_to_the_ramp_ends() {
+		let noise = Noise::from_vec(2, 1, vec![0.0, 1.0]);
+		assert_eq!(plain().render_noise(&noise), " @\n");
+	}
+
+	#[test]
+	fn collision_walls_sit_on_tile_edges() {
+		let mut collision = CollisionLayer::new(2, 1);
+		collision.set_collision_right(0, 0);
+		assert_eq!(plain().render_collision(&collision), "  +  \n  |  \n  +  \n");
+	}
+
+	#[test]
+	fn downsampled_collision_marks_cells_with_walls() {
+		let mut collision = CollisionLayer::new(4, 2);
+		collision.set_collision_bottom(3, 0);
+		let mut renderer = plain();
+		renderer.scale = 2;
+		assert_eq!(renderer.render_collision(&collision), ".#\n");
+	}
+}
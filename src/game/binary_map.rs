@@ -0,0 +1,423 @@
+// Building blocks of the binary map format.
+//
+// A file is the magic, a u16 version, a list of sections and a CRC32 of everything before it.
+// Every section is a u8 tag and a u32 length, so readers can skip sections they do not know.
+// Numbers are little endian, tile data is stored as LEB128 varints.
+
+pub const MAGIC: &'static [u8] = b"ZMAP";
+pub const VERSION: u16 = 1;
+pub const EXTENSION: &'static str = ".zmap";
+
+pub const SECTION_INFO: u8 = 1;
+pub const SECTION_LAYER: u8 = 2;
+pub const SECTION_OBJECTS: u8 = 3;
+pub const SECTION_COLLISION: u8 = 4;
+
+const ENCODING_RAW: u8 = 0;
+const ENCODING_RUN_LENGTH: u8 = 1;
+const ENCODING_DELTA: u8 = 2;		// run length encoded differences, good for gradients
+
+/// Most tiles a layer may have, 4096x4096, so a damaged count can not run out of memory
+pub const MAX_LAYER_TILES: usize = 1 << 24;
+
+pub fn is_binary(bytes: &[u8]) -> bool {
+	bytes.starts_with(MAGIC)
+}
+
+pub struct BinaryWriter {
+	bytes: Vec<u8>,
+}
+
+impl BinaryWriter {
+	/// Bare writer for section contents
+	pub fn new() -> Self {
+		BinaryWriter {
+			bytes: Vec::new(),
+		}
+	}
+
+	/// Writer that starts with the file header
+	pub fn new_file() -> Self {
+		let mut writer = BinaryWriter::new();
+		writer.bytes.extend_from_slice(MAGIC);
+		writer.u16(VERSION);
+		writer
+	}
+
+	pub fn u8(&mut self, value: u8) {
+		self.bytes.push(value);
+	}
+
+	pub fn u16(&mut self, value: u16) {
+		self.bytes.extend_from_slice(&[value as u8, (value >> 8) as u8]);
+	}
+
+	pub fn u32(&mut self, value: u32) {
+		self.bytes.extend_from_slice(&[value as u8, (value >> 8) as u8, (value >> 16) as u8, (value >> 24) as u8]);
+	}
+
+	pub fn f32(&mut self, value: f32) {
+		self.u32(value.to_bits());
+	}
+
+	pub fn varint(&mut self, mut value: u64) {
+		while value >= 0x80 {
+			self.bytes.push((value as u8) | 0x80);
+			value >>= 7;
+		}
+		self.bytes.push(value as u8);
+	}
+
+	pub fn string(&mut self, value: &str) {
+		self.varint(value.len() as u64);
+		self.bytes.extend_from_slice(value.as_bytes());
+	}
+
+	pub fn section(&mut self, tag: u8, content: BinaryWriter) {
+		self.u8(tag);
+		self.u32(content.bytes.len() as u32);
+		self.bytes.extend_from_slice(&content.bytes);
+	}
+
+	/// Tiles with whichever encoding is smallest
+	pub fn layer(&mut self, tiles: &[u32]) {
+		let candidates = [
+			(ENCODING_RAW, encode_raw(tiles)),
+			(ENCODING_RUN_LENGTH, encode_run_length(tiles.iter().map(|&t| t as u64))),
+			(ENCODING_DELTA, encode_run_length(deltas(tiles).into_iter())),
+		];
+		let &(encoding, ref data) = candidates.iter().min_by_key(|c| c.1.len()).unwrap();
+		self.varint(tiles.len() as u64);
+		self.u8(encoding);
+		self.varint(data.len() as u64);
+		self.bytes.extend_from_slice(data);
+	}
+
+	/// Appends the checksum
+	pub fn finish(mut self) -> Vec<u8> {
+		let checksum = crc32(&self.bytes);
+		self.u32(checksum);
+		self.bytes
+	}
+}
+
+pub struct BinaryReader<'a> {
+	bytes: &'a [u8],
+	pos: usize,
+}
+
+impl<'a> BinaryReader<'a> {
+	/// Checks magic, version and checksum before anything is read
+	pub fn new(bytes: &'a [u8]) -> Result<Self, String> {
+		if !is_binary(bytes) || bytes.len() < MAGIC.len() + 6 {
+			return Err("Not a binary map".to_string());
+		}
+		let (body, checksum) = bytes.split_at(bytes.len() - 4);
+		let mut reader = BinaryReader {
+			bytes: checksum,
+			pos: 0,
+		};
+		if reader.u32()? != crc32(body) {
+			return Err("Checksum mismatch, the file is damaged".to_string());
+		}
+		reader.bytes = body;
+		reader.pos = MAGIC.len();
+		let version = reader.u16()?;
+		if version > VERSION {
+			return Err(format!("Map version {} is newer than supported version {}", version, VERSION));
+		}
+		Ok(reader)
+	}
+
+	pub fn is_empty(&self) -> bool {
+		self.pos >= self.bytes.len()
+	}
+
+	/// Bytes left to read
+	pub fn remaining(&self) -> usize {
+		self.bytes.len().saturating_sub(self.pos)
+	}
+
+	pub fn u8(&mut self) -> Result<u8, String> {
+		Ok(self.take(1)?[0])
+	}
+
+	pub fn u16(&mut self) -> Result<u16, String> {
+		let b = self.take(2)?;
+		Ok(b[0] as u16 | (b[1] as u16) << 8)
+	}
+
+	pub fn u32(&mut self) -> Result<u32, String> {
+		let b = self.take(4)?;
+		Ok(b[0] as u32 | (b[1] as u32) << 8 | (b[2] as u32) << 16 | (b[3] as u32) << 24)
+	}
+
+	pub fn f32(&mut self) -> Result<f32, String> {
+		Ok(f32::from_bits(self.u32()?))
+	}
+
+	pub fn varint(&mut self) -> Result<u64, String> {
+		let mut value = 0u64;
+		let mut shift = 0;
+		loop {
+			let byte = self.u8()?;
+			if shift >= 64 {
+				return Err("Number too large".to_string());
+			}
+			value |= ((byte & 0x7f) as u64) << shift;
+			if byte & 0x80 == 0 {
+				return Ok(value);
+			}
+			shift += 7;
+		}
+	}
+
+	pub fn string(&mut self) -> Result<String, String> {
+		let len = self.varint()? as usize;
+		String::from_utf8(self.take(len)?.to_vec()).map_err(|_| "Text is not UTF-8".to_string())
+	}
+
+	/// Tag and reader of the next section
+	pub fn section(&mut self) -> Result<(u8, BinaryReader<'a>), String> {
+		let tag = self.u8()?;
+		let len = self.u32()? as usize;
+		let bytes = self.take(len)?;
+		Ok((tag, BinaryReader {
+			bytes: bytes,
+			pos: 0,
+		}))
+	}
+
+	pub fn layer(&mut self) -> Result<Vec<u32>, String> {
+		let count = self.varint()?;
+		if count > MAX_LAYER_TILES as u64 {
+			return Err(format!("Layer of {} tiles is too large", count));
+		}
+		let count = count as usize;
+		let encoding = self.u8()?;
+		let len = self.varint()?;
+		if len > self.remaining() as u64 {
+			return Err("Unexpected end of data".to_string());
+		}
+		let mut data = BinaryReader {
+			bytes: self.take(len as usize)?,
+			pos: 0,
+		};
+		// Every raw tile takes at least one byte, runs can be longer than their data so they grow as they are read
+		let mut tiles = Vec::with_capacity(count.min(data.remaining()));
+		match encoding {
+			ENCODING_RAW => {
+				if count > data.remaining() {
+					return Err("Unexpected end of data".to_string());
+				}
+				while tiles.len() < count {
+					tiles.push(data.varint()? as u32);
+				}
+			},
+			ENCODING_RUN_LENGTH | ENCODING_DELTA => {
+				let mut previous = 0i64;
+				while tiles.len() < count {
+					let run = data.varint()?;
+					let value = data.varint()?;
+					if run == 0 || run > (count - tiles.len()) as u64 {
+						return Err("Broken run in layer data".to_string());
+					}
+					for _ in 0..run {
+						if encoding == ENCODING_DELTA {
+							previous = previous.wrapping_add(unzigzag(value));
+							tiles.push(previous as u32);
+						}
+						else {
+							tiles.push(value as u32);
+						}
+					}
+				}
+			},
+			_ => return Err(format!("Unknown layer encoding {}", encoding)),
+		}
+		Ok(tiles)
+	}
+
+	fn take(&mut self, len: usize) -> Result<&'a [u8], String> {
+		if len > self.remaining() {
+			return Err("Unexpected end of data".to_string());
+		}
+		let bytes = &self.bytes[self.pos..self.pos + len];
+		self.pos += len;
+		Ok(bytes)
+	}
+}
+
+fn encode_raw(tiles: &[u32]) -> Vec<u8> {
+	let mut writer = BinaryWriter::new();
+	for &tile in tiles.iter() {
+		writer.varint(tile as u64);
+	}
+	writer.bytes
+}
+
+/// Pairs of run length and value
+fn encode_run_length<I: Iterator<Item = u64>>(values: I) -> Vec<u8> {
+	let mut writer = BinaryWriter::new();
+	let mut run: Option<(u64, u64)> = None;
+	for value in values {
+		run = match run {
+			Some((count, current)) if current == value => Some((count + 1, current)),
+			Some((count, current)) => {
+				writer.varint(count);
+				writer.varint(current);
+				Some((1, value))
+			},
+			None => Some((1, value)),
+		};
+	}
+	if let Some((count, current)) = run {
+		writer.varint(count);
+		writer.varint(current);
+	}
+	writer.bytes
+}
+
+/// Zigzag encoded differences to the previous tile
+fn deltas(tiles: &[u32]) -> Vec<u64> {
+	tiles.iter().scan(0i64, |previous, &tile| {
+		let delta = tile as i64 - *previous;
+		*previous = tile as i64;
+		Some(((delta << 1) ^ (delta >> 63)) as u64)
+	}).collect()
+}
+
+fn unzigzag(value: u64) -> i64 {
+	((value >> 1) as i64) ^ -((value & 1) as i64)
+}
+
+pub fn crc32(bytes: &[u8]) -> u32 {
+	let mut table = [0u32; 256];
+	for i in 0..256 {
+		let mut c = i as u32;
+		for _ in 0..8 {
+			c = if c & 1 != 0 { 0xEDB88320 ^ (c >> 1) } else { c >> 1 };
+		}
+		table[i] = c;
+	}
+	let mut crc = 0xFFFFFFFFu32;
+	for &byte in bytes {
+		crc = table[((crc ^ byte as u32) & 0xFF) as usize] ^ (crc >> 8);
+	}
+	!crc
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	fn file_with(section: BinaryWriter) -> Vec<u8> {
+		let mut writer = BinaryWriter::new_file();
+		writer.section(SECTION_LAYER, section);
+		writer.finish()
+	}
+
+	fn read_layer(tiles: &[u32]) -> Vec<u32> {
+		let mut section = BinaryWriter::new();
+		section.layer(tiles);
+		let bytes = file_with(section);
+		let mut reader = BinaryReader::new(&bytes).unwrap();
+		let (tag, mut section) = reader.section().unwrap();
+		assert_eq!(tag, SECTION_LAYER);
+		assert!(reader.is_empty());
+		section.layer().unwrap()
+	}
+
+	#[test]
+	fn layer_round_trip_in_every_encoding() {
+		let mixed: Vec<u32> = (0..200).map(|i| (i * 7919) % 1000).collect();
+		let runs: Vec<u32> = (0..200).map(|i| i / 50).collect();
+		let gradient: Vec<u32> = (0..200).collect();
+		for tiles in [mixed, runs, gradient, Vec::new()].iter() {
+			assert_eq!(&read_layer(tiles), tiles);
+		}
+	}
+
+	#[test]
+	fn numbers_and_strings_round_trip() {
+		let mut writer = BinaryWriter::new_file();
+		writer.u8(200);
+		writer.u16(60000);
+		writer.u32(4000000000);
+		writer.f32(-1.5);
+		writer.varint(u64::max_value());
+		writer.string("Zombie ß");
+		let bytes = writer.finish();
+		let mut reader = BinaryReader::new(&bytes).unwrap();
+		assert_eq!(reader.u8(), Ok(200));
+		assert_eq!(reader.u16(), Ok(60000));
+		assert_eq!(reader.u32(), Ok(4000000000));
+		assert_eq!(reader.f32(), Ok(-1.5));
+		assert_eq!(reader.varint(), Ok(u64::max_value()));
+		assert_eq!(reader.string(), Ok("Zombie ß".to_string()));
+		assert!(reader.is_empty());
+	}
+
+	#[test]
+	fn corrupt_checksum_is_rejected() {
+		let mut bytes = file_with(BinaryWriter::new());
+		let last = bytes.len() - 1;
+		bytes[last] ^= 1;
+		assert!(BinaryReader::new(&bytes).is_err());
+		let mut bytes = file_with(BinaryWriter::new());
+		bytes[MAGIC.len() + 2] ^= 1;
+		assert!(BinaryReader::new(&bytes).is_err());
+	}
+
+	#[test]
+	fn truncated_file_is_rejected() {
+		let mut section = BinaryWriter::new();
+		section.layer(&[1, 2, 3, 4, 5]);
+		let bytes = file_with(section);
+		for len in 0..bytes.len() {
+			assert!(BinaryReader::new(&bytes[..len]).is_err());
+		}
+	}
+
+	#[test]
+	fn counts_larger_than_the_data_are_rejected() {
+		// Raw layer claiming more tiles than it has bytes
+		let mut section = BinaryWriter::new();
+		section.varint(1000);
+		section.u8(ENCODING_RAW);
+		section.varint(3);
+		section.u8(1);
+		section.u8(2);
+		section.u8(3);
+		let bytes = file_with(section);
+		let mut reader = BinaryReader::new(&bytes).unwrap();
+		assert!(reader.section().unwrap().1.layer().is_err());
+
+		// Run length layer past the size limit
+		let mut section = BinaryWriter::new();
+		section.varint(u64::max_value());
+		section.u8(ENCODING_RUN_LENGTH);
+		section.varint(2);
+		section.varint(1);
+		section.varint(0);
+		let bytes = file_with(section);
+		let mut reader = BinaryReader::new(&bytes).unwrap();
+		assert!(reader.section().unwrap().1.layer().is_err());
+
+		// Run longer than the layer
+		let mut section = BinaryWriter::new();
+		section.varint(4);
+		section.u8(ENCODING_RUN_LENGTH);
+		section.varint(2);
+		section.varint(5);
+		section.varint(0);
+		let bytes = file_with(section);
+		let mut reader = BinaryReader::new(&bytes).unwrap();
+		assert!(reader.section().unwrap().1.layer().is_err());
+	}
+
+	#[test]
+	fn crc32_check_value() {
+		assert_eq!(crc32(b"123456789"), 0xCBF43926);
+	}
+}
@@ -1,2 +1,87 @@
+use sfml::system::Vector2u;
+use game::map::CollisionLayer;
+use game::map_generator;
+use game::zoning::Zone;
+use game::objects::LootTable;
+use util::Vec2D;
 
+pub struct Building {
+	position: Vector2u,
+	tiles: Vec2D<u32>,
+	collision: CollisionLayer,
+	zone: Option<Zone>,
+	prefab: Option<&'static str>,
+}
 
+impl Building {
+	pub fn new(x: u32, y: u32, width: usize, height: usize) -> Self {
+		let mut tiles: Vec<u32> = Vec::with_capacity(width * height);
+		for _ in 0..(width * height) {
+			tiles.push(map_generator::Building);
+		}
+		let mut collision = CollisionLayer::new(width, height);
+		for x in 0..width {
+			collision.set_collision_top(x,0);
+			collision.set_collision_bottom(x, height - 1);
+		}
+		for y in 0..height {
+			collision.set_collision_left(0,y);
+			collision.set_collision_right(width - 1, y);
+		}
+		Building {
+			position: Vector2u::new(x,y),
+			tiles: Vec2D::from_vec(width, height, tiles),
+			collision: collision,
+			zone: None,
+			prefab: None,
+		}
+	}
+
+	pub fn new_zoned(x: u32, y: u32, width: usize, height: usize, zone: Zone, prefab: &'static str) -> Self {
+		let mut building = Building::new(x, y, width, height);
+		building.zone = Some(zone);
+		building.prefab = Some(prefab);
+		building
+	}
+
+	pub fn position(&self) -> Vector2u {
+		self.position
+	}
+
+	pub fn width(&self) -> usize {
+		self.tiles.width()
+	}
+
+	pub fn height(&self) -> usize {
+		self.tiles.height()
+	}
+
+	/// Walls relative to the top left tile of the building
+	pub fn collision(&self) -> &CollisionLayer {
+		&self.collision
+	}
+
+	pub fn zone(&self) -> Option<Zone> {
+		self.zone
+	}
+
+	pub fn prefab(&self) -> Option<&'static str> {
+		self.prefab
+	}
+
+	pub fn loot_table(&self) -> Option<LootTable> {
+		self.zone.map(|zone| zone.profile().loot_table)
+	}
+
+	/// Writes the building footprint into a tile layer
+	pub fn stamp(&self, tiles: &mut Vec2D<u32>) {
+		let (bx, by) = (self.position.x as usize, self.position.y as usize);
+		for y in 0..self.height() {
+			for x in 0..self.width() {
+				if bx + x < tiles.width() && by + y < tiles.height() {
+					tiles[(bx + x, by + y)] = self.tiles[(x, y)];
+				}
+			}
+		}
+	}
+}
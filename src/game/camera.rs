@@ -1,27 +1,104 @@
 use sfml::system::{Vector2f};
 use sfml::graphics::{View};
+use game::map::Map;
 //use self::sfml::graphics::{Shape, Transformable, RenderWindow, Color, RectangleShape, RenderTarget, Vertex, VertexArray, PrimitiveType};
 
+const MIN_ZOOM: f32 = 0.25;
+const MAX_ZOOM: f32 = 4.0;
+const ZOOM_SPEED: f32 = 10.0;			// how fast the zoom eases to its target, higher is faster
+const TRAUMA_DECAY: f32 = 1.0;			// trauma lost per second
+const MAX_SHAKE: f32 = 1.0;				// offset in tiles at full trauma
+const SHAKE_FREQUENCY: f32 = 25.0;
 
 
 pub struct Camera {
 	view: View,
+	center: Vector2f,				// without the shake
+	previous_center: Vector2f,		// at the start of the last update, drawing blends between the two
+	base_size: Vector2f,			// view size at zoom 1
 	velocity: Vector2f,
 	speed: f32,
+	target: Option<Vector2f>,		// point to follow, panning is ignored while there is one
+	deadzone: Vector2f,				// half size of the area around the centre the target can move in freely
+	smoothing: f32,					// how fast the camera catches up, higher is faster
+	bounds: Option<Vector2f>,		// size of the map, the view never shows past it
+	zoom: f32,						// view size relative to the base size, larger shows more
+	previous_zoom: f32,
+	target_zoom: f32,
+	zoom_limits: (f32, f32),
+	zoom_anchor: Option<Vector2f>,	// world point that stays in place on screen while zooming
+	trauma: f32,					// 0 to 1, the shake grows with its square
+	shake_time: f32,
 }
 
 impl Camera {
 	pub fn new(width: f32, height: f32, speed: f32) -> Self {
+		let center = Vector2f::new(width / 2.0, height / 2.0);
 		Camera {
 			view: View::new_init(
-				&Vector2f::new(width / 2.0, height / 2.0), 
+				&center,
 				&Vector2f::new(width, height))
 				.expect("Could not create View"),
+			center: center,
+			previous_center: center,
+			base_size: Vector2f::new(width, height),
 			velocity: Vector2f::new(0.0, 0.0),
 			speed: speed,
+			target: None,
+			deadzone: Vector2f::new(2.0, 1.5),
+			smoothing: 6.0,
+			bounds: None,
+			zoom: 1.0,
+			previous_zoom: 1.0,
+			target_zoom: 1.0,
+			zoom_limits: (MIN_ZOOM, MAX_ZOOM),
+			zoom_anchor: None,
+			trauma: 0.0,
+			shake_time: 0.0,
 		}
 	}
 
+	pub fn follow(&mut self, target: Vector2f) {
+		self.target = Some(target);
+	}
+
+	pub fn stop_following(&mut self) {
+		self.target = None;
+	}
+
+	/// Jumps to the point without smoothing
+	pub fn snap_to(&mut self, center: Vector2f) {
+		self.center = center;
+		self.clamp();
+		self.previous_center = self.center;
+		self.apply(1.0);
+	}
+
+	pub fn set_deadzone(&mut self, width: f32, height: f32) {
+		self.deadzone = Vector2f::new(width / 2.0, height / 2.0);
+	}
+
+	pub fn set_smoothing(&mut self, smoothing: f32) {
+		self.smoothing = smoothing;
+	}
+
+	pub fn set_bounds(&mut self, width: f32, height: f32) {
+		self.bounds = Some(Vector2f::new(width, height));
+		self.clamp();
+		self.previous_center = self.center;
+		self.apply(1.0);
+	}
+
+	/// Bounds are the map size, and the view may not zoom out past the map on either axis
+	pub fn fit_to_map(&mut self, map: &Map) {
+		let (width, height) = (map.width() as f32, map.height() as f32);
+		let whole_map = (width / self.base_size.x).min(height / self.base_size.y);
+		self.zoom_limits.1 = MAX_ZOOM.min(whole_map).max(self.zoom_limits.0);
+		self.target_zoom = self.clamp_zoom(self.target_zoom);
+		self.zoom = self.clamp_zoom(self.zoom);
+		self.set_bounds(width, height);
+	}
+
 	pub fn move_right(&mut self) {
 		self.velocity.x = self.speed;
 	}
@@ -46,15 +123,235 @@ impl Camera {
 		self.velocity.y = 0.0;
 	}
 
+	/// Eases the view size by the factor, below 1 zooms in
 	pub fn zoom(&mut self, amount: f32) {
-		self.view.zoom(amount);
+		self.zoom_anchor = None;
+		self.target_zoom = self.clamp_zoom(self.target_zoom * amount);
+	}
+
+	/// Like zoom, but the world point stays under the same spot on the screen, for the mouse wheel
+	pub fn zoom_at(&mut self, amount: f32, point: Vector2f) {
+		self.zoom_anchor = Some(point);
+		self.target_zoom = self.clamp_zoom(self.target_zoom * amount);
+	}
+
+	pub fn set_zoom_limits(&mut self, min: f32, max: f32) {
+		self.zoom_limits = (min, max.max(min));
+		self.target_zoom = self.clamp_zoom(self.target_zoom);
+	}
+
+	pub fn get_zoom(&self) -> f32 {
+		self.zoom
+	}
+
+	/// Explosions and hits add trauma, which decays over time
+	pub fn add_trauma(&mut self, amount: f32) {
+		self.trauma = (self.trauma + amount).max(0.0).min(1.0);
+	}
+
+	pub fn get_trauma(&self) -> f32 {
+		self.trauma
 	}
 
 	pub fn update(&mut self, delta: f32) {
-		self.view.move_(&(self.velocity * delta));
+		self.previous_center = self.center;
+		self.previous_zoom = self.zoom;
+		self.update_zoom(delta);
+		match self.target {
+			Some(target) => {
+				// Only move once the target leaves the deadzone, then ease towards keeping it on the edge
+				let offset = target - self.center;
+				let outside = Vector2f::new(Self::past(offset.x, self.deadzone.x), Self::past(offset.y, self.deadzone.y));
+				let alpha = 1.0 - (-self.smoothing * delta).exp();
+				self.center = self.center + outside * alpha;
+			},
+			None => self.center = self.center + self.velocity * delta,
+		}
+		self.clamp();
+		self.trauma = (self.trauma - TRAUMA_DECAY * delta).max(0.0);
+		self.shake_time += delta;
+		self.apply(1.0);
+	}
+
+	/// Sets the view between the last two updates, alpha 0 is the previous one
+	pub fn interpolate(&mut self, alpha: f32) {
+		self.apply(alpha);
 	}
 
 	pub fn get_view(&self) -> &View {
 		&self.view
 	}
-}
\ No newline at end of file
+
+	/// Eases in log space so zooming in and out feel the same
+	fn update_zoom(&mut self, delta: f32) {
+		if self.zoom == self.target_zoom {
+			self.zoom_anchor = None;
+			return;
+		}
+		let alpha = 1.0 - (-ZOOM_SPEED * delta).exp();
+		let mut zoom = (self.zoom.ln() + (self.target_zoom.ln() - self.zoom.ln()) * alpha).exp();
+		if (zoom / self.target_zoom - 1.0).abs() < 0.001 {
+			zoom = self.target_zoom;
+		}
+		if let Some(anchor) = self.zoom_anchor {
+			// The anchor keeps its offset from the centre relative to the view size
+			self.center = anchor + (self.center - anchor) * (zoom / self.zoom);
+		}
+		self.zoom = zoom;
+	}
+
+	fn clamp_zoom(&self, zoom: f32) -> f32 {
+		zoom.max(self.zoom_limits.0).min(self.zoom_limits.1)
+	}
+
+	/// Writes centre, shake and zoom into the view
+	fn apply(&mut self, alpha: f32) {
+		let zoom = self.previous_zoom + (self.zoom - self.previous_zoom) * alpha;
+		let size = self.base_size * zoom;
+		self.view.set_size(&size);
+		let shake = self.trauma * self.trauma * MAX_SHAKE;
+		let time = self.shake_time * SHAKE_FREQUENCY;
+		// Sums of unrelated sines stand in for noise
+		let offset = Vector2f::new((time * 1.0).sin() + (time * 2.3 + 1.7).sin() * 0.5, (time * 1.3 + 4.1).sin() + (time * 2.9 + 0.3).sin() * 0.5) * (shake / 1.5);
+		let center = self.previous_center + (self.center - self.previous_center) * alpha;
+		let center = self.clamped(center + offset, zoom);
+		self.view.set_center(&center);
+	}
+
+	/// How far the value is past -limit..limit
+	fn past(value: f32, limit: f32) -> f32 {
+		if value > limit {
+			value - limit
+		}
+		else if value < -limit {
+			value + limit
+		}
+		else {
+			0.0
+		}
+	}
+
+	fn clamp(&mut self) {
+		let zoom = self.zoom;
+		self.center = self.clamped(self.center, zoom);
+	}
+
+	/// Keeps the view inside the bounds, a view larger than the map is centred on it
+	fn clamped(&self, center: Vector2f, zoom: f32) -> Vector2f {
+		let bounds = match self.bounds {
+			Some(bounds) => bounds,
+			None => return center,
+		};
+		let half = self.base_size * (zoom / 2.0);
+		let axis = |center: f32, half: f32, size: f32| {
+			if half * 2.0 >= size {
+				size / 2.0
+			}
+			else {
+				center.max(half).min(size - half)
+			}
+		};
+		Vector2f::new(axis(center.x, half.x, bounds.x), axis(center.y, half.y, bounds.y))
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	fn close(a: f32, b: f32) -> bool {
+		(a - b).abs() < 0.001
+	}
+
+	/// Lets the eased zoom settle
+	fn settle(camera: &mut Camera) {
+		for _ in 0..200 {
+			camera.update(1.0 / 60.0);
+		}
+	}
+
+	/// Where the point is on screen, 0 to 1 on both axes
+	fn screen_position(camera: &Camera, point: Vector2f) -> (f32, f32) {
+		let (center, size) = (camera.get_view().get_center(), camera.get_view().get_size());
+		((point.x - center.x) / size.x + 0.5, (point.y - center.y) / size.y + 0.5)
+	}
+
+	fn assert_inside_map(camera: &Camera, width: f32, height: f32) {
+		let (center, size) = (camera.get_view().get_center(), camera.get_view().get_size());
+		assert!(center.x - size.x / 2.0 >= -0.001 && center.x + size.x / 2.0 <= width + 0.001, "x {} size {}", center.x, size.x);
+		assert!(center.y - size.y / 2.0 >= -0.001 && center.y + size.y / 2.0 <= height + 0.001, "y {} size {}", center.y, size.y);
+	}
+
+	#[test]
+	fn zoom_at_keeps_the_point_in_place() {
+		let mut camera = Camera::new(40.0, 30.0, 10.0);
+		let point = Vector2f::new(31.0, 8.0);
+		let before = screen_position(&camera, point);
+		camera.zoom_at(0.5, point);
+		camera.update(1.0 / 60.0);
+		let during = screen_position(&camera, point);
+		settle(&mut camera);
+		let after = screen_position(&camera, point);
+		assert!(close(camera.get_zoom(), 0.5));
+		for &(x, y) in [during, after].iter() {
+			assert!(close(x, before.0) && close(y, before.1), "{:?} instead of {:?}", (x, y), before);
+		}
+	}
+
+	#[test]
+	fn zoom_stays_inside_the_limits() {
+		let mut camera = Camera::new(40.0, 30.0, 10.0);
+		camera.zoom(100.0);
+		settle(&mut camera);
+		assert!(close(camera.get_zoom(), MAX_ZOOM));
+		camera.zoom(0.0001);
+		settle(&mut camera);
+		assert!(close(camera.get_zoom(), MIN_ZOOM));
+		camera.set_zoom_limits(0.5, 2.0);
+		settle(&mut camera);
+		assert!(close(camera.get_zoom(), 0.5));
+		camera.zoom_at(10.0, Vector2f::new(0.0, 0.0));
+		settle(&mut camera);
+		assert!(close(camera.get_zoom(), 2.0));
+	}
+
+	#[test]
+	fn trauma_decays_to_nothing() {
+		let mut camera = Camera::new(40.0, 30.0, 10.0);
+		camera.add_trauma(0.8);
+		assert!(close(camera.get_trauma(), 0.8));
+		camera.add_trauma(0.5);
+		assert!(close(camera.get_trauma(), 1.0));
+		camera.update(0.25);
+		assert!(close(camera.get_trauma(), 1.0 - 0.25 * TRAUMA_DECAY));
+		camera.update(0.25);
+		assert!(camera.get_view().get_center().x != 20.0 || camera.get_view().get_center().y != 15.0);
+		camera.update(2.0);
+		assert_eq!(camera.get_trauma(), 0.0);
+		let center = camera.get_view().get_center();
+		assert!(close(center.x, 20.0) && close(center.y, 15.0));
+	}
+
+	#[test]
+	fn view_stays_inside_the_map() {
+		let size = 50 * 40;
+		let map = Map::new_init(50, 40, vec![0; size], vec![0; size], vec![0; size]);
+		let mut camera = Camera::new(40.0, 30.0, 10.0);
+		camera.fit_to_map(&map);
+		camera.zoom(10.0);
+		settle(&mut camera);
+		assert!(close(camera.get_zoom(), 1.25));
+		assert_inside_map(&camera, 50.0, 40.0);
+		camera.zoom_at(0.5, Vector2f::new(49.0, 39.0));
+		settle(&mut camera);
+		assert_inside_map(&camera, 50.0, 40.0);
+		camera.snap_to(Vector2f::new(-100.0, 300.0));
+		assert_inside_map(&camera, 50.0, 40.0);
+		camera.follow(Vector2f::new(200.0, -50.0));
+		camera.add_trauma(1.0);
+		for _ in 0..120 {
+			camera.update(1.0 / 60.0);
+			assert_inside_map(&camera, 50.0, 40.0);
+		}
+	}
+}
@@ -0,0 +1,248 @@
+use sfml::system::Vector2f;
+use sfml::graphics::{Drawable, RenderStates, RenderTarget, Color, VertexArray, Quads};
+use game::ecs::{Entity, Storage};
+use game::fov::FieldOfView;
+use game::movement::BodyShape;
+
+/// Centre in tiles and the look direction in radians
+pub struct Position {
+	pub position: Vector2f,
+	pub facing: f32,
+	pub previous: Vector2f,		// position at the start of the tick, drawing blends between the two
+}
+
+impl Position {
+	pub fn new(position: Vector2f) -> Self {
+		Position {
+			position: position,
+			facing: 0.0,
+			previous: position,
+		}
+	}
+
+	/// Where to draw between the last two ticks, alpha 0 is the previous tick
+	pub fn interpolated(&self, alpha: f32) -> Vector2f {
+		self.previous + (self.position - self.previous) * alpha
+	}
+}
+
+/// Tiles per second
+pub struct Velocity {
+	pub velocity: Vector2f,
+}
+
+/// Entities with a collider are moved by the MovementSolver, the others pass through walls
+pub struct Collider {
+	pub shape: BodyShape,
+}
+
+pub struct Sprite {
+	pub color: Color,
+	pub size: f32,			// in tiles
+}
+
+pub struct Health {
+	pub current: f32,
+	pub max: f32,
+}
+
+impl Health {
+	pub fn new(max: f32) -> Self {
+		Health {
+			current: max,
+			max: max,
+		}
+	}
+
+	pub fn damage(&mut self, amount: f32) {
+		self.current = (self.current - amount).max(0.0);
+	}
+
+	pub fn heal(&mut self, amount: f32) {
+		self.current = (self.current + amount).min(self.max);
+	}
+
+	pub fn is_dead(&self) -> bool {
+		self.current <= 0.0
+	}
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Behaviour {
+	Idle,
+	Wander,
+	Chase(&'static str),		// follows the named flow field down to its goals, wanders where it has no step
+	Flee(&'static str),			// same, for fields made with DistanceMap::new_fleeing
+}
+
+pub struct Ai {
+	pub behaviour: Behaviour,
+	pub speed: f32,				// tiles per second
+	pub timer: f32,				// seconds until a wandering entity picks a new direction
+}
+
+impl Ai {
+	pub fn new(behaviour: Behaviour, speed: f32) -> Self {
+		Ai {
+			behaviour: behaviour,
+			speed: speed,
+			timer: 0.0,
+		}
+	}
+}
+
+/// Movement and aim from the input handler
+pub struct PlayerControl {
+	pub direction: Vector2f,	// length up to 1
+	pub aim: Vector2f,			// point in tiles the player looks at
+	pub speed: f32,				// tiles per second
+}
+
+pub struct Item {
+	pub name: String,
+	pub count: u32,
+}
+
+pub struct Inventory {
+	pub items: Vec<Item>,
+	pub capacity: usize,		// different items, not their count
+}
+
+impl Inventory {
+	pub fn new(capacity: usize) -> Self {
+		Inventory {
+			items: Vec::new(),
+			capacity: capacity,
+		}
+	}
+
+	/// Returns false if a new item does not fit anymore
+	pub fn add(&mut self, name: &str, count: u32) -> bool {
+		if let Some(item) = self.items.iter_mut().find(|item| item.name == name) {
+			item.count += count;
+			return true;
+		}
+		if self.items.len() >= self.capacity {
+			return false;
+		}
+		self.items.push(Item {
+			name: name.to_string(),
+			count: count,
+		});
+		true
+	}
+
+	/// Returns false if there are not enough
+	pub fn remove(&mut self, name: &str, count: u32) -> bool {
+		let index = match self.items.iter().position(|item| item.name == name && item.count >= count) {
+			Some(index) => index,
+			None => return false,
+		};
+		self.items[index].count -= count;
+		if self.items[index].count == 0 {
+			self.items.remove(index);
+		}
+		true
+	}
+
+	pub fn count(&self, name: &str) -> u32 {
+		self.items.iter().find(|item| item.name == name).map(|item| item.count).unwrap_or(0)
+	}
+}
+
+/// One storage per component type
+pub struct Components {
+	pub positions: Storage<Position>,
+	pub velocities: Storage<Velocity>,
+	pub colliders: Storage<Collider>,
+	pub sprites: Storage<Sprite>,
+	pub healths: Storage<Health>,
+	pub ais: Storage<Ai>,
+	pub inventories: Storage<Inventory>,
+	pub players: Storage<PlayerControl>,
+}
+
+impl Components {
+	pub fn new() -> Self {
+		Components {
+			positions: Storage::new(),
+			velocities: Storage::new(),
+			colliders: Storage::new(),
+			sprites: Storage::new(),
+			healths: Storage::new(),
+			ais: Storage::new(),
+			inventories: Storage::new(),
+			players: Storage::new(),
+		}
+	}
+
+	/// Drops every component of a despawned entity
+	pub fn remove_all(&mut self, entity: Entity) {
+		self.positions.remove(entity);
+		self.velocities.remove(entity);
+		self.colliders.remove(entity);
+		self.sprites.remove(entity);
+		self.healths.remove(entity);
+		self.ais.remove(entity);
+		self.inventories.remove(entity);
+		self.players.remove(entity);
+	}
+}
+
+/// Coloured quads for every entity with a position and a sprite
+pub struct EntityRenderer {
+	vertices: VertexArray,
+	capacity: usize,		// quads the vertex array has room for
+	count: usize,
+}
+
+impl EntityRenderer {
+	pub fn new() -> Self {
+		EntityRenderer {
+			vertices: VertexArray::new_init(Quads, 0).expect("Could not create VertexArray"),
+			capacity: 0,
+			count: 0,
+		}
+	}
+
+	/// Only entities inside the field of view are drawn, if there is one. Alpha blends between
+	/// the previous and the current tick
+	pub fn update(&mut self, components: &Components, fov: Option<&FieldOfView>, alpha: f32) {
+		let needed = components.sprites.len();
+		if needed > self.capacity {
+			self.capacity = (needed * 2).max(64);
+			self.vertices = VertexArray::new_init(Quads, self.capacity as u32 * 4).expect("Could not create VertexArray");
+		}
+		let mut count = 0;
+		for (_, sprite, position) in components.sprites.join(&components.positions) {
+			if fov.map(|fov| !fov.can_see(position.position)).unwrap_or(false) {
+				continue;
+			}
+			let center = position.interpolated(alpha);
+			let half = sprite.size / 2.0;
+			let corners = [(-half, -half), (half, -half), (half, half), (-half, half)];
+			for (k, &(dx, dy)) in corners.iter().enumerate() {
+				let mut vertex = self.vertices.get_vertex(count as u32 * 4 + k as u32);
+				vertex.0.position = Vector2f::new(center.x + dx, center.y + dy);
+				vertex.0.color = sprite.color;
+			}
+			count += 1;
+		}
+		// Quads left over from the last frame collapse to nothing
+		for i in count..self.count {
+			for k in 0..4 {
+				let mut vertex = self.vertices.get_vertex(i as u32 * 4 + k);
+				vertex.0.position = Vector2f::new(0.0, 0.0);
+				vertex.0.color = Color::transparent();
+			}
+		}
+		self.count = count;
+	}
+}
+
+impl Drawable for EntityRenderer {
+	fn draw<RT: RenderTarget>(&self, target: &mut RT, _: &mut RenderStates) {
+		let mut rs = RenderStates::default();
+		target.draw_vertex_array(&self.vertices, &mut rs);
+	}
+}
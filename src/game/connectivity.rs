@@ -0,0 +1,323 @@
+use std::collections::VecDeque;
+use game::map::CollisionLayer;
+use game::map_generator::{self, Road, Dirt, DeepWater, ShallowWater, Building};
+use util::Vec2D;
+
+const NEIGHBORS: [(isize, isize); 4] = [(1, 0), (-1, 0), (0, 1), (0, -1)];
+
+/// A set of walkable tiles that can all reach each other
+pub struct Region {
+	pub id: usize,
+	pub tiles: Vec<(usize, usize)>,
+}
+
+impl Region {
+	pub fn size(&self) -> usize {
+		self.tiles.len()
+	}
+}
+
+/// Connected components of the walkable tiles of a map
+pub struct ConnectivityMap {
+	labels: Vec2D<Option<usize>>,
+	regions: Vec<Region>,
+}
+
+impl ConnectivityMap {
+	pub fn analyze(tiles: &Vec2D<u32>, collision: Option<&CollisionLayer>) -> Self {
+		let mut labels: Vec2D<Option<usize>> = Vec2D::from_vec(tiles.width(), tiles.height(), vec![None; tiles.len()]);
+		let mut regions: Vec<Region> = Vec::new();
+		let mut queue: VecDeque<(usize, usize)> = VecDeque::new();
+		for y in 0..tiles.height() {
+			for x in 0..tiles.width() {
+				if labels[(x, y)].is_some() || !map_generator::is_walkable(tiles[(x, y)]) {
+					continue;
+				}
+				let id = regions.len();
+				let mut region = Region { id: id, tiles: Vec::new() };
+				labels[(x, y)] = Some(id);
+				queue.push_back((x, y));
+				while let Some((cx, cy)) = queue.pop_front() {
+					region.tiles.push((cx, cy));
+					for &(dx, dy) in NEIGHBORS.iter() {
+						if let Some((nx, ny)) = step(tiles, collision, cx, cy, dx, dy) {
+							if labels[(nx, ny)].is_none() && map_generator::is_walkable(tiles[(nx, ny)]) {
+								labels[(nx, ny)] = Some(id);
+								queue.push_back((nx, ny));
+							}
+						}
+					}
+				}
+				regions.push(region);
+			}
+		}
+		ConnectivityMap {
+			labels: labels,
+			regions: regions,
+		}
+	}
+
+	pub fn regions(&self) -> &Vec<Region> {
+		&self.regions
+	}
+
+	pub fn region_at(&self, x: usize, y: usize) -> Option<usize> {
+		match self.labels.get(x, y) {
+			Some(label) => *label,
+			None => None,
+		}
+	}
+
+	pub fn is_connected(&self, a: (usize, usize), b: (usize, usize)) -> bool {
+		match (self.region_at(a.0, a.1), self.region_at(b.0, b.1)) {
+			(Some(ra), Some(rb)) => ra == rb,
+			_ => false,
+		}
+	}
+
+	pub fn largest(&self) -> Option<&Region> {
+		self.regions.iter().max_by_key(|r| r.size())
+	}
+
+	/// Every region that can not be reached from the largest one
+	pub fn isolated(&self) -> Vec<&Region> {
+		match self.largest() {
+			Some(main) => self.regions.iter().filter(|r| r.id != main.id).collect(),
+			None => Vec::new(),
+		}
+	}
+
+	/// Number of regions and where the isolated ones are, one line each
+	pub fn report(&self) -> String {
+		let mut report = format!("{} walkable regions", self.regions.len());
+		for region in self.isolated() {
+			let (x, y) = region.tiles[0];
+			report.push_str(&format!("\nisolated region {} ({} tiles) at {}, {}", region.id, region.size(), x, y));
+		}
+		report
+	}
+}
+
+/// The neighbouring tile in direction (dx, dy) if it is inside the map and no wall is in the way
+fn step(tiles: &Vec2D<u32>, collision: Option<&CollisionLayer>, x: usize, y: usize, dx: isize, dy: isize) -> Option<(usize, usize)> {
+	let nx = x as isize + dx;
+	let ny = y as isize + dy;
+	if nx < 0 || ny < 0 || nx >= tiles.width() as isize || ny >= tiles.height() as isize {
+		return None;
+	}
+	if let Some(collision) = collision {
+		let open = match (dx, dy) {
+			(1, 0) => collision.can_walk_right(x, y),
+			(-1, 0) => collision.can_walk_left(x, y),
+			(0, 1) => collision.can_walk_down(x, y),
+			_ => collision.can_walk_up(x, y),
+		};
+		if !open {
+			return None;
+		}
+	}
+	Some((nx as usize, ny as usize))
+}
+
+pub struct RepairReport {
+	pub removed_regions: usize,
+	pub removed_tiles: usize,
+	pub connected_regions: usize,
+	pub carved_tiles: usize,
+}
+
+/// Removes tiny pockets and carves paths from the remaining pockets to the main region
+pub struct ConnectivityRepair {
+	pub min_region_size: usize,
+	pub fill_tile: u32,			// replaces the tiles of removed pockets
+	pub bridge_tile: u32,		// used where a path crosses water
+	pub path_tile: u32,			// used where a path crosses other blocking tiles
+}
+
+impl ConnectivityRepair {
+	pub fn new() -> Self {
+		ConnectivityRepair {
+			min_region_size: 16,
+			fill_tile: DeepWater,
+			bridge_tile: Road,
+			path_tile: Dirt,
+		}
+	}
+
+	pub fn repair(&self, tiles: &mut Vec2D<u32>, collision: Option<&CollisionLayer>) -> RepairReport {
+		let mut report = RepairReport { removed_regions: 0, removed_tiles: 0, connected_regions: 0, carved_tiles: 0 };
+		let connectivity = ConnectivityMap::analyze(tiles, collision);
+		let main = match connectivity.largest() {
+			Some(main) => main.id,
+			None => return report,
+		};
+		let mut connected: Vec2D<bool> = Vec2D::from_vec(tiles.width(), tiles.height(), vec![false; tiles.len()]);
+		for &(x, y) in connectivity.regions()[main].tiles.iter() {
+			connected[(x, y)] = true;
+		}
+		for region in connectivity.isolated() {
+			if region.size() < self.min_region_size {
+				for &(x, y) in region.tiles.iter() {
+					tiles[(x, y)] = self.fill_tile;
+				}
+				report.removed_regions += 1;
+				report.removed_tiles += region.size();
+			}
+			else if let Some(path) = Self::find_carve_path(tiles, collision, &connected, region) {
+				for &(x, y) in path.iter() {
+					if !map_generator::is_walkable(tiles[(x, y)]) {
+						tiles[(x, y)] = match tiles[(x, y)] {
+							DeepWater | ShallowWater => self.bridge_tile,
+							_ => self.path_tile,
+						};
+						report.carved_tiles += 1;
+					}
+					connected[(x, y)] = true;
+				}
+				for &(x, y) in region.tiles.iter() {
+					connected[(x, y)] = true;
+				}
+				report.connected_regions += 1;
+			}
+		}
+		report
+	}
+
+	/// Shortest path from the region to an already connected tile, never crossing buildings
+	fn find_carve_path(tiles: &Vec2D<u32>, collision: Option<&CollisionLayer>, connected: &Vec2D<bool>, region: &Region) -> Option<Vec<(usize, usize)>> {
+		let mut parent: Vec2D<Option<(usize, usize)>> = Vec2D::from_vec(tiles.width(), tiles.height(), vec![None; tiles.len()]);
+		let mut visited: Vec2D<bool> = Vec2D::from_vec(tiles.width(), tiles.height(), vec![false; tiles.len()]);
+		let mut queue: VecDeque<(usize, usize)> = VecDeque::new();
+		for &(x, y) in region.tiles.iter() {
+			visited[(x, y)] = true;
+			queue.push_back((x, y));
+		}
+		while let Some((x, y)) = queue.pop_front() {
+			if connected[(x, y)] {
+				let mut path = Vec::new();
+				let mut current = Some((x, y));
+				while let Some(p) = current {
+					path.push(p);
+					current = parent[p];
+				}
+				return Some(path);
+			}
+			for &(dx, dy) in NEIGHBORS.iter() {
+				if let Some((nx, ny)) = step(tiles, collision, x, y, dx, dy) {
+					if !visited[(nx, ny)] && tiles[(nx, ny)] != Building {
+						visited[(nx, ny)] = true;
+						parent[(nx, ny)] = Some((x, y));
+						queue.push_back((nx, ny));
+					}
+				}
+			}
+		}
+		None
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use game::map_generator::{Grass, Snow};
+
+	/// '.' grass, '~' deep water, 'x' snow, '#' building
+	fn grid(rows: &[&str]) -> Vec2D<u32> {
+		let tiles: Vec<u32> = rows.iter().flat_map(|row| row.chars()).map(|c| match c {
+			'~' => DeepWater,
+			'x' => Snow,
+			'#' => Building,
+			_ => Grass,
+		}).collect();
+		Vec2D::from_vec(rows[0].len(), rows.len(), tiles)
+	}
+
+	#[test]
+	fn regions_follow_water_and_walls() {
+		let tiles = grid(&[
+			"....~..",
+			"....~..",
+			"~~~~~~~",
+			"..~....",
+		]);
+		let connectivity = ConnectivityMap::analyze(&tiles, None);
+		let sizes: Vec<usize> = connectivity.regions().iter().map(|r| r.size()).collect();
+		assert_eq!(sizes, vec![8, 4, 2, 4]);
+		assert_eq!(connectivity.largest().unwrap().id, 0);
+		let isolated: Vec<usize> = connectivity.isolated().iter().map(|r| r.id).collect();
+		assert_eq!(isolated, vec![1, 2, 3]);
+		assert_eq!(connectivity.region_at(6, 0), Some(1));
+		assert_eq!(connectivity.region_at(4, 0), None);
+		assert!(connectivity.is_connected((0, 0), (3, 1)));
+		assert!(!connectivity.is_connected((0, 0), (6, 3)));
+
+		let mut walls = CollisionLayer::new(7, 4);
+		walls.set_collision_right(0, 0);
+		walls.set_collision_right(0, 1);
+		let connectivity = ConnectivityMap::analyze(&tiles, Some(&walls));
+		assert_eq!(connectivity.regions().len(), 5);
+		assert!(!connectivity.is_connected((0, 0), (1, 0)));
+		assert_eq!(connectivity.largest().unwrap().tiles.len(), 6);
+		assert_eq!(connectivity.isolated().len(), 4);
+	}
+
+	#[test]
+	fn tiny_pockets_are_filled() {
+		let mut tiles = grid(&[
+			"........",
+			"........",
+			"........",
+			"....~~~~",
+			"....~..~",
+			"....~~~~",
+		]);
+		let report = ConnectivityRepair::new().repair(&mut tiles, None);
+		assert_eq!((report.removed_regions, report.removed_tiles), (1, 2));
+		assert_eq!((report.connected_regions, report.carved_tiles), (0, 0));
+		assert_eq!(tiles[(5, 4)], DeepWater);
+		assert_eq!(tiles[(6, 4)], DeepWater);
+		assert_eq!(ConnectivityMap::analyze(&tiles, None).regions().len(), 1);
+	}
+
+	#[test]
+	fn large_pockets_get_a_path_around_buildings() {
+		let mut tiles = grid(&[
+			"........~.",
+			"........~~",
+			"..........",
+			"####~#####",
+			"..........",
+			"..........",
+		]);
+		let report = ConnectivityRepair::new().repair(&mut tiles, None);
+		assert_eq!((report.removed_regions, report.removed_tiles), (1, 1));
+		assert_eq!((report.connected_regions, report.carved_tiles), (1, 1));
+		assert_eq!(tiles[(9, 0)], DeepWater);
+		assert_eq!(tiles[(4, 3)], Road);
+		for x in (0..10).filter(|&x| x != 4) {
+			assert_eq!(tiles[(x, 3)], Building);
+		}
+		let connectivity = ConnectivityMap::analyze(&tiles, None);
+		assert!(connectivity.is_connected((0, 0), (9, 5)));
+	}
+
+	#[test]
+	fn carved_paths_respect_walls() {
+		let mut tiles = grid(&[
+			"..........",
+			"..........",
+			"..........",
+			"####~###x#",
+			"..........",
+			"..........",
+		]);
+		let mut walls = CollisionLayer::new(10, 6);
+		walls.set_collision_top(4, 4);
+		let report = ConnectivityRepair::new().repair(&mut tiles, Some(&walls));
+		assert_eq!((report.connected_regions, report.carved_tiles), (1, 1));
+		assert_eq!(tiles[(4, 3)], DeepWater);
+		assert_eq!(tiles[(8, 3)], Dirt);
+		let connectivity = ConnectivityMap::analyze(&tiles, Some(&walls));
+		assert_eq!(connectivity.regions().len(), 1);
+	}
+}
@@ -0,0 +1,262 @@
+use std::iter::Zip;
+use std::slice::{Iter, IterMut};
+use std::u32;
+
+const NONE: u32 = u32::MAX;
+
+/// Index into the component storages plus a generation, so ids of despawned entities stay invalid
+/// after their index is reused
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct Entity {
+	index: u32,
+	generation: u32,
+}
+
+impl Entity {
+	pub fn index(&self) -> usize {
+		self.index as usize
+	}
+
+	pub fn generation(&self) -> u32 {
+		self.generation
+	}
+}
+
+/// Hands out entity ids and reuses the indices of despawned ones
+pub struct Entities {
+	generations: Vec<u32>,
+	alive: Vec<bool>,
+	free: Vec<u32>,
+	count: usize,
+}
+
+impl Entities {
+	pub fn new() -> Self {
+		Entities {
+			generations: Vec::new(),
+			alive: Vec::new(),
+			free: Vec::new(),
+			count: 0,
+		}
+	}
+
+	pub fn create(&mut self) -> Entity {
+		self.count += 1;
+		match self.free.pop() {
+			Some(index) => {
+				self.alive[index as usize] = true;
+				Entity {
+					index: index,
+					generation: self.generations[index as usize],
+				}
+			},
+			None => {
+				self.generations.push(0);
+				self.alive.push(true);
+				Entity {
+					index: self.generations.len() as u32 - 1,
+					generation: 0,
+				}
+			},
+		}
+	}
+
+	/// Returns false if the entity was already destroyed
+	pub fn destroy(&mut self, entity: Entity) -> bool {
+		if !self.is_alive(entity) {
+			return false;
+		}
+		let index = entity.index as usize;
+		self.alive[index] = false;
+		self.generations[index] = self.generations[index].wrapping_add(1);
+		self.free.push(entity.index);
+		self.count -= 1;
+		true
+	}
+
+	pub fn is_alive(&self, entity: Entity) -> bool {
+		let index = entity.index as usize;
+		index < self.alive.len() && self.alive[index] && self.generations[index] == entity.generation
+	}
+
+	pub fn len(&self) -> usize {
+		self.count
+	}
+
+	pub fn iter<'a>(&'a self) -> impl Iterator<Item = Entity> + 'a {
+		self.alive.iter().enumerate().filter(|&(_, &alive)| alive).map(move |(index, _)| Entity {
+			index: index as u32,
+			generation: self.generations[index],
+		})
+	}
+}
+
+/// Sparse set: components packed together for fast iteration, looked up through the entity index
+pub struct Storage<T> {
+	dense: Vec<T>,
+	entities: Vec<Entity>,		// owner of every dense component
+	sparse: Vec<u32>,			// dense index for every entity index, NONE if it has no component
+}
+
+impl<T> Storage<T> {
+	pub fn new() -> Self {
+		Storage {
+			dense: Vec::new(),
+			entities: Vec::new(),
+			sparse: Vec::new(),
+		}
+	}
+
+	/// Returns the component the entity had before
+	pub fn insert(&mut self, entity: Entity, component: T) -> Option<T> {
+		let index = entity.index as usize;
+		if index >= self.sparse.len() {
+			self.sparse.resize(index + 1, NONE);
+		}
+		match self.sparse[index] {
+			NONE => {
+				self.sparse[index] = self.dense.len() as u32;
+				self.dense.push(component);
+				self.entities.push(entity);
+				None
+			},
+			i => {
+				// A stale component of an older generation is simply replaced
+				self.entities[i as usize] = entity;
+				Some(::std::mem::replace(&mut self.dense[i as usize], component))
+			},
+		}
+	}
+
+	pub fn remove(&mut self, entity: Entity) -> Option<T> {
+		let i = match self.dense_index(entity) {
+			Some(i) => i,
+			None => return None,
+		};
+		let last = self.entities[self.entities.len() - 1];
+		self.sparse[last.index as usize] = i as u32;
+		self.sparse[entity.index as usize] = NONE;
+		self.entities.swap_remove(i);
+		Some(self.dense.swap_remove(i))
+	}
+
+	pub fn get(&self, entity: Entity) -> Option<&T> {
+		self.dense_index(entity).map(move |i| &self.dense[i])
+	}
+
+	pub fn get_mut(&mut self, entity: Entity) -> Option<&mut T> {
+		match self.dense_index(entity) {
+			Some(i) => Some(&mut self.dense[i]),
+			None => None,
+		}
+	}
+
+	pub fn contains(&self, entity: Entity) -> bool {
+		self.dense_index(entity).is_some()
+	}
+
+	pub fn len(&self) -> usize {
+		self.dense.len()
+	}
+
+	pub fn entities(&self) -> &[Entity] {
+		&self.entities
+	}
+
+	pub fn iter(&self) -> Zip<Iter<Entity>, Iter<T>> {
+		self.entities.iter().zip(self.dense.iter())
+	}
+
+	pub fn iter_mut(&mut self) -> Zip<Iter<Entity>, IterMut<T>> {
+		self.entities.iter().zip(self.dense.iter_mut())
+	}
+
+	/// Entities that have both components, walks this storage so it should be the smaller one
+	pub fn join<'a, U>(&'a self, other: &'a Storage<U>) -> impl Iterator<Item = (Entity, &'a T, &'a U)> + 'a {
+		self.iter().filter_map(move |(&entity, a)| other.get(entity).map(|b| (entity, a, b)))
+	}
+
+	fn dense_index(&self, entity: Entity) -> Option<usize> {
+		match self.sparse.get(entity.index as usize) {
+			Some(&i) if i != NONE && self.entities[i as usize] == entity => Some(i as usize),
+			_ => None,
+		}
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn destroyed_ids_stay_invalid_after_reuse() {
+		let mut entities = Entities::new();
+		let a = entities.create();
+		let b = entities.create();
+		assert!(entities.destroy(a));
+		assert!(!entities.destroy(a));
+		assert!(!entities.is_alive(a));
+		let c = entities.create();
+		assert_eq!(c.index(), a.index());
+		assert_eq!(c.generation(), a.generation() + 1);
+		assert!(entities.is_alive(c));
+		assert!(!entities.is_alive(a));
+		assert_eq!(entities.len(), 2);
+		assert_eq!(entities.iter().collect::<Vec<_>>(), vec![c, b]);
+	}
+
+	#[test]
+	fn storage_insert_get_remove() {
+		let mut entities = Entities::new();
+		let ids: Vec<Entity> = (0..4).map(|_| entities.create()).collect();
+		let mut storage = Storage::new();
+		for (i, &entity) in ids.iter().enumerate() {
+			assert_eq!(storage.insert(entity, i * 10), None);
+		}
+		assert_eq!(storage.insert(ids[2], 25), Some(20));
+		assert_eq!(storage.remove(ids[0]), Some(0));
+		assert_eq!(storage.remove(ids[0]), None);
+		// The last component was moved into the gap
+		assert_eq!(storage.get(ids[3]), Some(&30));
+		assert_eq!(storage.get(ids[2]), Some(&25));
+		*storage.get_mut(ids[1]).unwrap() += 1;
+		assert_eq!(storage.get(ids[1]), Some(&11));
+		assert_eq!(storage.len(), 3);
+		assert_eq!(storage.remove(ids[3]), Some(30));
+		assert_eq!(storage.iter().map(|(&e, &v)| (e, v)).collect::<Vec<_>>(), vec![(ids[2], 25), (ids[1], 11)]);
+	}
+
+	#[test]
+	fn stale_ids_do_not_see_components() {
+		let mut entities = Entities::new();
+		let old = entities.create();
+		let mut storage = Storage::new();
+		storage.insert(old, "old");
+		entities.destroy(old);
+		let new = entities.create();
+		assert!(storage.contains(old));
+		assert!(!storage.contains(new));
+		assert_eq!(storage.get(new), None);
+		assert_eq!(storage.remove(new), None);
+		assert_eq!(storage.insert(new, "new"), Some("old"));
+		assert!(!storage.contains(old));
+		assert_eq!(storage.get(new), Some(&"new"));
+		assert_eq!(storage.len(), 1);
+	}
+
+	#[test]
+	fn join_finds_entities_with_both() {
+		let mut entities = Entities::new();
+		let ids: Vec<Entity> = (0..5).map(|_| entities.create()).collect();
+		let mut positions = Storage::new();
+		let mut healths = Storage::new();
+		for (i, &entity) in ids.iter().enumerate() {
+			positions.insert(entity, i as f32);
+			if i % 2 == 0 {
+				healths.insert(entity, i * 100);
+			}
+		}
+		let joined: Vec<(Entity, f32, usize)> = healths.join(&positions).map(|(e, &h, &p)| (e, p, h)).collect();
+		assert_eq!(joined, vec![(ids[0], 0.0, 0), (ids[2], 2.0, 200), (ids[4], 4.0, 400)]);
+	}
+}
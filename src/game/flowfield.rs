@@ -0,0 +1,417 @@
+use std::collections::{BinaryHeap, HashMap};
+use std::f32;
+use game::map::Map;
+use game::pathfinding::{self, MovementProfile, Candidate};
+use util::Vec2D;
+
+const SQRT_2: f32 = 1.41421356;
+const NO_STEP: u8 = 255;
+const DIRECTIONS: [(isize, isize); 8] = [(1, 0), (-1, 0), (0, 1), (0, -1), (1, 1), (1, -1), (-1, 1), (-1, -1)];
+
+/// Multi source Dijkstra map: the cost from every tile to the cheapest goal and the step towards it.
+/// Goals can start at any value, so a goal that is worth more can be given a lower one
+pub struct DistanceMap {
+	pub profile: MovementProfile,
+	pub limit: f32,			// tiles further away than this are left unreached
+	goals: Vec<((usize, usize), f32)>,
+	distances: Vec2D<f32>,
+	steps: Vec2D<u8>,		// index into DIRECTIONS, NO_STEP on goals and unreached tiles
+	marks: Vec2D<u32>,		// tiles invalidated in the current pass carry the generation
+	generation: u32,
+}
+
+impl DistanceMap {
+	pub fn new(map: &Map, profile: MovementProfile) -> Self {
+		let (width, height) = (map.width(), map.height());
+		DistanceMap {
+			profile: profile,
+			limit: f32::INFINITY,
+			goals: Vec::new(),
+			distances: Vec2D::from_vec(width, height, vec![f32::INFINITY; width * height]),
+			steps: Vec2D::from_vec(width, height, vec![NO_STEP; width * height]),
+			marks: Vec2D::from_vec(width, height, vec![0; width * height]),
+			generation: 0,
+		}
+	}
+
+	/// Walks away from the goals of another map: its distances scaled by a negative factor are
+	/// used as goals, so agents head for far away tiles instead of running into corners.
+	/// Has to be called again after the other map changed
+	pub fn new_fleeing(map: &Map, from: &DistanceMap, factor: f32, profile: MovementProfile) -> Self {
+		let mut fleeing = DistanceMap::new(map, profile);
+		let mut goals = Vec::new();
+		for y in 0..map.height() {
+			for x in 0..map.width() {
+				let distance = from.distances[(x, y)];
+				if distance.is_finite() {
+					goals.push(((x, y), distance * -factor));
+				}
+			}
+		}
+		fleeing.set_goals(map, &goals);
+		fleeing
+	}
+
+	pub fn goals(&self) -> &[((usize, usize), f32)] {
+		&self.goals
+	}
+
+	/// Only the goals that were removed, moved or changed value invalidate tiles, new ones just spread
+	pub fn set_goals(&mut self, map: &Map, goals: &[((usize, usize), f32)]) {
+		let goals: Vec<((usize, usize), f32)> = goals.iter().cloned().filter(|&(tile, _)| self.profile.tile_cost(map, tile.0, tile.1).is_some()).collect();
+		let removed: Vec<(usize, usize)> = self.goals.iter().filter(|old| !goals.contains(old)).map(|&(tile, _)| tile).collect();
+		self.goals = goals;
+		let mut open = self.invalidate(&removed);
+		for &(tile, value) in self.goals.iter() {
+			if value < self.distances[tile] {
+				self.distances[tile] = value;
+				self.steps[tile] = NO_STEP;
+				open.push(Candidate {
+					cost: value,
+					tile: tile,
+				});
+			}
+		}
+		self.spread(map, open);
+	}
+
+	/// Goals with the value 0
+	pub fn set_goal_tiles(&mut self, map: &Map, tiles: &[(usize, usize)]) {
+		let goals: Vec<((usize, usize), f32)> = tiles.iter().map(|&tile| (tile, 0.0)).collect();
+		self.set_goals(map, &goals);
+	}
+
+	/// Call after tiles or walls in the area changed
+	pub fn update_area(&mut self, map: &Map, x: usize, y: usize, width: usize, height: usize) {
+		// One tile more, steps into the area and diagonal steps past it changed too
+		let (x0, y0) = (x.saturating_sub(1), y.saturating_sub(1));
+		let (x1, y1) = ((x + width + 1).min(map.width()), (y + height + 1).min(map.height()));
+		let mut tiles = Vec::with_capacity((x1 - x0) * (y1 - y0));
+		for ty in y0..y1 {
+			for tx in x0..x1 {
+				tiles.push((tx, ty));
+			}
+		}
+		let open = self.invalidate(&tiles);
+		self.spread(map, open);
+	}
+
+	/// Recomputes every tile
+	pub fn recompute(&mut self, map: &Map) {
+		let goals = self.goals.clone();
+		self.goals.clear();
+		for distance in self.distances.iter_mut() {
+			*distance = f32::INFINITY;
+		}
+		for step in self.steps.iter_mut() {
+			*step = NO_STEP;
+		}
+		self.set_goals(map, &goals);
+	}
+
+	/// Cost to the cheapest goal, infinite if no goal can be reached
+	pub fn distance(&self, x: usize, y: usize) -> f32 {
+		self.distances[(x, y)]
+	}
+
+	pub fn distances(&self) -> &Vec2D<f32> {
+		&self.distances
+	}
+
+	/// The next tile on the way to the cheapest goal, None if none can be reached and on goals, unless
+	/// their value is so high that walking to a neighbouring goal is cheaper
+	pub fn next_step(&self, x: usize, y: usize) -> Option<(usize, usize)> {
+		match self.steps[(x, y)] {
+			NO_STEP => None,
+			step => {
+				let (dx, dy) = DIRECTIONS[step as usize];
+				Some(((x as isize + dx) as usize, (y as isize + dy) as usize))
+			},
+		}
+	}
+
+	/// Resets the tiles and every tile whose steps lead through them, returns the open list
+	/// seeded with the valid tiles around them and the goals among them
+	fn invalidate(&mut self, tiles: &[(usize, usize)]) -> BinaryHeap<Candidate> {
+		let (width, height) = (self.distances.width(), self.distances.height());
+		// A new generation unmarks every tile without touching the grid
+		self.generation = self.generation.wrapping_add(1);
+		if self.generation == 0 {
+			for mark in self.marks.iter_mut() {
+				*mark = 0;
+			}
+			self.generation = 1;
+		}
+		let generation = self.generation;
+		let mut queue: Vec<(usize, usize)> = Vec::new();
+		for &tile in tiles.iter() {
+			if self.marks[tile] != generation && self.distances[tile].is_finite() {
+				self.marks[tile] = generation;
+				queue.push(tile);
+			}
+		}
+		let mut i = 0;
+		while i < queue.len() {
+			let (x, y) = queue[i];
+			i += 1;
+			for (j, &(dx, dy)) in DIRECTIONS.iter().enumerate() {
+				if let Some(tile) = Self::offset(x, y, -dx, -dy, width, height) {
+					if self.marks[tile] != generation && self.steps[tile] == j as u8 {
+						self.marks[tile] = generation;
+						queue.push(tile);
+					}
+				}
+			}
+		}
+		let mut open = BinaryHeap::new();
+		for &tile in queue.iter() {
+			self.distances[tile] = f32::INFINITY;
+			self.steps[tile] = NO_STEP;
+		}
+		for &(x, y) in queue.iter() {
+			for &(dx, dy) in DIRECTIONS.iter() {
+				if let Some(tile) = Self::offset(x, y, dx, dy, width, height) {
+					if self.marks[tile] != generation && self.distances[tile].is_finite() {
+						open.push(Candidate {
+							cost: self.distances[tile],
+							tile: tile,
+						});
+					}
+				}
+			}
+		}
+		for &(tile, value) in self.goals.iter() {
+			if value < self.distances[tile] {
+				self.distances[tile] = value;
+				self.steps[tile] = NO_STEP;
+				open.push(Candidate {
+					cost: value,
+					tile: tile,
+				});
+			}
+		}
+		open
+	}
+
+	/// Dijkstra outwards from the open list, walking the steps backwards
+	fn spread(&mut self, map: &Map, mut open: BinaryHeap<Candidate>) {
+		while let Some(Candidate { cost, tile }) = open.pop() {
+			if cost > self.distances[tile] {
+				continue;
+			}
+			let enter = match self.profile.tile_cost(map, tile.0, tile.1) {
+				Some(enter) => enter,
+				None => continue,
+			};
+			// Steps are symmetric, so every neighbour can also step onto this tile
+			for (from, _) in pathfinding::neighbours(map, tile.0, tile.1, &self.profile) {
+				let diagonal = from.0 != tile.0 && from.1 != tile.1;
+				let distance = cost + if diagonal { enter * SQRT_2 } else { enter };
+				if distance < self.distances[from] && distance <= self.limit {
+					self.distances[from] = distance;
+					self.steps[from] = Self::direction(from, tile);
+					open.push(Candidate {
+						cost: distance,
+						tile: from,
+					});
+				}
+			}
+		}
+	}
+
+	fn direction(from: (usize, usize), to: (usize, usize)) -> u8 {
+		let delta = (to.0 as isize - from.0 as isize, to.1 as isize - from.1 as isize);
+		DIRECTIONS.iter().position(|&d| d == delta).expect("Could not find step direction") as u8
+	}
+
+	fn offset(x: usize, y: usize, dx: isize, dy: isize, width: usize, height: usize) -> Option<(usize, usize)> {
+		let (nx, ny) = (x as isize + dx, y as isize + dy);
+		if nx < 0 || ny < 0 || nx >= width as isize || ny >= height as isize {
+			None
+		}
+		else {
+			Some((nx as usize, ny as usize))
+		}
+	}
+}
+
+/// Named distance maps that agents mix, e.g. "players" and "noise" for zombies, "fleeing" for humans
+pub struct FlowFields {
+	maps: HashMap<String, DistanceMap>,
+}
+
+impl FlowFields {
+	pub fn new() -> Self {
+		FlowFields {
+			maps: HashMap::new(),
+		}
+	}
+
+	pub fn insert(&mut self, name: &str, distance_map: DistanceMap) {
+		self.maps.insert(name.to_string(), distance_map);
+	}
+
+	pub fn remove(&mut self, name: &str) -> Option<DistanceMap> {
+		self.maps.remove(name)
+	}
+
+	pub fn get(&self, name: &str) -> Option<&DistanceMap> {
+		self.maps.get(name)
+	}
+
+	pub fn get_mut(&mut self, name: &str) -> Option<&mut DistanceMap> {
+		self.maps.get_mut(name)
+	}
+
+	/// Forwards map changes to every distance map
+	pub fn update_area(&mut self, map: &Map, x: usize, y: usize, width: usize, height: usize) {
+		for distance_map in self.maps.values_mut() {
+			distance_map.update_area(map, x, y, width, height);
+		}
+	}
+
+	/// Neighbour with the lowest weighted sum of the named maps, None if standing still is best.
+	/// Maps that are missing or do not reach a tile are left out
+	pub fn best_step(&self, map: &Map, x: usize, y: usize, weights: &[(&str, f32)]) -> Option<(usize, usize)> {
+		let first = weights.first().and_then(|&(name, _)| self.maps.get(name));
+		let profile = match first {
+			Some(distance_map) => &distance_map.profile,
+			None => return None,
+		};
+		let score = |tile: (usize, usize)| -> f32 {
+			weights.iter().filter_map(|&(name, weight)| self.maps.get(name).map(|m| (m.distances[tile], weight))).filter(|&(distance, _)| distance.is_finite()).map(|(distance, weight)| distance * weight).sum()
+		};
+		let mut best = (None, score((x, y)));
+		for (tile, _) in pathfinding::neighbours(map, x, y, profile) {
+			let value = score(tile);
+			if value < best.1 {
+				best = (Some(tile), value);
+			}
+		}
+		best.0
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use game::map_generator::{Grass, Building};
+
+	fn open_map(width: usize, height: usize) -> Map {
+		let size = width * height;
+		Map::new_init(width, height, vec![Grass; size], vec![0; size], vec![0; size])
+	}
+
+	fn same_distances(a: &DistanceMap, b: &DistanceMap) -> bool {
+		a.distances().iter().zip(b.distances().iter()).all(|(&x, &y)| x == y || (x - y).abs() < 0.001)
+	}
+
+	#[test]
+	fn distances_and_steps_lead_to_the_goal() {
+		let map = open_map(5, 4);
+		let mut field = DistanceMap::new(&map, MovementProfile::new_human());
+		field.set_goal_tiles(&map, &[(4, 3)]);
+		assert_eq!(field.distance(4, 3), 0.0);
+		assert!((field.distance(0, 3) - 4.0).abs() < 0.001);
+		assert!((field.distance(0, 0) - (1.0 + 3.0 * SQRT_2)).abs() < 0.001);
+		assert_eq!(field.next_step(4, 3), None);
+		let mut tile = (0, 0);
+		for _ in 0..10 {
+			match field.next_step(tile.0, tile.1) {
+				Some(next) => {
+					assert!(field.distance(next.0, next.1) < field.distance(tile.0, tile.1));
+					tile = next;
+				},
+				None => break,
+			}
+		}
+		assert_eq!(tile, (4, 3));
+	}
+
+	#[test]
+	fn cheapest_goal_wins_and_limit_stops_spreading() {
+		let map = open_map(9, 1);
+		let mut field = DistanceMap::new(&map, MovementProfile::new_human());
+		field.set_goals(&map, &[((0, 0), 0.0), ((8, 0), -2.0)]);
+		assert_eq!(field.next_step(4, 0), Some((5, 0)));
+		assert_eq!(field.next_step(2, 0), Some((1, 0)));
+		let mut limited = DistanceMap::new(&map, MovementProfile::new_human());
+		limited.limit = 2.0;
+		limited.set_goal_tiles(&map, &[(0, 0)]);
+		assert!(limited.distance(2, 0).is_finite());
+		assert!(limited.distance(3, 0).is_infinite());
+		assert_eq!(limited.next_step(5, 0), None);
+	}
+
+	#[test]
+	fn new_goal_on_a_reached_tile_has_no_step() {
+		let map = open_map(5, 1);
+		let mut field = DistanceMap::new(&map, MovementProfile::new_human());
+		field.set_goal_tiles(&map, &[(0, 0)]);
+		assert_eq!(field.next_step(3, 0), Some((2, 0)));
+		field.set_goal_tiles(&map, &[(0, 0), (3, 0)]);
+		assert_eq!(field.next_step(3, 0), None);
+		assert_eq!(field.next_step(4, 0), Some((3, 0)));
+	}
+
+	#[test]
+	fn moving_goals_matches_recompute() {
+		let map = open_map(12, 8);
+		let mut field = DistanceMap::new(&map, MovementProfile::new_zombie());
+		let moves = [(0, 0), (5, 5), (11, 7), (6, 0), (6, 1)];
+		for &goal in moves.iter() {
+			field.set_goal_tiles(&map, &[goal, (2, 6)]);
+			let mut fresh = DistanceMap::new(&map, MovementProfile::new_zombie());
+			fresh.set_goal_tiles(&map, &[goal, (2, 6)]);
+			assert!(same_distances(&field, &fresh));
+		}
+	}
+
+	#[test]
+	fn update_area_matches_recompute() {
+		let mut map = open_map(10, 10);
+		let mut field = DistanceMap::new(&map, MovementProfile::new_human());
+		field.set_goal_tiles(&map, &[(0, 0)]);
+		// A wall of buildings with a door, then the door closed and opened again
+		let edits = [(5, 0, Building), (5, 1, Building), (5, 2, Building), (5, 3, Building), (5, 5, Building),
+			(5, 6, Building), (5, 7, Building), (5, 8, Building), (5, 9, Building), (5, 4, Building), (5, 4, Grass)];
+		for &(x, y, tile) in edits.iter() {
+			map.set_tile(0, x, y, tile);
+			field.update_area(&map, x, y, 1, 1);
+			let mut fresh = DistanceMap::new(&map, MovementProfile::new_human());
+			fresh.set_goal_tiles(&map, &[(0, 0)]);
+			assert!(same_distances(&field, &fresh));
+		}
+		assert!(field.distance(9, 9).is_finite());
+		map.set_tile(0, 5, 4, Building);
+		field.update_area(&map, 5, 4, 1, 1);
+		assert!(field.distance(9, 9).is_infinite());
+		assert_eq!(field.next_step(9, 9), None);
+	}
+
+	#[test]
+	fn fleeing_heads_away() {
+		let map = open_map(9, 1);
+		let mut chase = DistanceMap::new(&map, MovementProfile::new_human());
+		chase.set_goal_tiles(&map, &[(2, 0)]);
+		let flee = DistanceMap::new_fleeing(&map, &chase, 1.2, MovementProfile::new_human());
+		assert_eq!(flee.next_step(3, 0), Some((4, 0)));
+		assert_eq!(flee.next_step(1, 0), Some((0, 0)));
+	}
+
+	#[test]
+	fn best_step_mixes_fields() {
+		let map = open_map(9, 1);
+		let mut fields = FlowFields::new();
+		let mut players = DistanceMap::new(&map, MovementProfile::new_zombie());
+		players.set_goal_tiles(&map, &[(0, 0)]);
+		let mut noise = DistanceMap::new(&map, MovementProfile::new_zombie());
+		noise.set_goal_tiles(&map, &[(8, 0)]);
+		fields.insert("players", players);
+		fields.insert("noise", noise);
+		assert_eq!(fields.best_step(&map, 4, 0, &[("players", 1.0)]), Some((3, 0)));
+		assert_eq!(fields.best_step(&map, 4, 0, &[("players", 1.0), ("noise", 2.0)]), Some((5, 0)));
+		assert_eq!(fields.best_step(&map, 4, 0, &[("missing", 1.0)]), None);
+		assert_eq!(fields.best_step(&map, 0, 0, &[("players", 1.0)]), None);
+	}
+}
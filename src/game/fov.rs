@@ -0,0 +1,409 @@
+use std::f32::consts::PI;
+use sfml::system::Vector2f;
+use sfml::graphics::{Drawable, RenderStates, RenderTarget, Color, VertexArray, Quads};
+use game::map::Map;
+use util::Vec2D;
+
+// Quadrants of the shadowcaster, (depth x, depth y, column x, column y)
+const QUADRANTS: [(isize, isize, isize, isize); 4] = [
+	(0, -1, 1, 0),		// north
+	(1, 0, 0, 1),		// east
+	(0, 1, 1, 0),		// south
+	(-1, 0, 0, 1),		// west
+];
+
+/// Tiles one observer can see, computed with symmetric shadowcasting
+pub struct FieldOfView {
+	pub radius: f32,
+	pub cone: Option<(f32, f32)>,		// facing angle and half width in radians, None sees all around
+	origin: (usize, usize),
+	visible: Vec2D<bool>,
+	bounds: (usize, usize, usize, usize),		// tiles that may be visible, x0, y0, x1, y1 exclusive
+}
+
+impl FieldOfView {
+	pub fn new(map: &Map, radius: f32) -> Self {
+		FieldOfView {
+			radius: radius,
+			cone: None,
+			origin: (0, 0),
+			visible: Vec2D::from_vec(map.width(), map.height(), vec![false; map.width() * map.height()]),
+			bounds: (0, 0, 0, 0),
+		}
+	}
+
+	/// Limits the view to a cone, facing is the angle of the look direction
+	pub fn set_cone(&mut self, facing: f32, width: f32) {
+		self.cone = Some((facing, width / 2.0));
+	}
+
+	/// Sight blocking tiles stop the view and are visible themselves, placed walls hide the tile behind them
+	pub fn compute(&mut self, map: &Map, position: Vector2f) {
+		let (x0, y0, x1, y1) = self.bounds;
+		for y in y0..y1 {
+			for x in x0..x1 {
+				self.visible[(x, y)] = false;
+			}
+		}
+		let (width, height) = (map.width(), map.height());
+		if position.x < 0.0 || position.y < 0.0 || position.x >= width as f32 || position.y >= height as f32 {
+			self.bounds = (0, 0, 0, 0);
+			return;
+		}
+		let origin = (position.x as usize, position.y as usize);
+		let reach = self.radius.ceil() as usize + 1;
+		self.origin = origin;
+		self.bounds = (origin.0.saturating_sub(reach), origin.1.saturating_sub(reach), (origin.0 + reach + 1).min(width), (origin.1 + reach + 1).min(height));
+		self.visible[origin] = true;
+		for quadrant in QUADRANTS.iter() {
+			self.scan(map, quadrant, 1, -1.0, 1.0);
+		}
+		if let Some(cone) = self.cone {
+			let (x0, y0, x1, y1) = self.bounds;
+			for y in y0..y1 {
+				for x in x0..x1 {
+					if self.visible[(x, y)] && (x, y) != origin && !Self::in_cone(cone, origin, x, y) {
+						self.visible[(x, y)] = false;
+					}
+				}
+			}
+		}
+	}
+
+	pub fn is_visible(&self, x: usize, y: usize) -> bool {
+		x < self.visible.width() && y < self.visible.height() && self.visible[(x, y)]
+	}
+
+	/// For entities, a point is visible if its tile is
+	pub fn can_see(&self, point: Vector2f) -> bool {
+		point.x >= 0.0 && point.y >= 0.0 && self.is_visible(point.x as usize, point.y as usize)
+	}
+
+	pub fn bounds(&self) -> (usize, usize, usize, usize) {
+		self.bounds
+	}
+
+	/// One row of one quadrant, recursing into the next row for every open stretch
+	fn scan(&mut self, map: &Map, quadrant: &(isize, isize, isize, isize), depth: usize, mut start: f32, end: f32) {
+		if depth as f32 > self.radius {
+			return;
+		}
+		let min_col = (depth as f32 * start + 0.5).floor() as isize;		// round ties up
+		let max_col = (depth as f32 * end - 0.5).ceil() as isize;			// round ties down
+		let mut previous: Option<bool> = None;
+		for col in min_col..(max_col + 1) {
+			let tile = self.transform(quadrant, depth, col);
+			let opaque = match tile {
+				Some((x, y)) => {
+					let blocks_sight = map.blocks_sight(x, y);
+					let behind_wall = self.behind_wall(map, quadrant, x, y, col);
+					let symmetric = col as f32 >= depth as f32 * start && col as f32 <= depth as f32 * end;
+					if !behind_wall && (blocks_sight || symmetric) && self.in_radius(x, y) {
+						self.visible[(x, y)] = true;
+					}
+					blocks_sight || behind_wall
+				},
+				None => true,
+			};
+			match previous {
+				Some(true) if !opaque => start = Self::slope(depth, col),
+				Some(false) if opaque => self.scan(map, quadrant, depth + 1, start, Self::slope(depth, col)),
+				_ => (),
+			}
+			previous = Some(opaque);
+		}
+		if previous == Some(false) {
+			self.scan(map, quadrant, depth + 1, start, end);
+		}
+	}
+
+	fn transform(&self, quadrant: &(isize, isize, isize, isize), depth: usize, col: isize) -> Option<(usize, usize)> {
+		let &(dx, dy, cx, cy) = quadrant;
+		let x = self.origin.0 as isize + dx * depth as isize + cx * col;
+		let y = self.origin.1 as isize + dy * depth as isize + cy * col;
+		if x < 0 || y < 0 || x >= self.visible.width() as isize || y >= self.visible.height() as isize {
+			None
+		}
+		else {
+			Some((x as usize, y as usize))
+		}
+	}
+
+	/// A placed wall on the side of the tile that faces the observer
+	fn behind_wall(&self, map: &Map, quadrant: &(isize, isize, isize, isize), x: usize, y: usize, col: isize) -> bool {
+		let walls = map.get_walls();
+		let (vertical, horizontal) = (walls.vertical_edges(), walls.horizontal_edges());
+		let near = match *quadrant {
+			(0, -1, _, _) => horizontal[(x, y + 1)],
+			(0, _, _, _) => horizontal[(x, y)],
+			(1, _, _, _) => vertical[(x, y)],
+			_ => vertical[(x + 1, y)],
+		};
+		let side = match (quadrant.2, col) {
+			(_, 0) => false,
+			(1, c) if c > 0 => vertical[(x, y)],
+			(1, _) => vertical[(x + 1, y)],
+			(_, c) if c > 0 => horizontal[(x, y)],
+			_ => horizontal[(x, y + 1)],
+		};
+		near || side
+	}
+
+	fn in_radius(&self, x: usize, y: usize) -> bool {
+		let dx = x as f32 - self.origin.0 as f32;
+		let dy = y as f32 - self.origin.1 as f32;
+		dx * dx + dy * dy <= self.radius * self.radius
+	}
+
+	fn in_cone(cone: (f32, f32), origin: (usize, usize), x: usize, y: usize) -> bool {
+		let angle = (y as f32 - origin.1 as f32).atan2(x as f32 - origin.0 as f32);
+		let mut difference = (angle - cone.0) % (2.0 * PI);
+		if difference > PI {
+			difference -= 2.0 * PI;
+		}
+		if difference < -PI {
+			difference += 2.0 * PI;
+		}
+		difference.abs() <= cone.1
+	}
+
+	fn slope(depth: usize, col: isize) -> f32 {
+		(2 * col - 1) as f32 / (2 * depth) as f32
+	}
+}
+
+/// Tiles the player has seen at some point
+pub struct ExploredTiles {
+	explored: Vec2D<bool>,
+}
+
+impl ExploredTiles {
+	pub fn new(width: usize, height: usize) -> Self {
+		ExploredTiles {
+			explored: Vec2D::from_vec(width, height, vec![false; width * height]),
+		}
+	}
+
+	pub fn reveal(&mut self, fov: &FieldOfView) {
+		let (x0, y0, x1, y1) = fov.bounds();
+		for y in y0..y1 {
+			for x in x0..x1 {
+				if fov.is_visible(x, y) {
+					self.explored[(x, y)] = true;
+				}
+			}
+		}
+	}
+
+	pub fn is_explored(&self, x: usize, y: usize) -> bool {
+		self.explored[(x, y)]
+	}
+
+	pub fn tiles(&self) -> &Vec2D<bool> {
+		&self.explored
+	}
+}
+
+/// Dark quads over the map: black where nothing was seen, dimmed where nothing is seen right now
+pub struct FogRenderer {
+	vertices: VertexArray,
+	width: usize,
+	dirty: (usize, usize, usize, usize),		// area covered by the last field of view
+}
+
+const UNEXPLORED: u8 = 255;
+const REMEMBERED: u8 = 150;
+
+impl FogRenderer {
+	pub fn new(width: usize, height: usize) -> Self {
+		let mut vertices = VertexArray::new_init(Quads, (width * height) as u32 * 4).expect("Could not create VertexArray");
+		for i in 0..(width * height) {
+			let px = (i % width) as f32;
+			let py = (i / width) as f32;
+			let j = i as u32;
+			vertices.get_vertex(j * 4).0.position = Vector2f::new(px, py);
+			vertices.get_vertex(j * 4 + 1).0.position = Vector2f::new(px + 1.0, py);
+			vertices.get_vertex(j * 4 + 2).0.position = Vector2f::new(px + 1.0, py + 1.0);
+			vertices.get_vertex(j * 4 + 3).0.position = Vector2f::new(px, py + 1.0);
+			for k in 0..4 {
+				vertices.get_vertex(j * 4 + k).0.color = Color::new_rgba(0, 0, 0, UNEXPLORED);
+			}
+		}
+		FogRenderer {
+			vertices: vertices,
+			width: width,
+			dirty: (0, 0, 0, 0),
+		}
+	}
+
+	/// Only touches the tiles of the previous and the current field of view
+	pub fn update(&mut self, fov: &FieldOfView, explored: &ExploredTiles) {
+		let (a, b) = (self.dirty, fov.bounds());
+		let area = (a.0.min(b.0), a.1.min(b.1), a.2.max(b.2), a.3.max(b.3));
+		for y in area.1..area.3 {
+			for x in area.0..area.2 {
+				let alpha = if fov.is_visible(x, y) {
+					0
+				}
+				else if explored.is_explored(x, y) {
+					REMEMBERED
+				}
+				else {
+					UNEXPLORED
+				};
+				let j = (y * self.width + x) as u32;
+				for k in 0..4 {
+					self.vertices.get_vertex(j * 4 + k).0.color = Color::new_rgba(0, 0, 0, alpha);
+				}
+			}
+		}
+		self.dirty = b;
+	}
+}
+
+impl Drawable for FogRenderer {
+	fn draw<RT: RenderTarget>(&self, target: &mut RT, _: &mut RenderStates) {
+		let mut rs = RenderStates::default();
+		target.draw_vertex_array(&self.vertices, &mut rs);
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use game::map::CollisionLayer;
+	use game::map_generator::{Grass, Building, Forest};
+
+	/// '.' grass, '#' building, 'f' forest
+	fn map_from(rows: &[&str]) -> Map {
+		let tiles: Vec<u32> = rows.iter().flat_map(|row| row.chars()).map(|c| match c {
+			'#' => Building,
+			'f' => Forest,
+			_ => Grass,
+		}).collect();
+		let size = tiles.len();
+		Map::new_init(rows[0].len(), rows.len(), tiles, vec![0; size], vec![0; size])
+	}
+
+	fn open_map(width: usize, height: usize) -> Map {
+		Map::new_init(width, height, vec![Grass; width * height], vec![0; width * height], vec![0; width * height])
+	}
+
+	fn center(x: usize, y: usize) -> Vector2f {
+		Vector2f::new(x as f32 + 0.5, y as f32 + 0.5)
+	}
+
+	#[test]
+	fn open_map_sees_the_whole_radius() {
+		let map = open_map(21, 21);
+		let mut fov = FieldOfView::new(&map, 5.0);
+		fov.compute(&map, center(10, 10));
+		for y in 0..21 {
+			for x in 0..21 {
+				let (dx, dy) = (x as isize - 10, y as isize - 10);
+				assert_eq!(fov.is_visible(x, y), dx * dx + dy * dy <= 25, "tile {:?}", (x, y));
+			}
+		}
+		fov.compute(&map, Vector2f::new(-1.0, 3.0));
+		assert!(!fov.is_visible(10, 10));
+		assert_eq!(fov.bounds(), (0, 0, 0, 0));
+	}
+
+	#[test]
+	fn walls_shadow_the_tiles_behind_them() {
+		// Wall between the second and third tile from the observer at (5, 5) in each quadrant,
+		// the tiles on the near side, the far side and two further behind
+		let cases: [(fn(&mut CollisionLayer), (usize, usize), (usize, usize), (usize, usize)); 4] = [
+			(|walls| walls.set_collision_top(5, 4), (5, 4), (5, 3), (5, 2)),
+			(|walls| walls.set_collision_right(6, 5), (6, 5), (7, 5), (8, 5)),
+			(|walls| walls.set_collision_bottom(5, 6), (5, 6), (5, 7), (5, 8)),
+			(|walls| walls.set_collision_left(4, 5), (4, 5), (3, 5), (2, 5)),
+		];
+		for &(place, near, far, behind) in cases.iter() {
+			let mut map = open_map(11, 11);
+			let mut walls = CollisionLayer::new(11, 11);
+			place(&mut walls);
+			map.set_walls(walls);
+			let mut fov = FieldOfView::new(&map, 8.0);
+			fov.compute(&map, center(5, 5));
+			assert!(fov.is_visible(near.0, near.1), "near tile {:?}", near);
+			assert!(!fov.is_visible(far.0, far.1), "far tile {:?}", far);
+			assert!(!fov.is_visible(behind.0, behind.1), "tile behind {:?}", behind);
+		}
+	}
+
+	#[test]
+	fn sight_blocking_tiles_are_visible_but_hide_what_is_behind() {
+		let map = map_from(&[
+			".......",
+			"...#...",
+			".......",
+			"...f...",
+			".......",
+		]);
+		let mut fov = FieldOfView::new(&map, 6.0);
+		fov.compute(&map, center(3, 2));
+		assert!(fov.is_visible(3, 1) && fov.is_visible(3, 3));
+		assert!(!fov.is_visible(3, 0) && !fov.is_visible(3, 4));
+		assert!(fov.is_visible(0, 0) && fov.is_visible(6, 4));
+	}
+
+	#[test]
+	fn visibility_is_symmetric() {
+		let rows = [
+			"............",
+			"..#....f....",
+			"..#.........",
+			"......ff....",
+			"....#.......",
+			"..........#.",
+			".f..........",
+			"....##......",
+			"...........f",
+			"..f.....#...",
+		];
+		let map = map_from(&rows);
+		let (width, height) = (map.width(), map.height());
+		let open: Vec<(usize, usize)> = (0..height).flat_map(|y| (0..width).map(move |x| (x, y)))
+			.filter(|&(x, y)| !map.blocks_sight(x, y)).collect();
+		let mut fov = FieldOfView::new(&map, 7.5);
+		let seen: Vec<Vec<bool>> = open.iter().map(|&(x, y)| {
+			fov.compute(&map, center(x, y));
+			open.iter().map(|&(bx, by)| fov.is_visible(bx, by)).collect()
+		}).collect();
+		for a in 0..open.len() {
+			for b in 0..open.len() {
+				assert_eq!(seen[a][b], seen[b][a], "{:?} and {:?}", open[a], open[b]);
+			}
+		}
+	}
+
+	#[test]
+	fn cone_wraps_around_pi() {
+		let map = open_map(11, 11);
+		let mut fov = FieldOfView::new(&map, 8.0);
+		// Facing west slightly below the x axis, a quarter turn wide
+		fov.set_cone(PI - 0.1, PI / 2.0);
+		fov.compute(&map, center(5, 5));
+		assert!(fov.is_visible(5, 5));
+		assert!(fov.is_visible(2, 5));
+		assert!(fov.is_visible(2, 4), "above the axis, angle near -pi");
+		assert!(fov.is_visible(2, 6), "below the axis, angle near pi");
+		assert!(!fov.is_visible(8, 5));
+		assert!(!fov.is_visible(5, 2) && !fov.is_visible(5, 8));
+	}
+
+	#[test]
+	fn explored_tiles_stay_after_moving() {
+		let map = open_map(30, 10);
+		let mut fov = FieldOfView::new(&map, 3.0);
+		let mut explored = ExploredTiles::new(30, 10);
+		fov.compute(&map, center(2, 5));
+		explored.reveal(&fov);
+		fov.compute(&map, center(25, 5));
+		explored.reveal(&fov);
+		assert!(explored.is_explored(2, 5) && !fov.is_visible(2, 5));
+		assert!(explored.is_explored(25, 5) && fov.is_visible(25, 5));
+		assert!(!explored.is_explored(15, 5));
+	}
+}
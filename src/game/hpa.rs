@@ -0,0 +1,375 @@
+use std::collections::{BinaryHeap, HashMap};
+use std::f32;
+use game::map::Map;
+use game::pathfinding::{self, MovementProfile, Path, Candidate};
+use util::Vec2D;
+
+const SQRT_2: f32 = 1.41421356;
+const MAX_SINGLE_TRANSITION: usize = 6;		// longer openings get a transition at both ends
+
+/// Square of tiles, searched tile by tile only on its own
+struct Cluster {
+	bounds: (usize, usize, usize, usize),		// x0, y0, x1, y1 exclusive
+	edges: HashMap<(usize, usize), Vec<((usize, usize), f32)>>,		// cheapest way inside the cluster from every entrance to the others
+}
+
+/// HPA*: the map split into clusters, with entrances where clusters can be crossed into each other.
+/// Routes are searched over the entrances first and only turned into tiles a stretch at a time
+pub struct PathHierarchy {
+	pub profile: MovementProfile,
+	cluster_size: usize,
+	columns: usize,
+	rows: usize,
+	clusters: Vec<Cluster>,
+	borders: HashMap<(usize, usize), Vec<((usize, usize), (usize, usize))>>,		// right and lower neighbour cluster, pairs of tiles a step apart
+}
+
+impl PathHierarchy {
+	pub fn new(map: &Map, profile: MovementProfile, cluster_size: usize) -> Self {
+		let cluster_size = cluster_size.max(2);
+		let columns = (map.width() + cluster_size - 1) / cluster_size;
+		let rows = (map.height() + cluster_size - 1) / cluster_size;
+		let mut clusters = Vec::with_capacity(columns * rows);
+		for cy in 0..rows {
+			for cx in 0..columns {
+				clusters.push(Cluster {
+					bounds: (cx * cluster_size, cy * cluster_size, ((cx + 1) * cluster_size).min(map.width()), ((cy + 1) * cluster_size).min(map.height())),
+					edges: HashMap::new(),
+				});
+			}
+		}
+		let mut hierarchy = PathHierarchy {
+			profile: profile,
+			cluster_size: cluster_size,
+			columns: columns,
+			rows: rows,
+			clusters: clusters,
+			borders: HashMap::new(),
+		};
+		for i in 0..hierarchy.clusters.len() {
+			hierarchy.build_borders(map, i);
+		}
+		for i in 0..hierarchy.clusters.len() {
+			hierarchy.build_edges(map, i);
+		}
+		hierarchy
+	}
+
+	pub fn cluster_size(&self) -> usize {
+		self.cluster_size
+	}
+
+	/// Number of entrances over all clusters
+	pub fn entrance_count(&self) -> usize {
+		self.clusters.iter().map(|cluster| cluster.edges.len()).sum()
+	}
+
+	/// Call after tiles, walls or doors in the area changed, only the clusters around it are rebuilt
+	pub fn update_area(&mut self, map: &Map, x: usize, y: usize, width: usize, height: usize) {
+		let (cx0, cy0) = (x.saturating_sub(1) / self.cluster_size, y.saturating_sub(1) / self.cluster_size);
+		let cx1 = ((x + width) / self.cluster_size).min(self.columns - 1);
+		let cy1 = ((y + height) / self.cluster_size).min(self.rows - 1);
+		for cy in cy0..(cy1 + 1) {
+			for cx in cx0..(cx1 + 1) {
+				self.build_borders(map, cy * self.columns + cx);
+				if cx > 0 {
+					self.build_borders(map, cy * self.columns + cx - 1);
+				}
+				if cy > 0 {
+					self.build_borders(map, (cy - 1) * self.columns + cx);
+				}
+			}
+		}
+		// Neighbours share the rebuilt borders, so their entrances may have changed too
+		for cy in cy0.saturating_sub(1)..(cy1 + 2).min(self.rows) {
+			for cx in cx0.saturating_sub(1)..(cx1 + 2).min(self.columns) {
+				self.build_edges(map, cy * self.columns + cx);
+			}
+		}
+	}
+
+	/// Route over the entrances, None if the goal can not be reached
+	pub fn find_path(&self, map: &Map, start: (usize, usize), goal: (usize, usize)) -> Option<HierarchicalPath> {
+		if start.0 >= map.width() || start.1 >= map.height() || goal.0 >= map.width() || goal.1 >= map.height() || self.profile.tile_cost(map, goal.0, goal.1).is_none() {
+			return None;
+		}
+		let (start_cluster, goal_cluster) = (self.cluster_of(start), self.cluster_of(goal));
+		let start_bounds = self.clusters[start_cluster].bounds;
+		let goal_bounds = self.clusters[goal_cluster].bounds;
+		let from_start = Self::local_costs(map, &self.profile, start, start_bounds, false);
+		let to_goal = Self::local_costs(map, &self.profile, goal, goal_bounds, true);
+		let start_edges: Vec<((usize, usize), f32)> = self.clusters[start_cluster].edges.keys().map(|&entrance| (entrance, from_start[Self::local(entrance, start_bounds)])).filter(|&(_, cost)| cost.is_finite()).collect();
+		let scale = self.profile.min_cost(map);
+		let mut costs: HashMap<(usize, usize), f32> = HashMap::new();
+		let mut came_from: HashMap<(usize, usize), (usize, usize)> = HashMap::new();
+		let mut open = BinaryHeap::new();
+		costs.insert(start, 0.0);
+		open.push(Candidate {
+			cost: pathfinding::heuristic(start, goal, &self.profile) * scale,
+			tile: start,
+		});
+		while let Some(Candidate { cost, tile }) = open.pop() {
+			let tile_cost = costs[&tile];
+			if tile == goal {
+				let mut waypoints = vec![goal];
+				let mut current = goal;
+				while let Some(&previous) = came_from.get(&current) {
+					waypoints.push(previous);
+					current = previous;
+				}
+				waypoints.reverse();
+				return Some(HierarchicalPath {
+					waypoints: waypoints,
+					cost: tile_cost,
+					next: 0,
+				});
+			}
+			if cost > tile_cost + pathfinding::heuristic(tile, goal, &self.profile) * scale + 0.0001 {
+				continue;
+			}
+			let mut next: Vec<((usize, usize), f32)> = Vec::new();
+			if tile == start {
+				next.extend(start_edges.iter().cloned());
+			}
+			next.extend(self.entrance_neighbours(map, tile));
+			if self.cluster_of(tile) == goal_cluster {
+				let to_goal = to_goal[Self::local(tile, goal_bounds)];
+				if to_goal.is_finite() {
+					next.push((goal, to_goal));
+				}
+			}
+			for (neighbour, step) in next {
+				let new_cost = tile_cost + step;
+				if costs.get(&neighbour).map(|&c| new_cost < c).unwrap_or(true) {
+					costs.insert(neighbour, new_cost);
+					came_from.insert(neighbour, tile);
+					open.push(Candidate {
+						cost: new_cost + pathfinding::heuristic(neighbour, goal, &self.profile) * scale,
+						tile: neighbour,
+					});
+				}
+			}
+		}
+		None
+	}
+
+	fn cluster_of(&self, tile: (usize, usize)) -> usize {
+		(tile.1 / self.cluster_size) * self.columns + tile.0 / self.cluster_size
+	}
+
+	fn cluster_bounds(&self, tile: (usize, usize)) -> (usize, usize, usize, usize) {
+		self.clusters[self.cluster_of(tile)].bounds
+	}
+
+	/// Other entrances of the same cluster and the tiles across the border
+	fn entrance_neighbours(&self, map: &Map, tile: (usize, usize)) -> Vec<((usize, usize), f32)> {
+		let cluster = self.cluster_of(tile);
+		let mut result = match self.clusters[cluster].edges.get(&tile) {
+			Some(edges) => edges.clone(),
+			None => return Vec::new(),
+		};
+		let (cx, cy) = (cluster % self.columns, cluster / self.columns);
+		let mut keys = Vec::with_capacity(4);
+		if cx + 1 < self.columns {
+			keys.push((cluster, cluster + 1));
+		}
+		if cy + 1 < self.rows {
+			keys.push((cluster, cluster + self.columns));
+		}
+		if cx > 0 {
+			keys.push((cluster - 1, cluster));
+		}
+		if cy > 0 {
+			keys.push((cluster - self.columns, cluster));
+		}
+		for key in keys {
+			for &(a, b) in self.borders.get(&key).map(|pairs| &pairs[..]).unwrap_or(&[]) {
+				let other = if a == tile { b } else if b == tile { a } else { continue };
+				if let Some(cost) = self.profile.tile_cost(map, other.0, other.1) {
+					result.push((other, cost));
+				}
+			}
+		}
+		result
+	}
+
+	/// Transitions to the right and lower neighbour, one in the middle of short openings and one
+	/// at each end of long ones
+	fn build_borders(&mut self, map: &Map, cluster: usize) {
+		let (x0, y0, x1, y1) = self.clusters[cluster].bounds;
+		let (cx, cy) = (cluster % self.columns, cluster / self.columns);
+		if cx + 1 < self.columns {
+			let crossings: Vec<((usize, usize), (usize, usize))> = (y0..y1).map(|y| ((x1 - 1, y), (x1, y))).collect();
+			let transitions = self.transitions(map, &crossings, 1, 0);
+			self.borders.insert((cluster, cluster + 1), transitions);
+		}
+		if cy + 1 < self.rows {
+			let crossings: Vec<((usize, usize), (usize, usize))> = (x0..x1).map(|x| ((x, y1 - 1), (x, y1))).collect();
+			let transitions = self.transitions(map, &crossings, 0, 1);
+			self.borders.insert((cluster, cluster + self.columns), transitions);
+		}
+	}
+
+	fn transitions(&self, map: &Map, crossings: &[((usize, usize), (usize, usize))], dx: isize, dy: isize) -> Vec<((usize, usize), (usize, usize))> {
+		let mut transitions = Vec::new();
+		let mut run: Vec<((usize, usize), (usize, usize))> = Vec::new();
+		for (i, &(a, b)) in crossings.iter().enumerate() {
+			let open = pathfinding::orthogonal_step(map, a.0, a.1, dx, dy, &self.profile).is_some() && pathfinding::orthogonal_step(map, b.0, b.1, -dx, -dy, &self.profile).is_some();
+			if open {
+				run.push((a, b));
+			}
+			if (!open || i + 1 == crossings.len()) && !run.is_empty() {
+				if run.len() < MAX_SINGLE_TRANSITION {
+					transitions.push(run[run.len() / 2]);
+				}
+				else {
+					transitions.push(run[0]);
+					transitions.push(run[run.len() - 1]);
+				}
+				run.clear();
+			}
+		}
+		transitions
+	}
+
+	/// Entrances are the cluster's own tiles of the transitions on its four borders
+	fn build_edges(&mut self, map: &Map, cluster: usize) {
+		let (cx, cy) = (cluster % self.columns, cluster / self.columns);
+		let mut entrances: Vec<(usize, usize)> = Vec::new();
+		{
+			let mut add = |key: (usize, usize), own_first: bool| {
+				for &(a, b) in self.borders.get(&key).map(|pairs| &pairs[..]).unwrap_or(&[]) {
+					let tile = if own_first { a } else { b };
+					if !entrances.contains(&tile) {
+						entrances.push(tile);
+					}
+				}
+			};
+			if cx + 1 < self.columns {
+				add((cluster, cluster + 1), true);
+			}
+			if cy + 1 < self.rows {
+				add((cluster, cluster + self.columns), true);
+			}
+			if cx > 0 {
+				add((cluster - 1, cluster), false);
+			}
+			if cy > 0 {
+				add((cluster - self.columns, cluster), false);
+			}
+		}
+		let bounds = self.clusters[cluster].bounds;
+		let mut edges = HashMap::with_capacity(entrances.len());
+		for &entrance in entrances.iter() {
+			let costs = Self::local_costs(map, &self.profile, entrance, bounds, false);
+			let reachable = entrances.iter().filter(|&&other| other != entrance).map(|&other| (other, costs[Self::local(other, bounds)])).filter(|&(_, cost)| cost.is_finite()).collect();
+			edges.insert(entrance, reachable);
+		}
+		self.clusters[cluster].edges = edges;
+	}
+
+	/// Dijkstra inside the bounds, from the tile or with reverse to the tile
+	fn local_costs(map: &Map, profile: &MovementProfile, tile: (usize, usize), bounds: (usize, usize, usize, usize), reverse: bool) -> Vec2D<f32> {
+		let (x0, y0, x1, y1) = bounds;
+		let (width, height) = (x1 - x0, y1 - y0);
+		let mut costs: Vec2D<f32> = Vec2D::from_vec(width, height, vec![f32::INFINITY; width * height]);
+		let mut open = BinaryHeap::new();
+		costs[Self::local(tile, bounds)] = 0.0;
+		open.push(Candidate {
+			cost: 0.0,
+			tile: tile,
+		});
+		while let Some(Candidate { cost, tile }) = open.pop() {
+			if cost > costs[Self::local(tile, bounds)] {
+				continue;
+			}
+			let enter = profile.tile_cost(map, tile.0, tile.1).unwrap_or(f32::INFINITY);
+			for (next, step) in pathfinding::neighbours(map, tile.0, tile.1, profile) {
+				if next.0 < x0 || next.1 < y0 || next.0 >= x1 || next.1 >= y1 {
+					continue;
+				}
+				// Backwards the step goes onto this tile, so this tile's cost counts
+				let step = if reverse {
+					if next.0 != tile.0 && next.1 != tile.1 { enter * SQRT_2 } else { enter }
+				}
+				else {
+					step
+				};
+				let new_cost = cost + step;
+				if new_cost < costs[Self::local(next, bounds)] {
+					costs[Self::local(next, bounds)] = new_cost;
+					open.push(Candidate {
+						cost: new_cost,
+						tile: next,
+					});
+				}
+			}
+		}
+		costs
+	}
+
+	fn local(tile: (usize, usize), bounds: (usize, usize, usize, usize)) -> (usize, usize) {
+		(tile.0 - bounds.0, tile.1 - bounds.1)
+	}
+}
+
+/// Waypoints of a route through the hierarchy, turned into tiles one stretch at a time
+pub struct HierarchicalPath {
+	waypoints: Vec<(usize, usize)>,
+	cost: f32,
+	next: usize,		// first waypoint of the next stretch
+}
+
+impl HierarchicalPath {
+	pub fn waypoints(&self) -> &[(usize, usize)] {
+		&self.waypoints
+	}
+
+	pub fn cost(&self) -> f32 {
+		self.cost
+	}
+
+	pub fn is_finished(&self) -> bool {
+		self.next + 1 >= self.waypoints.len()
+	}
+
+	/// Tiles up to the next waypoint, without the one standing on. None at the end, or if the
+	/// stretch got blocked since the route was found, then a new route is needed
+	pub fn next_segment(&mut self, map: &Map, hierarchy: &PathHierarchy) -> Option<Vec<(usize, usize)>> {
+		if self.is_finished() {
+			return None;
+		}
+		let (from, to) = (self.waypoints[self.next], self.waypoints[self.next + 1]);
+		let crossing = hierarchy.cluster_of(from) != hierarchy.cluster_of(to);
+		let tiles = if crossing {
+			let (dx, dy) = (to.0 as isize - from.0 as isize, to.1 as isize - from.1 as isize);
+			match pathfinding::orthogonal_step(map, from.0, from.1, dx, dy, &hierarchy.profile) {
+				Some(_) => vec![to],
+				None => return None,
+			}
+		}
+		else {
+			match pathfinding::find_path_within(map, from, to, &hierarchy.profile, hierarchy.cluster_bounds(from)) {
+				Some(path) => path.tiles[1..].to_vec(),
+				None => return None,
+			}
+		};
+		self.next += 1;
+		Some(tiles)
+	}
+
+	/// The whole tile path at once
+	pub fn refine(mut self, map: &Map, hierarchy: &PathHierarchy) -> Option<Path> {
+		let mut tiles = vec![self.waypoints[0]];
+		while !self.is_finished() {
+			match self.next_segment(map, hierarchy) {
+				Some(segment) => tiles.extend(segment),
+				None => return None,
+			}
+		}
+		Some(Path {
+			tiles: tiles,
+			cost: self.cost,
+		})
+	}
+}
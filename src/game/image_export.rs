@@ -0,0 +1,359 @@
+use image::{self, Rgba, RgbaImage};
+use game::map::{Map, CollisionLayer};
+use game::map_generator::{Noise, Road, Building};
+use game::objects::ObjectLayer;
+use game::tileset::Tileset;
+use util::Vec2D;
+
+/// Maps values in 0..1 to colours by interpolating between stops
+pub struct ColorRamp {
+	stops: Vec<(f32, (u8, u8, u8))>,
+}
+
+pub const RAMP_NAMES: &'static [&'static str] = &["grey", "terrain", "heat"];
+
+impl ColorRamp {
+	pub fn new(mut stops: Vec<(f32, (u8, u8, u8))>) -> Self {
+		stops.sort_by(|a, b| a.0.partial_cmp(&b.0).expect("Ramp stops must not be NaN"));
+		ColorRamp {
+			stops: stops,
+		}
+	}
+
+	pub fn new_grey() -> Self {
+		ColorRamp::new(vec![(0.0, (0, 0, 0)), (1.0, (255, 255, 255))])
+	}
+
+	/// Same bands as the terrain classification
+	pub fn new_terrain() -> Self {
+		ColorRamp::new(vec![
+			(0.0, (235, 240, 245)),
+			(0.15, (160, 160, 150)),
+			(0.3, (78, 154, 52)),
+			(0.55, (28, 90, 36)),
+			(0.65, (82, 160, 200)),
+			(1.0, (24, 56, 140)),
+		])
+	}
+
+	pub fn new_heat() -> Self {
+		ColorRamp::new(vec![(0.0, (0, 0, 64)), (0.35, (160, 0, 160)), (0.7, (255, 128, 0)), (1.0, (255, 255, 200))])
+	}
+
+	pub fn from_name(name: &str) -> Option<Self> {
+		match name {
+			"grey" => Some(ColorRamp::new_grey()),
+			"terrain" => Some(ColorRamp::new_terrain()),
+			"heat" => Some(ColorRamp::new_heat()),
+			_ => None,
+		}
+	}
+
+	pub fn sample(&self, value: f32) -> (u8, u8, u8) {
+		let first = self.stops[0];
+		if value <= first.0 {
+			return first.1;
+		}
+		for pair in self.stops.windows(2) {
+			let ((v0, c0), (v1, c1)) = (pair[0], pair[1]);
+			if value <= v1 {
+				let alpha = (value - v0) / (v1 - v0);
+				let mix = |a: u8, b: u8| Noise::interpolate(a as f32, b as f32, alpha) as u8;
+				return (mix(c0.0, c1.0), mix(c0.1, c1.1), mix(c0.2, c1.2));
+			}
+		}
+		self.stops[self.stops.len() - 1].1
+	}
+}
+
+/// Software rasterizer that draws maps and noise into images, no window needed
+pub struct ImageExporter {
+	pub pixels_per_tile: u32,
+	tileset: Tileset,
+	tileset_image: Option<RgbaImage>,
+}
+
+impl ImageExporter {
+	pub fn new(pixels_per_tile: u32) -> Self {
+		ImageExporter {
+			pixels_per_tile: pixels_per_tile.max(1),
+			tileset: Tileset::new_default(),
+			tileset_image: None,
+		}
+	}
+
+	/// Draw tiles from the real tileset image instead of flat colours
+	pub fn load_tileset_image(&mut self) {
+		let image = image::open(self.tileset.image_path()).expect("Could not load tileset");
+		self.tileset_image = Some(image.to_rgba8());
+	}
+
+	pub fn render_tiles(&self, tiles: &Vec2D<u32>) -> RgbaImage {
+		let ppt = self.pixels_per_tile;
+		let mut image = RgbaImage::new(tiles.width() as u32 * ppt, tiles.height() as u32 * ppt);
+		for y in 0..tiles.height() {
+			for x in 0..tiles.width() {
+				let tile = tiles[(x, y)];
+				for py in 0..ppt {
+					for px in 0..ppt {
+						let color = self.tile_pixel(tile, px, py);
+						image.put_pixel(x as u32 * ppt + px, y as u32 * ppt + py, color);
+					}
+				}
+			}
+		}
+		image
+	}
+
+	pub fn render_map(&self, map: &Map) -> RgbaImage {
+		self.render_tiles(map.get_layer(0).tiles())
+	}
+
+	pub fn render_noise(&self, noise: &Noise, ramp: &ColorRamp) -> RgbaImage {
+		let ppt = self.pixels_per_tile;
+		let mut image = RgbaImage::new(noise.width() as u32 * ppt, noise.height() as u32 * ppt);
+		for y in 0..noise.height() {
+			for x in 0..noise.width() {
+				let (r, g, b) = ramp.sample(noise[(x, y)]);
+				self.fill_tile(&mut image, x, y, Rgba([r, g, b, 255]), 1.0);
+			}
+		}
+		image
+	}
+
+	pub fn overlay_roads(&self, image: &mut RgbaImage, tiles: &Vec2D<u32>) {
+		for y in 0..tiles.height() {
+			for x in 0..tiles.width() {
+				if tiles[(x, y)] == Road {
+					self.fill_tile(image, x, y, Rgba([255, 220, 0, 255]), 0.6);
+				}
+			}
+		}
+	}
+
+	/// Outlines every group of building tiles
+	pub fn overlay_buildings(&self, image: &mut RgbaImage, tiles: &Vec2D<u32>) {
+		let is_building = |x: isize, y: isize| {
+			x >= 0 && y >= 0 && (x as usize) < tiles.width() && (y as usize) < tiles.height() && tiles[(x as usize, y as usize)] == Building
+		};
+		let color = Rgba([255, 40, 40, 255]);
+		let last = self.pixels_per_tile - 1;
+		for y in 0..tiles.height() {
+			for x in 0..tiles.width() {
+				let (ix, iy) = (x as isize, y as isize);
+				if !is_building(ix, iy) {
+					continue;
+				}
+				if !is_building(ix, iy - 1) {
+					self.draw_line(image, x, y, (0, 0), (last, 0), color);
+				}
+				if !is_building(ix, iy + 1) {
+					self.draw_line(image, x, y, (0, last), (last, last), color);
+				}
+				if !is_building(ix - 1, iy) {
+					self.draw_line(image, x, y, (0, 0), (0, last), color);
+				}
+				if !is_building(ix + 1, iy) {
+					self.draw_line(image, x, y, (last, 0), (last, last), color);
+				}
+			}
+		}
+	}
+
+	pub fn overlay_collision(&self, image: &mut RgbaImage, collision: &CollisionLayer) {
+		let color = Rgba([255, 0, 255, 255]);
+		let ppt = self.pixels_per_tile;
+		let vertical = collision.vertical_edges();
+		for y in 0..vertical.height() {
+			for x in 0..vertical.width() {
+				if vertical[(x, y)] {
+					let px = (x as u32 * ppt).min(image.width() - 1);
+					for py in (y as u32 * ppt)..((y as u32 + 1) * ppt) {
+						image.put_pixel(px, py, color);
+					}
+				}
+			}
+		}
+		let horizontal = collision.horizontal_edges();
+		for y in 0..horizontal.height() {
+			for x in 0..horizontal.width() {
+				if horizontal[(x, y)] {
+					let py = (y as u32 * ppt).min(image.height() - 1);
+					for px in (x as u32 * ppt)..((x as u32 + 1) * ppt) {
+						image.put_pixel(px, py, color);
+					}
+				}
+			}
+		}
+	}
+
+	pub fn overlay_objects(&self, image: &mut RgbaImage, objects: &ObjectLayer) {
+		let ppt = self.pixels_per_tile as f32;
+		for object in objects.iter() {
+			let r = object.radius() * ppt;
+			let (cx, cy) = (object.x * ppt, object.y * ppt);
+			let color = if object.kind.blocks_movement() { Rgba([20, 20, 20, 255]) } else { Rgba([200, 200, 200, 255]) };
+			let x0 = (cx - r).max(0f32) as u32;
+			let y0 = (cy - r).max(0f32) as u32;
+			let x1 = ((cx + r) as u32).min(image.width() - 1);
+			let y1 = ((cy + r) as u32).min(image.height() - 1);
+			for py in y0..(y1 + 1) {
+				for px in x0..(x1 + 1) {
+					let dx = px as f32 + 0.5 - cx;
+					let dy = py as f32 + 0.5 - cy;
+					if dx * dx + dy * dy <= r * r {
+						image.put_pixel(px, py, color);
+					}
+				}
+			}
+		}
+	}
+
+	pub fn save(image: &RgbaImage, path: &str) {
+		image.save(path).expect("Could not write image!");
+	}
+
+	fn tile_pixel(&self, tile: u32, px: u32, py: u32) -> Rgba<u8> {
+		match self.tileset_image {
+			Some(ref tileset_image) if (tile as usize) < self.tileset.len() => {
+				let size = self.tileset.tile_size();
+				let (tx, ty) = self.tileset.image_position(tile);
+				let sx = tx + px * size / self.pixels_per_tile;
+				let sy = ty + py * size / self.pixels_per_tile;
+				*tileset_image.get_pixel(sx.min(tileset_image.width() - 1), sy.min(tileset_image.height() - 1))
+			},
+			_ => {
+				let (r, g, b) = self.tileset.color(tile);
+				Rgba([r, g, b, 255])
+			},
+		}
+	}
+
+	fn fill_tile(&self, image: &mut RgbaImage, x: usize, y: usize, color: Rgba<u8>, alpha: f32) {
+		let ppt = self.pixels_per_tile;
+		for py in (y as u32 * ppt)..((y as u32 + 1) * ppt) {
+			for px in (x as u32 * ppt)..((x as u32 + 1) * ppt) {
+				let blended = Self::blend(*image.get_pixel(px, py), color, alpha);
+				image.put_pixel(px, py, blended);
+			}
+		}
+	}
+
+	/// Straight line inside one tile, from and to are pixel offsets in the tile
+	fn draw_line(&self, image: &mut RgbaImage, x: usize, y: usize, from: (u32, u32), to: (u32, u32), color: Rgba<u8>) {
+		let ppt = self.pixels_per_tile;
+		let (ox, oy) = (x as u32 * ppt, y as u32 * ppt);
+		for py in from.1..(to.1 + 1) {
+			for px in from.0..(to.0 + 1) {
+				image.put_pixel(ox + px, oy + py, color);
+			}
+		}
+	}
+
+	fn blend(under: Rgba<u8>, over: Rgba<u8>, alpha: f32) -> Rgba<u8> {
+		let mix = |a: u8, b: u8| Noise::interpolate(a as f32, b as f32, alpha) as u8;
+		Rgba([mix(under[0], over[0]), mix(under[1], over[1]), mix(under[2], over[2]), 255])
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use game::map_generator::{Grass, ShallowWater};
+
+	fn color(tile: u32) -> Rgba<u8> {
+		let (r, g, b) = Tileset::new_default().color(tile);
+		Rgba([r, g, b, 255])
+	}
+
+	fn small_map() -> Vec2D<u32> {
+		Vec2D::from_vec(3, 2, vec![
+			Grass, Road, Building,
+			ShallowWater, Road, Building,
+		])
+	}
+
+	#[test]
+	fn one_pixel_per_tile() {
+		let image = ImageExporter::new(1).render_tiles(&small_map());
+		assert_eq!(image.dimensions(), (3, 2));
+		assert_eq!(*image.get_pixel(0, 0), color(Grass));
+		assert_eq!(*image.get_pixel(1, 1), color(Road));
+		assert_eq!(*image.get_pixel(0, 1), color(ShallowWater));
+	}
+
+	#[test]
+	fn tiles_fill_their_whole_square() {
+		let image = ImageExporter::new(4).render_tiles(&small_map());
+		assert_eq!(image.dimensions(), (12, 8));
+		for py in 0..4 {
+			for px in 8..12 {
+				assert_eq!(*image.get_pixel(px, py), color(Building));
+			}
+		}
+		assert_eq!(*image.get_pixel(3, 7), color(ShallowWater));
+	}
+
+	#[test]
+	fn zero_pixels_per_tile_is_raised_to_one() {
+		assert_eq!(ImageExporter::new(0).render_tiles(&small_map()).dimensions(), (3, 2));
+	}
+
+	#[test]
+	fn collision_is_drawn_on_the_edges() {
+		let exporter = ImageExporter::new(4);
+		let mut image = exporter.render_tiles(&small_map());
+		let mut collision = CollisionLayer::new(3, 2);
+		collision.set_collision_right(0, 0);
+		collision.set_collision_bottom(2, 1);
+		exporter.overlay_collision(&mut image, &collision);
+		let wall = Rgba([255, 0, 255, 255]);
+		for py in 0..4 {
+			assert_eq!(*image.get_pixel(4, py), wall);
+		}
+		assert_eq!(*image.get_pixel(3, 4), color(ShallowWater));
+		assert_eq!(*image.get_pixel(4, 4), color(Road));
+		// The bottom edge of the map is clamped onto the last pixel row
+		for px in 8..12 {
+			assert_eq!(*image.get_pixel(px, 7), wall);
+		}
+	}
+
+	#[test]
+	fn buildings_are_outlined_as_one_group() {
+		let exporter = ImageExporter::new(4);
+		let mut image = exporter.render_tiles(&small_map());
+		exporter.overlay_buildings(&mut image, &small_map());
+		let outline = Rgba([255, 40, 40, 255]);
+		assert_eq!(*image.get_pixel(8, 0), outline);
+		assert_eq!(*image.get_pixel(8, 7), outline);
+		// No line between the two building tiles
+		assert_eq!(*image.get_pixel(10, 3), color(Building));
+		assert_eq!(*image.get_pixel(10, 4), color(Building));
+	}
+
+	#[test]
+	fn roads_are_tinted() {
+		let exporter = ImageExporter::new(1);
+		let mut image = exporter.render_tiles(&small_map());
+		exporter.overlay_roads(&mut image, &small_map());
+		assert!(*image.get_pixel(1, 0) != color(Road));
+		assert_eq!(*image.get_pixel(0, 0), color(Grass));
+	}
+
+	#[test]
+	fn noise_uses_the_ramp() {
+		let noise = Noise::from_vec(2, 1, vec![0.0, 1.0]);
+		let image = ImageExporter::new(2).render_noise(&noise, &ColorRamp::new_grey());
+		assert_eq!(*image.get_pixel(1, 1), Rgba([0, 0, 0, 255]));
+		assert_eq!(*image.get_pixel(2, 0), Rgba([255, 255, 255, 255]));
+	}
+
+	#[test]
+	fn ramp_interpolates_between_stops() {
+		let ramp = ColorRamp::new(vec![(1.0, (200, 0, 0)), (0.0, (0, 0, 100))]);
+		assert_eq!(ramp.sample(-1.0), (0, 0, 100));
+		assert_eq!(ramp.sample(0.5), (100, 0, 50));
+		assert_eq!(ramp.sample(2.0), (200, 0, 0));
+	}
+}
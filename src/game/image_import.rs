@@ -0,0 +1,163 @@
+use image;
+use rustc_serialize::json;
+use std::fs::File;
+use std::io::Read;
+use game::map::Map;
+use game::map_generator::{Noise, TerrainGenerator};
+use game::tileset::Tileset;
+use util::Vec2D;
+
+#[derive(RustcDecodable, RustcEncodable)]
+pub struct PaletteEntry {
+	color: String,		// "#rrggbb"
+	tile: u32,
+}
+
+#[derive(RustcDecodable, RustcEncodable)]
+pub struct PaletteFile {
+	colors: Vec<PaletteEntry>,
+}
+
+/// Maps image colours to tile ids, colours not in the palette get the nearest entry
+pub struct Palette {
+	entries: Vec<((u8, u8, u8), u32)>,
+}
+
+impl Palette {
+	/// Uses the flat tileset colours, i.e. the colours of an exported map
+	pub fn from_tileset(tileset: &Tileset) -> Self {
+		Palette {
+			entries: (0..tileset.len() as u32).map(|id| (tileset.color(id), id)).collect(),
+		}
+	}
+
+	pub fn load(path: &str) -> Self {
+		let mut file = File::open(path).expect("Could not open file!");
+		let mut buf = String::new();
+		file.read_to_string(&mut buf).expect("Could not read file!");
+		let decoded: PaletteFile = json::decode(&buf).expect("Could not decode");
+		Palette {
+			entries: decoded.colors.iter().map(|entry| {
+				let color = parse_color(&entry.color).expect("Invalid palette colour, expected #rrggbb");
+				(color, entry.tile)
+			}).collect(),
+		}
+	}
+
+	pub fn tile_for(&self, color: (u8, u8, u8)) -> u32 {
+		let distance = |c: (u8, u8, u8)| {
+			let dr = c.0 as i32 - color.0 as i32;
+			let dg = c.1 as i32 - color.1 as i32;
+			let db = c.2 as i32 - color.2 as i32;
+			dr * dr + dg * dg + db * db
+		};
+		match self.entries.iter().min_by_key(|&&(c, _)| distance(c)) {
+			Some(&(_, tile)) => tile,
+			None => 0,
+		}
+	}
+}
+
+pub fn parse_color(text: &str) -> Option<(u8, u8, u8)> {
+	if text.len() != 7 || !text.starts_with('#') {
+		return None;
+	}
+	let channel = |i: usize| u8::from_str_radix(&text[i..i + 2], 16).ok();
+	match (channel(1), channel(3), channel(5)) {
+		(Some(r), Some(g), Some(b)) => Some((r, g, b)),
+		_ => None,
+	}
+}
+
+pub struct ImageImporter;
+
+impl ImageImporter {
+	pub fn import_palette(path: &str, palette: &Palette) -> Map {
+		let image = image::open(path).expect("Could not load image").to_rgb8();
+		Self::from_palette_image(&image, palette)
+	}
+
+	/// Every pixel becomes the tile of its palette colour
+	pub fn from_palette_image(image: &image::RgbImage, palette: &Palette) -> Map {
+		let (width, height) = (image.width() as usize, image.height() as usize);
+		let tiles: Vec<u32> = image.pixels().map(|p| palette.tile_for((p[0], p[1], p[2]))).collect();
+		Map::new_init(width, height, tiles, vec![0; width * height], vec![0; width * height])
+	}
+
+	pub fn import_heightmap(path: &str) -> Map {
+		let image = image::open(path).expect("Could not load image").to_luma8();
+		Self::from_heightmap_image(&image)
+	}
+
+	/// Greyscale values from 0 to 1 are classified like generated terrain
+	pub fn from_heightmap_image(image: &image::GrayImage) -> Map {
+		let (width, height) = (image.width() as usize, image.height() as usize);
+		let values: Vec<f32> = image.pixels().map(|p| p[0] as f32 / 255f32).collect();
+		let tiles: Vec2D<u32> = TerrainGenerator::from_noise(&Noise::from_vec(width, height, values));
+		Map::new_init(width, height, tiles.into_vec(), vec![0; width * height], vec![0; width * height])
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use image::{RgbImage, GrayImage, Rgb, Luma};
+	use game::map_generator::{Grass, Road, DeepWater, Forest, Building};
+
+	fn tiles(map: &Map) -> Vec<u32> {
+		map.get_layer(0).tiles().iter().cloned().collect()
+	}
+
+	#[test]
+	fn colours_map_to_the_nearest_palette_entry() {
+		let palette = Palette {
+			entries: vec![((0, 0, 0), Road), ((0, 255, 0), Grass), ((0, 0, 255), DeepWater), ((255, 0, 0), Building)],
+		};
+		assert_eq!(palette.tile_for((0, 255, 0)), Grass);
+		assert_eq!(palette.tile_for((20, 230, 10)), Grass);
+		assert_eq!(palette.tile_for((40, 30, 200)), DeepWater);
+		assert_eq!(palette.tile_for((30, 30, 30)), Road);
+		assert_eq!(palette.tile_for((200, 60, 60)), Building);
+		assert_eq!(Palette { entries: Vec::new() }.tile_for((1, 2, 3)), 0);
+	}
+
+	#[test]
+	fn tileset_colours_import_as_their_tiles() {
+		let tileset = Tileset::new_default();
+		let palette = Palette::from_tileset(&tileset);
+		let ids: Vec<u32> = (0..tileset.len() as u32).collect();
+		let image = RgbImage::from_fn(ids.len() as u32, 2, |x, y| {
+			let (r, g, b) = tileset.color(x);
+			// The second row is a little off, like a resized or compressed image
+			let nudge = |c: u8| if y == 0 { c } else if c > 128 { c - 6 } else { c + 6 };
+			Rgb([nudge(r), nudge(g), nudge(b)])
+		});
+		let map = ImageImporter::from_palette_image(&image, &palette);
+		assert_eq!((map.width(), map.height()), (ids.len(), 2));
+		let expected: Vec<u32> = ids.iter().chain(ids.iter()).cloned().collect();
+		assert_eq!(tiles(&map), expected);
+	}
+
+	#[test]
+	fn palette_colours_are_parsed() {
+		assert_eq!(parse_color("#4e9a34"), Some((78, 154, 52)));
+		assert_eq!(parse_color("#FFFFFF"), Some((255, 255, 255)));
+		assert_eq!(parse_color("4e9a34"), None);
+		assert_eq!(parse_color("#4e9a3"), None);
+		assert_eq!(parse_color("#4e9g34"), None);
+	}
+
+	#[test]
+	fn heightmaps_classify_like_generated_terrain() {
+		let image = GrayImage::from_fn(64, 8, |x, y| Luma([((x * 4 + y) % 256) as u8]));
+		let map = ImageImporter::from_heightmap_image(&image);
+		let values: Vec<f32> = image.pixels().map(|p| p[0] as f32 / 255f32).collect();
+		let expected = TerrainGenerator::from_noise(&Noise::from_vec(64, 8, values));
+		assert_eq!(tiles(&map), expected.into_vec());
+		// Away from the band edges smoothing keeps the class of the ramp value
+		assert_eq!(map.get_layer(0).tiles()[(5, 4)], Road);
+		assert_eq!(map.get_layer(0).tiles()[(20, 4)], Grass);
+		assert_eq!(map.get_layer(0).tiles()[(36, 4)], Forest);
+		assert_eq!(map.get_layer(0).tiles()[(60, 4)], DeepWater);
+	}
+}
@@ -5,56 +5,132 @@ use sfml::graphics::{RenderWindow};
 use sfml::system::{Vector2i, Vector2f};
 use sfml::graphics::RenderTarget;
 
-use game::world::World;
+use game::simulation::{Simulation, Command};
+use game::picking::{ScreenPoint, Click};
+
+const WHEEL_ZOOM: f32 = 0.85;		// view size factor per notch, scrolling up zooms in
 
 pub struct PlayerInputHandler {
-	dummy: i32
+	up: bool,
+	down: bool,
+	left: bool,
+	right: bool,
+	sent_direction: (f32, f32),		// last Move and Aim pushed, only changes become commands
+	sent_aim: Option<(f32, f32)>,
 }
 
 impl PlayerInputHandler {
 	pub fn new() -> Self {
 		PlayerInputHandler {
-			dummy: 0,
+			up: false,
+			down: false,
+			left: false,
+			right: false,
+			sent_direction: (0.0, 0.0),
+			sent_aim: None,
 		}
 	}
 
-	pub fn handle_input(&mut self, world: &mut World, window: &mut RenderWindow) {
+	/// Player actions become commands for the next tick, the camera is changed directly.
+	/// Returns the mouse clicks of the frame in order
+	pub fn handle_input(&mut self, simulation: &mut Simulation, window: &mut RenderWindow) -> Vec<Click> {
+		let mut clicks = Vec::new();
 		for event in window.events() {
 			match event {
 				event::Closed => window.close(),
-				event::KeyPressed{code, ..} => self.handle_key_pressed(world, window, code),	//world, window, code
-				event::KeyReleased{code, ..} => self.handle_key_released(world, window, code),	//world, window, code
-				event::MouseButtonPressed{button, x, y} => self.handle_mouse_pressed(world, window, button, x, y), //world, window, button, x, y
+				event::KeyPressed{code, ..} => self.handle_key_pressed(simulation, window, code),	//simulation, window, code
+				event::KeyReleased{code, ..} => self.handle_key_released(simulation, window, code),	//simulation, window, code
+				event::MouseButtonPressed{button, x, y} => clicks.push(self.handle_mouse_pressed(simulation, window, button, x, y)), //simulation, window, button, x, y
+				event::MouseWheelMoved{delta, x, y} => self.handle_mouse_wheel(simulation, window, delta, x, y),
 				_ => (),
 			}
 		}
+		// Every frame, the cursor stays put while the camera moves under it
+		let mouse = window.get_mouse_position();
+		let aim = ScreenPoint::new(mouse.x, mouse.y).to_world(window, simulation.get_world().get_camera());
+		if self.sent_aim != Some((aim.x, aim.y)) {
+			self.sent_aim = Some((aim.x, aim.y));
+			simulation.push(Command::Aim(aim.x, aim.y));
+		}
+		clicks
+	}
+
+	/// Held keys as a direction of length 1 or 0
+	fn direction(&self) -> Vector2f {
+		let x = (self.right as i32 - self.left as i32) as f32;
+		let y = (self.down as i32 - self.up as i32) as f32;
+		let length = (x * x + y * y).sqrt();
+		if length > 0.0 {
+			Vector2f::new(x / length, y / length)
+		}
+		else {
+			Vector2f::new(0.0, 0.0)
+		}
 	}
 
-	fn handle_key_pressed(&mut self, world: &mut World, window: &mut RenderWindow, code: Key) {
+	/// Called after a movement key changed
+	fn push_direction(&mut self, simulation: &mut Simulation) {
+		let direction = self.direction();
+		if self.sent_direction != (direction.x, direction.y) {
+			self.sent_direction = (direction.x, direction.y);
+			simulation.push(Command::Move(direction.x, direction.y));
+		}
+	}
+
+	fn handle_key_pressed(&mut self, simulation: &mut Simulation, window: &mut RenderWindow, code: Key) {
 		match code {
             Key::Escape => window.close(),
-            Key::Right | Key::D  => world.get_mut_camera().move_right(),
-            Key::Left | Key::A  => world.get_mut_camera().move_left(),
-            Key::Up | Key::W  => world.get_mut_camera().move_up(),
-            Key::Down | Key::S  => world.get_mut_camera().move_down(),
+            Key::Add | Key::E   => simulation.get_mut_world().get_mut_camera().zoom(0.5),
+            Key::Subtract | Key::Q  => simulation.get_mut_world().get_mut_camera().zoom(2.0),
+            Key::Right | Key::D  => {
+                self.right = true;
+                self.push_direction(simulation);
+            },
+            Key::Left | Key::A  => {
+                self.left = true;
+                self.push_direction(simulation);
+            },
+            Key::Up | Key::W  => {
+                self.up = true;
+                self.push_direction(simulation);
+            },
+            Key::Down | Key::S  => {
+                self.down = true;
+                self.push_direction(simulation);
+            },
+            Key::Space => {
+                let paused = simulation.is_paused();
+                simulation.set_paused(!paused);
+            },
+            Key::Num1 => simulation.set_speed(1.0),
+            Key::Num2 => simulation.set_speed(2.0),
+            Key::Num3 => simulation.set_speed(4.0),
+            Key::Num4 => simulation.set_speed(8.0),
             _   => ()
         }
 	}
-	fn handle_key_released(&mut self, world: &mut World, window: &mut RenderWindow, code: Key) {
+	fn handle_key_released(&mut self, simulation: &mut Simulation, window: &mut RenderWindow, code: Key) {
         match code {
-            Key::Right | Key::Left | Key::D | Key::A => world.get_mut_camera().stop_horizontal(),
-            Key::Up | Key::Down | Key::W | Key::S    => world.get_mut_camera().stop_vertical(),
-            Key::Add | Key::E              => world.get_mut_camera().zoom(0.5),
-            Key::Subtract | Key::Q          => world.get_mut_camera().zoom(2.0),
-            _ => ()
+            Key::Right | Key::D => self.right = false,
+            Key::Left | Key::A  => self.left = false,
+            Key::Up | Key::W    => self.up = false,
+            Key::Down | Key::S  => self.down = false,
+            _ => return,
         }
+        self.push_direction(simulation);
     }
 
-    fn handle_mouse_pressed(&mut self, world: &mut World, window: &mut RenderWindow, code: MouseButton, x: i32, y: i32) {
-        /*let v = Vector2i::new(x,y);
-        let v2: Vector2f = window.map_pixel_to_coords(&v, &world.get_view());
-        let v3 = screen_to_map(v2);
-        println!("Mouse({}, {})", v3.x, v3.y);*/
+    /// Zooms towards the point under the cursor
+    fn handle_mouse_wheel(&mut self, simulation: &mut Simulation, window: &mut RenderWindow, delta: i32, x: i32, y: i32) {
+        let point = ScreenPoint::new(x, y).to_world(window, simulation.get_world().get_camera());
+        simulation.get_mut_world().get_mut_camera().zoom_at(WHEEL_ZOOM.powi(delta), point.to_vector());
+    }
 
+    fn handle_mouse_pressed(&mut self, simulation: &mut Simulation, window: &mut RenderWindow, code: MouseButton, x: i32, y: i32) -> Click {
+        let world = simulation.get_world();
+        Click {
+            button: code,
+            pick: world.pick(ScreenPoint::new(x, y).to_world(window, world.get_camera())),
+        }
     }
 }
\ No newline at end of file
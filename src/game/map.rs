@@ -4,24 +4,61 @@ use sfml::system::{Vector2f};
 use sfml::graphics::{Drawable, RenderStates, RenderTarget, RectangleShape, Color, VertexArray, Texture, Quads};
 use rustc_serialize::json;
 use std::fs::File;
-use std::io::Read;
+use std::io::{Read, Write};
 use util::Vec2D;
 use util;
 //use self::util::*;
-use game::map_generator::TerrainGenerator;
+use game::map_generator::{MapGenerator, GeneratorPreset};
+use game::building::Building;
+use game::tileset::Tileset;
+use game::raycast::{self, RaycastHit};
+use game::pathfinding::{self, MovementProfile, Path};
+use game::hpa::PathHierarchy;
+use game::objects::{ObjectLayer, MapObject, PropKind};
+use game::binary_map::{self, BinaryWriter, BinaryReader, SECTION_INFO, SECTION_LAYER, SECTION_OBJECTS, SECTION_COLLISION};
 use std::slice::{Iter, IterMut};
 use std::ops::{Index, IndexMut};
+use std::mem;
+
+const PATH_CLUSTER_SIZE: usize = 16;
+const HIERARCHY_DISTANCE: usize = 32;		// trips longer than this in tiles go through the PathHierarchy
 
-//TODO Separate Layer into Layer and LayerRenderer
 pub struct Layer {
-	vertices: VertexArray,
-	texture: Texture,
 	tiles: Vec2D<u32>,
 }
 
 impl Layer {
-	//TODO Use Map settings struct instead of several parameters
 	pub fn new(tiles: Vec2D<u32>) -> Self {
+		Layer {
+			tiles: tiles,
+		}
+	}
+
+	pub fn tiles(&self) -> &Vec2D<u32> {
+		&self.tiles
+	}
+
+	pub fn tiles_mut(&mut self) -> &mut Vec2D<u32> {
+		&mut self.tiles
+	}
+
+	pub fn width(&self) -> usize {
+		self.tiles.width()
+	}
+
+	pub fn height(&self) -> usize {
+		self.tiles.height()
+	}
+}
+
+pub struct LayerRenderer {
+	vertices: VertexArray,
+	texture: Texture,
+}
+
+impl LayerRenderer {
+	pub fn new(layer: &Layer) -> Self {
+		let tiles = layer.tiles();
 		let size = tiles.len();
 		let texture = Texture::new_from_file("assets/tileset.png").expect("Could not load tileset");
 		//let tiles = vec![0; size];		//TODO Load from file
@@ -50,15 +87,14 @@ impl Layer {
 			//vertices.get_vertex(j * 4 + 2).0.tex_coords = Vector2f::new(tx + 64.0, ty + 64.0);
 			//vertices.get_vertex(j * 4 + 3).0.tex_coords = Vector2f::new(tx, ty + 64.0);
 		}
-		Layer {
+		LayerRenderer {
 			vertices: vertices,
 			texture: texture,
-			tiles: tiles,
 		}
 	}
 }
 
-impl Drawable for Layer {
+impl Drawable for LayerRenderer {
 	fn draw<RT: RenderTarget>(&self, target: &mut RT, _: &mut RenderStates) {
 		let mut rs = RenderStates::default();	//TODO include RenderStates in struct?
 		rs.texture = Some(&self.texture);
@@ -72,56 +108,165 @@ pub struct Map {
 	width: usize,
 	height: usize,
 	layer: [Layer; 3],
+	objects: ObjectLayer,
+	tileset: Tileset,
+	walls: CollisionLayer,			// placed by buildings or by hand
+	collision: CollisionLayer,		// walls plus the edges of blocking tiles
+	hierarchies: Vec<PathHierarchy>,	// one per movement profile, kept up to date by update_collision
 }
 
 
 impl Map {
 	pub fn new_default() -> Self {
-		let width = 128;
-		let height = 128;
-		Map {
-			width: width,
-			height: height,
-			layer: {
-				//let x = TerrainGenerator::new_from_seed(&[1,2,3,4], 128,128);
-				let x = TerrainGenerator::new_from_seed(&[5,6,7,8], 128,128);
-				[x, 
-				Layer::new(Vec2D::from_vec(width, height, vec![0; width*height])),
-				Layer::new(Vec2D::from_vec(width, height, vec![0; width*height]))]
-			},
-		}
+		//let mut generator = MapGenerator::new_seed(&[1,2,3,4]);
+		let mut generator = MapGenerator::new_seed(&[5,6,7,8]);
+		generator.generate_map(128, 128, &GeneratorPreset::new_default())
 	}
 
 	pub fn new_init(width: usize, height: usize, l0: Vec<u32>, l1: Vec<u32>, l2: Vec<u32>) -> Self {
-		Map {
+		let mut map = Map {
 			width: width,
 			height: height,
-			layer: [Layer::new(Vec2D::from_vec(width, height, vec![0; width*height])), 
-				Layer::new(Vec2D::from_vec(width, height, vec![0; width*height])), 
-				Layer::new(Vec2D::from_vec(width, height, vec![0; width*height]))],
-		}
+			layer: [Layer::new(Vec2D::from_vec(width, height, l0)), 
+				Layer::new(Vec2D::from_vec(width, height, l1)), 
+				Layer::new(Vec2D::from_vec(width, height, l2))],
+			objects: ObjectLayer::new(),
+			tileset: Tileset::new_default(),
+			walls: CollisionLayer::new(width, height),
+			collision: CollisionLayer::new(width, height),
+			hierarchies: Vec::new(),
+		};
+		map.update_collision(0, 0, width, height);
+		map
+	}
+
+	pub fn width(&self) -> usize {
+		self.width
+	}
+
+	pub fn height(&self) -> usize {
+		self.height
 	}
 
 	pub fn get_layer(&self, n: usize) -> &Layer {
 		&self.layer[n]
 	}
-}
 
-impl Drawable for Map {
-	fn draw<RT: RenderTarget>(&self, render_target: &mut RT, _: &mut RenderStates) {
-		//for i in self.layer[0].iter() {
-			//self.rect.set_fill_color(&self.tile_info[i].color);
-			//self.rect.set_fill_color(&self.tile_info[i].color);
-			//render_target.draw(&self.rect);
-		//}
-		render_target.draw(&self.layer[0]);
-		render_target.draw(&self.layer[1]);
-		render_target.draw(&self.layer[2]);
+	pub fn get_mut_layer(&mut self, n: usize) -> &mut Layer {
+		&mut self.layer[n]
+	}
+
+	pub fn get_objects(&self) -> &ObjectLayer {
+		&self.objects
+	}
+
+	pub fn set_objects(&mut self, objects: ObjectLayer) {
+		self.objects = objects;
+	}
+
+	pub fn get_collision(&self) -> &CollisionLayer {
+		&self.collision
+	}
+
+	pub fn get_walls(&self) -> &CollisionLayer {
+		&self.walls
+	}
+
+	pub fn set_walls(&mut self, walls: CollisionLayer) {
+		assert!(walls.width() == self.width && walls.height() == self.height, "Walls do not match the map size");
+		self.walls = walls;
+		let (width, height) = (self.width, self.height);
+		self.update_collision(0, 0, width, height);
+	}
+
+	pub fn get_tileset(&self) -> &Tileset {
+		&self.tileset
+	}
+
+	pub fn set_tileset(&mut self, tileset: Tileset) {
+		self.tileset = tileset;
+		let (width, height) = (self.width, self.height);
+		self.update_collision(0, 0, width, height);
+	}
+
+	/// A tile blocks if its tile on any layer does
+	pub fn is_blocking(&self, x: usize, y: usize) -> bool {
+		self.layer.iter().any(|layer| self.tileset.is_blocking(layer.tiles()[(x, y)]))
+	}
+
+	pub fn blocks_sight(&self, x: usize, y: usize) -> bool {
+		self.layer.iter().any(|layer| self.tileset.blocks_sight(layer.tiles()[(x, y)]))
+	}
+
+	/// First placed wall or sight blocking tile between two points in tiles, the start tile is ignored
+	pub fn raycast(&self, from: Vector2f, to: Vector2f) -> Option<RaycastHit> {
+		raycast::raycast(self, from, to)
+	}
+
+	pub fn has_line_of_sight(&self, a: Vector2f, b: Vector2f) -> bool {
+		raycast::raycast(self, a, b).is_none()
+	}
+
+	/// Topmost tile that is not empty, upper layers use 0 for empty
+	pub fn top_tile(&self, x: usize, y: usize) -> u32 {
+		self.layer.iter().rev().map(|layer| layer.tiles()[(x, y)]).find(|&tile| tile != 0).unwrap_or(0)
+	}
+
+	/// Way between two tiles, None if the goal can not be reached. Long trips go through the path hierarchy
+	/// of the profile if there is one, which is much faster but can be a little longer than the cheapest way
+	pub fn find_path(&self, start: (usize, usize), goal: (usize, usize), profile: &MovementProfile) -> Option<Path> {
+		let distance = (start.0 as isize - goal.0 as isize).abs().max((start.1 as isize - goal.1 as isize).abs()) as usize;
+		if distance > HIERARCHY_DISTANCE {
+			if let Some(hierarchy) = self.get_path_hierarchy(profile.name) {
+				return hierarchy.find_path(self, start, goal).and_then(|path| path.refine(self, hierarchy));
+			}
+		}
+		pathfinding::find_path(self, start, goal, profile)
+	}
+
+	/// Builds the hierarchy find_path uses for long trips with the profile, replaces one with the same name
+	pub fn add_path_hierarchy(&mut self, profile: MovementProfile) {
+		self.hierarchies.retain(|hierarchy| hierarchy.profile.name != profile.name);
+		let hierarchy = PathHierarchy::new(self, profile, PATH_CLUSTER_SIZE);
+		self.hierarchies.push(hierarchy);
+	}
+
+	pub fn get_path_hierarchy(&self, profile: &str) -> Option<&PathHierarchy> {
+		self.hierarchies.iter().find(|hierarchy| hierarchy.profile.name == profile)
+	}
+
+	pub fn set_tile(&mut self, layer: usize, x: usize, y: usize, tile: u32) {
+		self.layer[layer].tiles_mut()[(x, y)] = tile;
+		self.update_collision(x, y, 1, 1);
+	}
+
+	/// Recomputes the collision and path hierarchies of the given tiles, call after changing tiles through get_mut_layer
+	pub fn update_collision(&mut self, x: usize, y: usize, width: usize, height: usize) {
+		{
+			let layers = &self.layer;
+			let tileset = &self.tileset;
+			let blocking = |x: usize, y: usize| layers.iter().any(|layer| tileset.is_blocking(layer.tiles()[(x, y)]));
+			self.collision.update_from_tiles(&self.walls, blocking, x, y, width, height);
+		}
+		// The hierarchies read the map while they are rebuilt, so they are taken out meanwhile
+		let mut hierarchies = mem::replace(&mut self.hierarchies, Vec::new());
+		for hierarchy in hierarchies.iter_mut() {
+			hierarchy.update_area(self, x, y, width, height);
+		}
+		self.hierarchies = hierarchies;
+	}
+
+	/// Writes the building into layer 0 and adds its walls to the map collision
+	pub fn place_building(&mut self, building: &Building) {
+		building.stamp(self.layer[0].tiles_mut());
+		let (x, y) = (building.position().x as usize, building.position().y as usize);
+		self.walls.merge(building.collision(), x, y);
+		self.update_collision(x, y, building.width(), building.height());
 	}
 }
 
 
-#[derive(RustcDecodable, RustcEncodable)]
+#[derive(RustcDecodable, RustcEncodable, Clone)]
 pub struct MapLoader {
 	name: String,
 	description: String,
@@ -133,6 +278,16 @@ pub struct MapLoader {
 	layer0: Vec<u32>,
 	layer1: Vec<u32>,
 	layer2: Vec<u32>,
+	objects: Option<Vec<ObjectData>>,
+	collision_vertical: Option<Vec<u32>>,		// indices of the placed walls in CollisionLayer::vertical_edges
+	collision_horizontal: Option<Vec<u32>>,
+}
+
+#[derive(RustcDecodable, RustcEncodable, Clone)]
+pub struct ObjectData {
+	kind: String,
+	x: f32,
+	y: f32,
 }
 
 impl MapLoader {
@@ -148,18 +303,211 @@ impl MapLoader {
 			layer0: vec![0;64*64],
 			layer1: vec![0;64*64],
 			layer2: vec![0;64*64],
+			objects: None,
+			collision_vertical: None,
+			collision_horizontal: None,
 		};
 		let encoded = json::encode(&map_loader).unwrap();
 		println!("{}", encoded);
 	}
 
+	pub fn from_map(map: &Map, name: &str, description: &str) -> Self {
+		MapLoader {
+			name: name.to_string(),
+			description: description.to_string(),
+			width: map.width() as u32,
+			height: map.height() as u32,
+			tileset0: "tileset.png".to_string(),
+			tileset1: "tileset.png".to_string(),
+			tileset2: "tileset.png".to_string(),
+			layer0: map.get_layer(0).tiles().iter().cloned().collect(),
+			layer1: map.get_layer(1).tiles().iter().cloned().collect(),
+			layer2: map.get_layer(2).tiles().iter().cloned().collect(),
+			objects: Some(map.get_objects().iter().map(|o| {
+				ObjectData {
+					kind: o.kind.name().to_string(),
+					x: o.x,
+					y: o.y,
+				}
+			}).collect()),
+			collision_vertical: Some(wall_indices(map.get_walls().vertical_edges())),
+			collision_horizontal: Some(wall_indices(map.get_walls().horizontal_edges())),
+		}
+	}
+
+	pub fn into_map(self) -> Map {
+		let mut map = Map::new_init(self.width as usize, self.height as usize, self.layer0, self.layer1, self.layer2);
+		if let Some(objects) = self.objects {
+			let mut layer = ObjectLayer::new();
+			for o in objects.iter() {
+				if let Some(kind) = PropKind::from_name(&o.kind) {
+					layer.add(MapObject::new(kind, o.x, o.y));
+				}
+			}
+			map.set_objects(layer);
+		}
+		if let (Some(vertical), Some(horizontal)) = (self.collision_vertical, self.collision_horizontal) {
+			map.set_walls(CollisionLayer::from_walls(self.width as usize, self.height as usize, &vertical, &horizontal));
+		}
+		map
+	}
+
 	pub fn load(file: &str) -> Map {
 		let folder = "assets/".to_string();
-		let mut file = File::open(folder + file).expect("Could not open file!");
-		let mut buf = String::new();
-		file.read_to_string(&mut buf).expect("Could not read file!");
-		let decoded: MapLoader = json::decode(&buf).expect("Could not decode");
-		Map::new_init(decoded.width as usize, decoded.height as usize, decoded.layer0, decoded.layer1, decoded.layer2)
+		Self::load_path(&(folder + file))
+	}
+
+	pub fn load_path(path: &str) -> Map {
+		Self::read_path(path).into_map()
+	}
+
+	/// Reads the file without building the map, to get at the name and description
+	pub fn read_path(path: &str) -> MapLoader {
+		let mut file = File::open(path).expect("Could not open file!");
+		let mut buf = Vec::new();
+		file.read_to_end(&mut buf).expect("Could not read file!");
+		match MapLoader::from_bytes(&buf) {
+			Ok(loader) => loader,
+			Err(e) => panic!("Could not decode {}: {}", path, e),
+		}
+	}
+
+	/// Binary or JSON, depending on the first bytes
+	pub fn from_bytes(bytes: &[u8]) -> Result<MapLoader, String> {
+		if binary_map::is_binary(bytes) {
+			return MapLoader::from_binary(bytes);
+		}
+		let text = ::std::str::from_utf8(bytes).map_err(|_| "Map is neither binary nor JSON".to_string())?;
+		json::decode(text).map_err(|e| e.to_string())
+	}
+
+	pub fn to_binary(&self) -> Vec<u8> {
+		let mut writer = BinaryWriter::new_file();
+		let mut info = BinaryWriter::new();
+		info.string(&self.name);
+		info.string(&self.description);
+		info.u32(self.width);
+		info.u32(self.height);
+		info.string(&self.tileset0);
+		info.string(&self.tileset1);
+		info.string(&self.tileset2);
+		writer.section(SECTION_INFO, info);
+		for (i, layer) in [&self.layer0, &self.layer1, &self.layer2].iter().enumerate() {
+			let mut section = BinaryWriter::new();
+			section.u8(i as u8);
+			section.layer(layer);
+			writer.section(SECTION_LAYER, section);
+		}
+		if let Some(ref objects) = self.objects {
+			let mut section = BinaryWriter::new();
+			section.varint(objects.len() as u64);
+			for o in objects.iter() {
+				section.string(&o.kind);
+				section.f32(o.x);
+				section.f32(o.y);
+			}
+			writer.section(SECTION_OBJECTS, section);
+		}
+		if let (&Some(ref vertical), &Some(ref horizontal)) = (&self.collision_vertical, &self.collision_horizontal) {
+			let mut section = BinaryWriter::new();
+			section.layer(vertical);
+			section.layer(horizontal);
+			writer.section(SECTION_COLLISION, section);
+		}
+		writer.finish()
+	}
+
+	pub fn from_binary(bytes: &[u8]) -> Result<MapLoader, String> {
+		let mut reader = BinaryReader::new(bytes)?;
+		let mut loader = MapLoader {
+			name: String::new(),
+			description: String::new(),
+			width: 0,
+			height: 0,
+			tileset0: String::new(),
+			tileset1: String::new(),
+			tileset2: String::new(),
+			layer0: Vec::new(),
+			layer1: Vec::new(),
+			layer2: Vec::new(),
+			objects: None,
+			collision_vertical: None,
+			collision_horizontal: None,
+		};
+		while !reader.is_empty() {
+			let (tag, mut section) = reader.section()?;
+			match tag {
+				SECTION_INFO => {
+					loader.name = section.string()?;
+					loader.description = section.string()?;
+					loader.width = section.u32()?;
+					loader.height = section.u32()?;
+					loader.tileset0 = section.string()?;
+					loader.tileset1 = section.string()?;
+					loader.tileset2 = section.string()?;
+				},
+				SECTION_LAYER => {
+					let index = section.u8()?;
+					let tiles = section.layer()?;
+					match index {
+						0 => loader.layer0 = tiles,
+						1 => loader.layer1 = tiles,
+						2 => loader.layer2 = tiles,
+						_ => return Err(format!("Unknown layer {}", index)),
+					}
+				},
+				SECTION_OBJECTS => {
+					// Kind length, x and y take at least 9 bytes per object
+					let count = section.varint()?;
+					if count > (section.remaining() / 9) as u64 {
+						return Err("Unexpected end of data".to_string());
+					}
+					let mut objects = Vec::with_capacity(count as usize);
+					for _ in 0..count {
+						objects.push(ObjectData {
+							kind: section.string()?,
+							x: section.f32()?,
+							y: section.f32()?,
+						});
+					}
+					loader.objects = Some(objects);
+				},
+				SECTION_COLLISION => {
+					loader.collision_vertical = Some(section.layer()?);
+					loader.collision_horizontal = Some(section.layer()?);
+				},
+				// Sections of newer versions are skipped
+				_ => (),
+			}
+		}
+		let size = match loader.width.checked_mul(loader.height) {
+			Some(size) if size as usize <= binary_map::MAX_LAYER_TILES => size as usize,
+			_ => return Err(format!("Map size {}x{} is too large", loader.width, loader.height)),
+		};
+		if loader.layer0.len() != size || loader.layer1.len() != size || loader.layer2.len() != size {
+			return Err(format!("Layers do not match the map size {}x{}", loader.width, loader.height));
+		}
+		Ok(loader)
+	}
+
+	pub fn name(&self) -> &str {
+		&self.name
+	}
+
+	pub fn description(&self) -> &str {
+		&self.description
+	}
+
+	/// Files ending in .zmap are written in the binary format, anything else as JSON
+	pub fn save_path(&self, path: &str) {
+		let encoded = if path.ends_with(binary_map::EXTENSION) {
+			self.to_binary()
+		}
+		else {
+			json::encode(self).expect("Could not encode").into_bytes()
+		};
+		let mut file = File::create(path).expect("Could not create file!");
+		file.write_all(&encoded).expect("Could not write file!");
 	}
 }
 
@@ -171,8 +519,8 @@ pub struct CollisionLayer {
 impl CollisionLayer {
 	pub fn new(width: usize, height: usize) -> Self {
 		CollisionLayer {
-			left_right: Vec2D::new(width + 1, height),
-			top_bottom: Vec2D::new(width, height + 1),
+			left_right: Vec2D::from_vec(width + 1, height, vec![false; (width + 1) * height]),
+			top_bottom: Vec2D::from_vec(width, height + 1, vec![false; width * (height + 1)]),
 		}
 	}
 
@@ -193,27 +541,292 @@ impl CollisionLayer {
 	}
 
 	pub fn can_walk_right(&self, x: usize, y: usize) -> bool {
-		self.left_right[(x+1,y)]
+		!self.left_right[(x+1,y)]
 	}
 
 	pub fn can_walk_left(&self, x: usize, y: usize) -> bool {
-		self.left_right[(x,y)]
+		!self.left_right[(x,y)]
 	}
 
 	pub fn can_walk_up(&self, x: usize, y: usize) -> bool {
-		self.top_bottom[(x,y)]
+		!self.top_bottom[(x,y)]
 	}
 
 	pub fn can_walk_down(&self, x: usize, y: usize) -> bool {
-		self.top_bottom[(x,y+1)]
+		!self.top_bottom[(x,y+1)]
+	}
+
+	/// Adds the walls of other with its top left tile at (x, y), walls outside this layer are dropped
+	pub fn merge(&mut self, other: &CollisionLayer, x: usize, y: usize) {
+		for oy in 0..other.left_right.height() {
+			for ox in 0..other.left_right.width() {
+				if other.left_right[(ox, oy)] && x + ox < self.left_right.width() && y + oy < self.left_right.height() {
+					self.left_right[(x + ox, y + oy)] = true;
+				}
+			}
+		}
+		for oy in 0..other.top_bottom.height() {
+			for ox in 0..other.top_bottom.width() {
+				if other.top_bottom[(ox, oy)] && x + ox < self.top_bottom.width() && y + oy < self.top_bottom.height() {
+					self.top_bottom[(x + ox, y + oy)] = true;
+				}
+			}
+		}
+	}
+
+	/// Recomputes the edges around the tiles x..x+width, y..y+height: an edge is a wall if it is in walls
+	/// or separates a blocking from an open tile. The map border only has the walls from walls.
+	pub fn update_from_tiles<F: Fn(usize, usize) -> bool>(&mut self, walls: &CollisionLayer, blocking: F, x: usize, y: usize, width: usize, height: usize) {
+		let (map_width, map_height) = (self.width(), self.height());
+		let x1 = (x + width).min(map_width);
+		let y1 = (y + height).min(map_height);
+		for ty in y..y1 {
+			for ex in x..(x1 + 1) {
+				let tile_edge = ex > 0 && ex < map_width && blocking(ex - 1, ty) != blocking(ex, ty);
+				self.left_right[(ex, ty)] = walls.left_right[(ex, ty)] || tile_edge;
+			}
+		}
+		for ey in y..(y1 + 1) {
+			for tx in x..x1 {
+				let tile_edge = ey > 0 && ey < map_height && blocking(tx, ey - 1) != blocking(tx, ey);
+				self.top_bottom[(tx, ey)] = walls.top_bottom[(tx, ey)] || tile_edge;
+			}
+		}
+	}
+
+	/// Builds a layer from the indices of the set edges, see vertical_edges and horizontal_edges
+	pub fn from_walls(width: usize, height: usize, vertical: &[u32], horizontal: &[u32]) -> Self {
+		let mut collision = CollisionLayer::new(width, height);
+		for &i in vertical.iter() {
+			if let Some(edge) = (*collision.left_right).get_mut(i as usize) {
+				*edge = true;
+			}
+		}
+		for &i in horizontal.iter() {
+			if let Some(edge) = (*collision.top_bottom).get_mut(i as usize) {
+				*edge = true;
+			}
+		}
+		collision
+	}
+
+	/// Walls on the left and right tile sides, (width + 1) x height
+	pub fn vertical_edges(&self) -> &Vec2D<bool> {
+		&self.left_right
+	}
+
+	/// Walls on the top and bottom tile sides, width x (height + 1)
+	pub fn horizontal_edges(&self) -> &Vec2D<bool> {
+		&self.top_bottom
 	}
 
 	pub fn width(&self) -> usize {
-		self.width()
+		self.top_bottom.width()
 	}
 
 	pub fn height(&self) -> usize {
-		self.height()
+		self.left_right.height()
 	}
 }
 
+fn wall_indices(edges: &Vec2D<bool>) -> Vec<u32> {
+	edges.iter().enumerate().filter(|&(_, &wall)| wall).map(|(i, _)| i as u32).collect()
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use game::map_generator::{Grass, Building};
+
+	fn small_loader() -> MapLoader {
+		MapLoader {
+			name: "Small".to_string(),
+			description: "Two by three".to_string(),
+			width: 2,
+			height: 3,
+			tileset0: "tileset.png".to_string(),
+			tileset1: "tileset.png".to_string(),
+			tileset2: "tileset.png".to_string(),
+			layer0: vec![0, 2, 2, 5, 9, 9],
+			layer1: vec![0; 6],
+			layer2: vec![0, 0, 0, 0, 1, 0],
+			objects: Some(vec![ObjectData {
+				kind: "tree".to_string(),
+				x: 0.5,
+				y: 1.25,
+			}]),
+			collision_vertical: Some(vec![1, 4]),
+			collision_horizontal: Some(vec![]),
+		}
+	}
+
+	fn info_section(width: u32, height: u32) -> BinaryWriter {
+		let mut info = BinaryWriter::new();
+		info.string("Broken");
+		info.string("");
+		info.u32(width);
+		info.u32(height);
+		for _ in 0..3 {
+			info.string("tileset.png");
+		}
+		info
+	}
+
+	#[test]
+	fn binary_round_trip() {
+		let loader = small_loader();
+		let decoded = MapLoader::from_bytes(&loader.to_binary()).unwrap();
+		assert_eq!(json::encode(&decoded).unwrap(), json::encode(&loader).unwrap());
+	}
+
+	#[test]
+	fn damaged_binary_is_rejected() {
+		let mut bytes = small_loader().to_binary();
+		assert!(MapLoader::from_bytes(&bytes[..bytes.len() - 5]).is_err());
+		bytes[10] ^= 0xff;
+		assert!(MapLoader::from_bytes(&bytes).is_err());
+	}
+
+	#[test]
+	fn object_count_past_the_section_is_rejected() {
+		let mut writer = BinaryWriter::new_file();
+		writer.section(SECTION_INFO, info_section(0, 0));
+		let mut objects = BinaryWriter::new();
+		objects.varint(u64::max_value());
+		objects.string("tree");
+		objects.f32(1.0);
+		objects.f32(1.0);
+		writer.section(SECTION_OBJECTS, objects);
+		assert!(MapLoader::from_bytes(&writer.finish()).is_err());
+	}
+
+	#[test]
+	fn overflowing_size_is_rejected() {
+		let mut writer = BinaryWriter::new_file();
+		writer.section(SECTION_INFO, info_section(0x10000, 0x10000));
+		assert!(MapLoader::from_bytes(&writer.finish()).is_err());
+	}
+
+	/// Building tiles down column 40 with a door at y 10
+	fn walled_map() -> Map {
+		let (width, height) = (80, 20);
+		let mut tiles = vec![Grass; width * height];
+		for y in 0..height {
+			if y != 10 {
+				tiles[y * width + 40] = Building;
+			}
+		}
+		Map::new_init(width, height, tiles, vec![0; width * height], vec![0; width * height])
+	}
+
+	#[test]
+	fn long_trips_use_the_hierarchy() {
+		let mut map = walled_map();
+		let human = MovementProfile::new_human();
+		let direct = map.find_path((0, 0), (79, 19), &human).unwrap();
+		map.add_path_hierarchy(MovementProfile::new_human());
+		assert!(map.get_path_hierarchy("human").is_some());
+		let routed = map.find_path((0, 0), (79, 19), &human).unwrap();
+		assert_eq!(routed.tiles.first(), Some(&(0, 0)));
+		assert_eq!(routed.tiles.last(), Some(&(79, 19)));
+		assert!(routed.tiles.contains(&(40, 10)));
+		assert!(routed.cost >= direct.cost - 0.001 && routed.cost < direct.cost * 1.1);
+	}
+
+	#[test]
+	fn hierarchy_follows_tile_edits() {
+		let mut map = walled_map();
+		let human = MovementProfile::new_human();
+		map.add_path_hierarchy(MovementProfile::new_human());
+		map.set_tile(0, 40, 10, Building);
+		assert!(map.find_path((0, 0), (79, 19), &human).is_none());
+		map.set_tile(0, 40, 3, Grass);
+		let path = map.find_path((0, 0), (79, 19), &human).unwrap();
+		assert!(path.tiles.contains(&(40, 3)));
+	}
+
+	#[test]
+	fn hierarchy_follows_walls() {
+		let mut map = walled_map();
+		let human = MovementProfile::new_human();
+		map.add_path_hierarchy(MovementProfile::new_human());
+		// Wall on the left side of the door, vertical edges are 81 wide
+		map.set_walls(CollisionLayer::from_walls(80, 20, &[10 * 81 + 40], &[]));
+		assert!(map.find_path((0, 0), (79, 19), &human).is_none());
+	}
+
+	fn grass_map(width: usize, height: usize) -> Map {
+		Map::new_init(width, height, vec![Grass; width * height], vec![0; width * height], vec![0; width * height])
+	}
+
+	#[test]
+	fn blocking_tiles_get_edges_to_open_tiles() {
+		let mut map = grass_map(4, 3);
+		map.set_tile(0, 1, 1, Building);
+		map.set_tile(0, 2, 1, Building);
+		let collision = map.get_collision();
+		assert!(!collision.can_walk_right(0, 1) && !collision.can_walk_left(3, 1));
+		assert!(!collision.can_walk_down(1, 0) && !collision.can_walk_up(2, 2));
+		// None between the two buildings
+		assert!(collision.can_walk_right(1, 1) && collision.can_walk_left(2, 1));
+		assert!(collision.can_walk_right(0, 0) && collision.can_walk_down(0, 0));
+		map.set_tile(0, 1, 1, Grass);
+		let collision = map.get_collision();
+		assert!(collision.can_walk_right(0, 1) && !collision.can_walk_right(1, 1));
+	}
+
+	#[test]
+	fn walls_survive_tile_changes() {
+		let mut map = grass_map(3, 3);
+		let mut walls = CollisionLayer::new(3, 3);
+		walls.set_collision_right(0, 0);
+		walls.set_collision_bottom(2, 2);
+		map.set_walls(walls);
+		map.set_tile(0, 1, 0, Building);
+		map.set_tile(0, 1, 0, Grass);
+		map.set_tile(0, 2, 2, Building);
+		map.set_tile(0, 2, 2, Grass);
+		let collision = map.get_collision();
+		assert!(!collision.can_walk_right(0, 0));
+		assert!(!collision.can_walk_down(2, 2));
+		assert!(collision.can_walk_right(1, 0) && collision.can_walk_up(2, 2));
+	}
+
+	#[test]
+	fn map_border_only_has_placed_walls() {
+		let mut map = grass_map(3, 2);
+		for &(x, y) in [(0, 0), (2, 0), (0, 1), (2, 1)].iter() {
+			map.set_tile(0, x, y, Building);
+		}
+		{
+			let collision = map.get_collision();
+			for y in 0..2 {
+				assert!(collision.can_walk_left(0, y) && collision.can_walk_right(2, y));
+			}
+			for x in 0..3 {
+				assert!(collision.can_walk_up(x, 0) && collision.can_walk_down(x, 1));
+			}
+			assert!(!collision.can_walk_right(0, 0) && !collision.can_walk_left(2, 1));
+		}
+		let mut walls = CollisionLayer::new(3, 2);
+		walls.set_collision_left(0, 1);
+		walls.set_collision_top(1, 0);
+		map.set_walls(walls);
+		let collision = map.get_collision();
+		assert!(!collision.can_walk_left(0, 1) && !collision.can_walk_up(1, 0));
+		assert!(collision.can_walk_left(0, 0) && collision.can_walk_up(0, 0));
+	}
+
+	#[test]
+	fn partial_updates_only_touch_their_area() {
+		let mut collision = CollisionLayer::new(4, 4);
+		let walls = CollisionLayer::new(4, 4);
+		collision.update_from_tiles(&walls, |x, _| x == 0, 0, 0, 4, 4);
+		assert!(!collision.can_walk_right(0, 3));
+		// Now column 3 blocks too but only rows 1 and 2 are recomputed
+		collision.update_from_tiles(&walls, |x, _| x == 0 || x == 3, 2, 1, 2, 2);
+		assert!(!collision.can_walk_right(2, 1) && !collision.can_walk_right(2, 2));
+		assert!(collision.can_walk_right(2, 0) && collision.can_walk_right(2, 3));
+		assert!(!collision.can_walk_right(0, 0) && !collision.can_walk_right(0, 3));
+	}
+}
@@ -3,11 +3,16 @@ use game::map::{Map, Layer, CollisionLayer};
 use rand::{Rng, SeedableRng, StdRng};
 use std::ops::{Index, IndexMut};
 use std::slice::{Iter, IterMut};
-use sfml::system::{Vector2f, Vector2u};
+use sfml::system::{Vector2f};
 //use sfml::graphics::*;
 use sfml::graphics::{Drawable, RenderStates, RenderTarget, RectangleShape, Color, VertexArray, Texture, Quads, IntRect};
 use util;
 use util::Vec2D;
+use game::zoning::{ZoneGenerator, TownGenerator};
+use game::building::Building;
+use game::connectivity::ConnectivityRepair;
+use game::objects::{ObjectLayer, MapObject};
+use game::scatter::PropScatterer;
 
 pub struct MapGenerator {
 	rng: StdRng,
@@ -27,6 +32,79 @@ impl MapGenerator {
 	pub fn next_f32(&mut self) -> f32 {
 		self.rng.gen::<f32>()
 	}
+
+	pub fn next_seed(&mut self) -> [usize; 4] {
+		[self.next_usize(), self.next_usize(), self.next_usize(), self.next_usize()]
+	}
+
+	/// Runs terrain, road, building and prop generation
+	pub fn generate_map(&mut self, width: usize, height: usize, preset: &GeneratorPreset) -> Map {
+		let terrain_seed = self.next_seed();
+		let scatter_seed = self.next_seed();
+		let (tiles, buildings, markers) = TerrainGenerator::generate_with_buildings(&terrain_seed, width, height, preset);
+		let mut objects = if preset.scatter {
+			PropScatterer::new(&scatter_seed).scatter(&tiles)
+		}
+		else {
+			ObjectLayer::new()
+		};
+		for marker in markers {
+			objects.add(marker);
+		}
+		let mut map = Map::new_init(width, height, tiles.into_vec(), vec![0; width * height], vec![0; width * height]);
+		map.set_objects(objects);
+		for building in buildings.iter() {
+			map.place_building(building);
+		}
+		map
+	}
+}
+
+pub struct GeneratorPreset {
+	pub name: &'static str,
+	pub octaves: usize,
+	pub persistance: f32,
+	pub roads: bool,
+	pub town: bool,		// needs roads
+	pub scatter: bool,
+}
+
+pub const PRESET_NAMES: &'static [&'static str] = &["default", "town", "wilderness"];
+
+impl GeneratorPreset {
+	pub fn new_default() -> Self {
+		GeneratorPreset {
+			name: "default",
+			octaves: 6,
+			persistance: 0.6,
+			roads: true,
+			town: true,
+			scatter: true,
+		}
+	}
+
+	pub fn from_name(name: &str) -> Option<Self> {
+		match name {
+			"default" => Some(GeneratorPreset::new_default()),
+			"town" => Some(GeneratorPreset {
+				name: "town",
+				octaves: 6,
+				persistance: 0.4,
+				roads: true,
+				town: true,
+				scatter: true,
+			}),
+			"wilderness" => Some(GeneratorPreset {
+				name: "wilderness",
+				octaves: 6,
+				persistance: 0.7,
+				roads: false,
+				town: false,
+				scatter: true,
+			}),
+			_ => None,
+		}
+	}
 }
 
 pub struct Noise(Vec2D<f32>);
@@ -36,6 +114,10 @@ impl Noise {
 		Noise(Vec2D::from_vec(width, height, vec![0f32; width * height]))
 	}
 
+	pub fn from_vec(width: usize, height: usize, values: Vec<f32>) -> Self {
+		Noise(Vec2D::from_vec(width, height, values))
+	}
+
 	pub fn new_white_from_seed(seed: &[usize], width: usize, height: usize) -> Self {
 		let mut rng: StdRng = SeedableRng::from_seed(seed);
 		let mut vec: Vec<f32> = Vec::with_capacity(width * height);
@@ -197,19 +279,49 @@ impl Drawable for NoiseRenderer {
 }
 
 
-const Grass: u32 = 0u32;
-const ShallowWater: u32 = 1u32;
-const Road: u32 = 2u32;
-const Snow: u32 = 3u32;
-const DeepWater: u32 = 4u32;
-const Forest: u32 = 5u32;
-const Dirt: u32 = 6u32;
-const Sand: u32 = 7u32;
-const Swamp: u32 = 8u32;
-const Building: u32 = 9u32;
+pub const Grass: u32 = 0u32;
+pub const ShallowWater: u32 = 1u32;
+pub const Road: u32 = 2u32;
+pub const Snow: u32 = 3u32;
+pub const DeepWater: u32 = 4u32;
+pub const Forest: u32 = 5u32;
+pub const Dirt: u32 = 6u32;
+pub const Sand: u32 = 7u32;
+pub const Swamp: u32 = 8u32;
+pub const Building: u32 = 9u32;
+
+pub fn tile_name(tile: u32) -> &'static str {
+	match tile {
+		Grass => "grass",
+		ShallowWater => "shallow water",
+		Road => "road",
+		Snow => "snow",
+		DeepWater => "deep water",
+		Forest => "forest",
+		Dirt => "dirt",
+		Sand => "sand",
+		Swamp => "swamp",
+		Building => "building",
+		_ => "unknown",
+	}
+}
+
+const ZONE_STREAM: usize = 1;
+const TOWN_STREAM: usize = 2;
+
+/// Seed for one stage of the generation, so stages seeded from the same seed do not correlate
+fn sub_seed(seed: &[usize], stream: usize) -> Vec<usize> {
+	let mut sub = seed.to_vec();
+	sub.push(stream);
+	sub
+}
 
-const oct: usize = 6;
-const per: f32 = 0.6;
+pub fn is_walkable(tile: u32) -> bool {
+	match tile {
+		DeepWater | Snow | Building => false,
+		_ => true,
+	}
+}
 
 pub struct TerrainGenerator {
 	dummy: i32,
@@ -217,25 +329,56 @@ pub struct TerrainGenerator {
 
 impl TerrainGenerator {
 	pub fn new_from_seed(seed: &[usize], width: usize, height: usize) -> Layer {
-		let noise = Noise::new_perlin_from_seed(seed, width, height, oct, per);
+		Layer::new(Self::generate(seed, width, height, &GeneratorPreset::new_default()))
+	}
+
+	pub fn generate(seed: &[usize], width: usize, height: usize, preset: &GeneratorPreset) -> Vec2D<u32> {
+		Self::generate_with_buildings(seed, width, height, preset).0
+	}
+
+	/// Tiles with the buildings already stamped in, plus the buildings for their collision and the
+	/// zombie spawn and loot markers of the town
+	pub fn generate_with_buildings(seed: &[usize], width: usize, height: usize, preset: &GeneratorPreset) -> (Vec2D<u32>, Vec<Building>, Vec<MapObject>) {
+		let noise = Noise::new_perlin_from_seed(seed, width, height, preset.octaves, preset.persistance);
+		let mut t = Self::from_noise(&noise);
+		let (buildings, markers) = if preset.roads {
+			Self::add_roads(&mut t, seed, preset.town)
+		}
+		else {
+			(Vec::new(), Vec::new())
+		};
+		let mut collision = CollisionLayer::new(width, height);
+		for building in buildings.iter() {
+			let position = building.position();
+			collision.merge(building.collision(), position.x as usize, position.y as usize);
+		}
+		ConnectivityRepair::new().repair(&mut t, Some(&collision));
+		//t[(0,0)] = Sand;
+		(t, buildings, markers)
+	}
+
+	pub fn classify(n: f32) -> u32 {
+		match n {
+			b if b < 0.05 => Snow,
+			b if b < 0.15 => Road,
+			b if b < 0.5 => Grass,
+			b if b < 0.65 => Forest,
+			_ => DeepWater,
+		}
+	}
+
+	/// Classified and smoothed terrain, without roads or towns
+	pub fn from_noise(noise: &Noise) -> Vec2D<u32> {
 		//let mut tiles: Vec<u32> = Vec::with_capacity(width * height);
-		let mut tiles: Vec2D<u32> = Vec2D::new(width, height);
+		let mut tiles: Vec2D<u32> = Vec2D::new(noise.width(), noise.height());
 		for n in noise.iter() {
-			let mut x = match *n {
-				b if b < 0.05 => Snow,
-				b if b < 0.15 => Road,
-				b if b < 0.5 => Grass,
-				b if b < 0.65 => Forest,
-				_ => DeepWater,
-			};
-			(*tiles).push(x);
+			(*tiles).push(Self::classify(*n));
 		}
 		let mut t = Self::smooth_terrain(&tiles);
 		for _ in 0..0 {
 			t = Self::smooth_terrain(&t);
 		}
-		//t[(0,0)] = Sand;
-		Layer::new(t)
+		t
 	}
 
 	fn smooth_terrain(tiles: &Vec2D<u32>) -> Vec2D<u32> {
@@ -244,8 +387,8 @@ impl TerrainGenerator {
 		//let i = util::ctoi(x,y,128);
 		//println!("{}, {} = {}", x, y, i);
 		for (i, tile) in tiles.iter().enumerate() {
-			let (x,y) = util::itoc(i, 128);
-			if x > 0 && x < 127 && y > 0 && y < 127 {
+			let (x,y) = util::itoc(i, tiles.width());
+			if x > 0 && x < tiles.width() - 1 && y > 0 && y < tiles.height() - 1 {
 				(*new).push(Self::smooth_tile(
 					tiles[(x,y)],
 					tiles[(x,y+1)],
@@ -258,13 +401,11 @@ impl TerrainGenerator {
 				(*new).push(tiles[(x,y)]);
 			}
 		}
-		//TODO fix this, this is just for testing
-		Self::add_roads(&mut new);
 		new
 	}
 
-	fn add_roads(tiles: &mut Vec2D<u32>) {
-		let mut road_generator = RoadGenerator::new(&[1,2,3,4], 128, 128, 5);	//TODO Change seed
+	fn add_roads(tiles: &mut Vec2D<u32>, seed: &[usize], town: bool) -> (Vec<Building>, Vec<MapObject>) {
+		let mut road_generator = RoadGenerator::new(seed, tiles.width(), tiles.height(), 5);
 		road_generator.generate();
 		//road_generator.print_roads();
 		for road in road_generator.roads.iter() {
@@ -279,6 +420,22 @@ impl TerrainGenerator {
 				}
 			}
 		}
+		if town {
+			Self::add_town(tiles, seed, &road_generator)
+		}
+		else {
+			(Vec::new(), Vec::new())
+		}
+	}
+
+	fn add_town(tiles: &mut Vec2D<u32>, seed: &[usize], road_generator: &RoadGenerator) -> (Vec<Building>, Vec<MapObject>) {
+		let zone_generator = ZoneGenerator::new(&sub_seed(seed, ZONE_STREAM), tiles.width(), tiles.height());
+		let blocks = zone_generator.zone_blocks(road_generator);
+		let mut town_generator = TownGenerator::new(&sub_seed(seed, TOWN_STREAM));
+		let buildings = town_generator.generate(&blocks, tiles);
+		TownGenerator::stamp(tiles, &blocks, &buildings);
+		let markers = town_generator.markers(&blocks, &buildings, tiles);
+		(buildings, markers)
 	}
 
 	//TODO Do something about this.... OMFG
@@ -339,15 +496,15 @@ impl TerrainGenerator {
 	}
 }
 
-struct Rect {
-	x1: usize,
-	y1: usize,
-	x2: usize,
-	y2: usize,
+pub struct Rect {
+	pub x1: usize,
+	pub y1: usize,
+	pub x2: usize,
+	pub y2: usize,
 }
 
 impl Rect {
-	fn new(x1: usize, y1: usize, x2: usize, y2: usize) -> Self {
+	pub fn new(x1: usize, y1: usize, x2: usize, y2: usize) -> Self {
 		Rect {
 			x1: x1,
 			y1: y1,
@@ -356,14 +513,18 @@ impl Rect {
 		}
 	}
 
-	fn width(&self) -> usize {
+	pub fn width(&self) -> usize {
 		self.x2 - self.x1
 	}
 
-	fn height(&self) -> usize {
+	pub fn height(&self) -> usize {
 		self.y2 - self.y1
 	}
 
+	pub fn center(&self) -> (usize, usize) {
+		((self.x1 + self.x2) / 2, (self.y1 + self.y2) / 2)
+	}
+
 	fn split_vertical(&self, x: usize) -> (Rect, Rect) {
 		let left = Rect {
 			x1: self.x1,
@@ -406,6 +567,7 @@ pub struct RoadGenerator {
 	height: usize,
 	rng: StdRng,
 	roads: Vec<Rect>,
+	blocks: Vec<Rect>,
 	splits: usize,
 }
 
@@ -416,6 +578,7 @@ impl RoadGenerator {
 			height: height,
 			rng: SeedableRng::from_seed(seed),
 			roads: Vec::new(),
+			blocks: Vec::new(),
 			splits: splits,
 		}
 	}
@@ -442,9 +605,21 @@ impl RoadGenerator {
 				process_list.push((true, bottom));
 				self.roads.push(Rect::new(rect.x1, rect.y1+y, rect.x2, rect.y1+y));
 			}
+			else {
+				self.blocks.push(rect);
+			}
 		}
 	}
 
+	pub fn roads(&self) -> &Vec<Rect> {
+		&self.roads
+	}
+
+	/// The leaf cells of the split, i.e. the blocks enclosed by roads
+	pub fn blocks(&self) -> &Vec<Rect> {
+		&self.blocks
+	}
+
 	pub fn print_roads(&self) {
 		for road in self.roads.iter() {
 			if road.x1 == road.x2 {
@@ -455,33 +630,4 @@ impl RoadGenerator {
 			}
 		}
 	}
-}	
-
-pub struct Building {
-	position: Vector2u,
-	tiles: Vec2D<u32>,	
-	collision: CollisionLayer,	
 }
-
-impl Building {
-	pub fn new(x: u32, y: u32, width: usize, height: usize) -> Self {
-		let mut tiles: Vec<u32> = Vec::with_capacity(width * height);
-		for _ in 0..(width * height) {
-			tiles.push(Building);
-		}
-		let mut collision = CollisionLayer::new(width, height);
-		for x in 0..width {
-			collision.set_collision_top(x,0);
-			collision.set_collision_bottom(x, height);
-		}
-		for y in 0..height {
-			collision.set_collision_left(0,y);
-			collision.set_collision_right(width, y);
-		}
-		Building {
-			position: Vector2u::new(x,y),
-			tiles: Vec2D::from_vec(width, height, tiles),
-			collision: collision,
-		}
-	}
-}
\ No newline at end of file
@@ -3,28 +3,60 @@ pub mod world;
 pub mod camera;
 pub mod map;
 pub mod map_generator;
+pub mod building;
+pub mod zoning;
+pub mod objects;
+pub mod scatter;
+pub mod connectivity;
+pub mod ascii;
+pub mod tileset;
+pub mod image_export;
+pub mod image_import;
+pub mod tiled;
+pub mod binary_map;
+pub mod movement;
+pub mod raycast;
+pub mod fov;
+pub mod pathfinding;
+pub mod flowfield;
+pub mod hpa;
+pub mod spatial;
+pub mod ecs;
+pub mod components;
+pub mod systems;
+pub mod picking;
+pub mod timestep;
+pub mod simulation;
+pub mod world_renderer;
+pub mod replay;
 
 use sfml::graphics::{RenderWindow, Color, RenderTarget, View};
-use sfml::window::{VideoMode, ContextSettings, window_style, event};
+use sfml::window::{VideoMode, ContextSettings, window_style, event, MouseButton};
 //use sfml::window::keyboard::{Key};
 use sfml::system::{Vector2f, Clock};
-use game::world::World;
-/*use game::world_renderer::WorldRenderer;
-use game::input::PlayerInputHandler;
+/*use game::input::PlayerInputHandler;
 use game::map_generator::MapGenerator;
 use util::noise::{Noise2D, Noise2DGenerator, NoiseType};
 use util::grid::Grid;*/
 
 use game::input::PlayerInputHandler;
+use game::simulation::Simulation;
+use game::world_renderer::WorldRenderer;
 
 pub struct Game {
 	window: RenderWindow,
-	world: World,
+	simulation: Simulation,
+	renderer: WorldRenderer,
 	player_input_handler: PlayerInputHandler,
 }
 
 impl Game {
 	pub fn new(width: u32, height: u32, name: &'static str, fullscreen: bool) -> Self {
+		Game::new_simulation(width, height, name, fullscreen, Simulation::new_default())
+	}
+
+	/// Shows a simulation that was set up elsewhere, for recording or playing back replays
+	pub fn new_simulation(width: u32, height: u32, name: &'static str, fullscreen: bool, simulation: Simulation) -> Self {
 		let settings: ContextSettings = ContextSettings::default();
 		let style = match fullscreen {
 			true => window_style::FULLSCREEN,
@@ -35,7 +67,8 @@ impl Game {
 
 		let mut game =  Game {
 			window: window,
-			world: World::new_empty(),
+			renderer: WorldRenderer::new(simulation.get_world()),
+			simulation: simulation,
 			player_input_handler: PlayerInputHandler::new(),
 		};
 		game.window.set_vertical_sync_enabled(false);
@@ -49,26 +82,36 @@ impl Game {
 		let mut delta = 0.0;
 		while self.window.is_open() {
 			self.handle_input();
-			self.update(delta);
+			// The simulation only ever sees whole ticks, drawing blends between the last two
+			self.simulation.advance(delta);
+			let alpha = self.simulation.alpha();
+			self.simulation.get_mut_world().interpolate(alpha);
+			self.renderer.update(self.simulation.get_world(), alpha);
 			//println!("FPS: {}", 1.0/delta);
-			self.window.set_view(self.world.get_camera().get_view());
+			self.window.set_view(self.simulation.get_world().get_camera().get_view());
 			self.render();
 			delta = clock.restart().as_seconds();
 		}
 	}
 
+	pub fn get_mut_simulation(&mut self) -> &mut Simulation {
+		&mut self.simulation
+	}
+
+	/// Left click selects the tile under the cursor, right click clears the selection
 	fn handle_input(&mut self) {
-		self.player_input_handler.handle_input(&mut self.world, &mut self.window); //TODO Replace self with world
+		for click in self.player_input_handler.handle_input(&mut self.simulation, &mut self.window) {
+			match click.button {
+				MouseButton::Left => self.renderer.set_selection(click.pick.tile),
+				MouseButton::Right => self.renderer.set_selection(None),
+				_ => (),
+			}
+		}
 	}
 
 	fn render(&mut self) {
 		self.window.clear(&Color::black());
-		self.window.draw(&self.world);
-		//TODO Render game objects
+		self.window.draw(&self.renderer);
 		self.window.display();
 	}
-
-	fn update(&mut self, delta: f32) {
-		self.world.update(delta);
-	}
 }
\ No newline at end of file
@@ -0,0 +1,313 @@
+use sfml::system::Vector2f;
+use game::map::Map;
+
+const WALL_THICKNESS: f32 = 0.05;		// CollisionLayer edges are treated as thin boxes
+const RESOLVE_ITERATIONS: usize = 4;
+const MAX_STEPS: usize = 64;			// per move, longer motions are cut short
+const EPSILON: f32 = 0.0001;
+
+#[derive(Clone, Copy, Debug)]
+pub enum BodyShape {
+	Circle(f32),			// radius
+	Aabb(f32, f32),			// half width, half height
+}
+
+impl BodyShape {
+	/// Half size of the bounding box
+	pub fn extents(&self) -> Vector2f {
+		match *self {
+			BodyShape::Circle(radius) => Vector2f::new(radius, radius),
+			BodyShape::Aabb(half_width, half_height) => Vector2f::new(half_width, half_height),
+		}
+	}
+
+	fn min_extent(&self) -> f32 {
+		let extents = self.extents();
+		extents.x.min(extents.y)
+	}
+}
+
+/// Anything that moves through the map, position is the centre in tiles
+pub struct Body {
+	pub position: Vector2f,
+	pub velocity: Vector2f,			// tiles per second
+	pub shape: BodyShape,
+}
+
+impl Body {
+	pub fn new_circle(x: f32, y: f32, radius: f32) -> Self {
+		Body {
+			position: Vector2f::new(x, y),
+			velocity: Vector2f::new(0.0, 0.0),
+			shape: BodyShape::Circle(radius),
+		}
+	}
+
+	pub fn new_aabb(x: f32, y: f32, width: f32, height: f32) -> Self {
+		Body {
+			position: Vector2f::new(x, y),
+			velocity: Vector2f::new(0.0, 0.0),
+			shape: BodyShape::Aabb(width / 2.0, height / 2.0),
+		}
+	}
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ContactKind {
+	Tile(usize, usize),				// a blocking tile
+	VerticalWall(usize, usize),		// an edge in CollisionLayer::vertical_edges
+	HorizontalWall(usize, usize),
+	Object(usize),					// index into the map ObjectLayer, props that block movement
+	Border,
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct Contact {
+	pub kind: ContactKind,
+	pub normal: Vector2f,			// points away from the obstacle
+}
+
+pub struct MoveResult {
+	pub contacts: Vec<Contact>,
+	pub velocity: Vector2f,			// what is left of the velocity after sliding along walls
+}
+
+/// Moves bodies by their velocity, blocked by blocking tiles, walls, props and the map border
+pub struct MovementSolver {
+	pub max_step: f32,			// longest distance in tiles moved before collisions are resolved
+}
+
+impl MovementSolver {
+	pub fn new() -> Self {
+		MovementSolver {
+			max_step: 0.25,
+		}
+	}
+
+	/// The motion is split into steps shorter than half the body, so no step can cross a wall.
+	/// At most MAX_STEPS steps are taken, a body that would move further stops short
+	pub fn move_body(&self, map: &Map, body: &mut Body, delta: f32) -> MoveResult {
+		let motion = body.velocity * delta;
+		let length = (motion.x * motion.x + motion.y * motion.y).sqrt();
+		let step_limit = self.max_step.min(body.shape.min_extent() * 0.5).max(0.001);
+		let max_length = step_limit * MAX_STEPS as f32;
+		let time = if length > max_length { delta * max_length / length } else { delta };
+		let steps = ((length.min(max_length) / step_limit).ceil() as usize).max(1).min(MAX_STEPS);
+		let mut result = MoveResult {
+			contacts: Vec::new(),
+			velocity: body.velocity,
+		};
+		for _ in 0..steps {
+			let step = result.velocity * (time / steps as f32);
+			match body.shape {
+				BodyShape::Aabb(half_width, half_height) => {
+					// One axis at a time, so boxes slide over the seams between walls
+					body.position.x += step.x;
+					Self::resolve_aabb_axis(map, body, half_width, half_height, step.x, true, &mut result);
+					body.position.y += step.y;
+					Self::resolve_aabb_axis(map, body, half_width, half_height, step.y, false, &mut result);
+				},
+				BodyShape::Circle(radius) => {
+					body.position = body.position + step;
+					Self::resolve_circle(map, body, radius, step, &mut result);
+				},
+			}
+		}
+		result
+	}
+
+	fn resolve_aabb_axis(map: &Map, body: &mut Body, half_width: f32, half_height: f32, motion: f32, horizontal: bool, result: &mut MoveResult) {
+		if motion == 0.0 {
+			return;
+		}
+		let (min, max) = Self::bounds(body);
+		for (kind, rect) in Self::obstacles(map, min, max) {
+			let (x0, y0, x1, y1) = rect;
+			let (min, max) = Self::bounds(body);
+			let overlaps = max.x - x0 > EPSILON && x1 - min.x > EPSILON && max.y - y0 > EPSILON && y1 - min.y > EPSILON;
+			if !overlaps {
+				continue;
+			}
+			let normal = if horizontal {
+				if motion > 0.0 {
+					body.position.x = x0 - half_width;
+					Vector2f::new(-1.0, 0.0)
+				}
+				else {
+					body.position.x = x1 + half_width;
+					Vector2f::new(1.0, 0.0)
+				}
+			}
+			else {
+				if motion > 0.0 {
+					body.position.y = y0 - half_height;
+					Vector2f::new(0.0, -1.0)
+				}
+				else {
+					body.position.y = y1 + half_height;
+					Vector2f::new(0.0, 1.0)
+				}
+			};
+			Self::add_contact(result, kind, normal);
+		}
+	}
+
+	/// Pushes the circle out of the deepest obstacle until it is free
+	fn resolve_circle(map: &Map, body: &mut Body, radius: f32, step: Vector2f, result: &mut MoveResult) {
+		for _ in 0..RESOLVE_ITERATIONS {
+			let (min, max) = Self::bounds(body);
+			let mut deepest: Option<(ContactKind, Vector2f, f32)> = None;
+			for (kind, rect) in Self::obstacles(map, min, max) {
+				if let Some((normal, depth)) = Self::circle_penetration(body.position, radius, rect, step) {
+					if depth > EPSILON && deepest.map(|d| depth > d.2).unwrap_or(true) {
+						deepest = Some((kind, normal, depth));
+					}
+				}
+			}
+			match deepest {
+				Some((kind, normal, depth)) => {
+					body.position = body.position + normal * depth;
+					Self::add_contact(result, kind, normal);
+				},
+				None => break,
+			}
+		}
+	}
+
+	fn circle_penetration(center: Vector2f, radius: f32, rect: (f32, f32, f32, f32), step: Vector2f) -> Option<(Vector2f, f32)> {
+		let (x0, y0, x1, y1) = rect;
+		let closest = Vector2f::new(center.x.max(x0).min(x1), center.y.max(y0).min(y1));
+		let offset = center - closest;
+		let distance_squared = offset.x * offset.x + offset.y * offset.y;
+		if distance_squared >= radius * radius {
+			return None;
+		}
+		if distance_squared > EPSILON * EPSILON {
+			let distance = distance_squared.sqrt();
+			return Some((offset / distance, radius - distance));
+		}
+		// The centre is inside, leave through the nearest side, preferring the side the body came from
+		let sides = [
+			(center.x - x0, Vector2f::new(-1.0, 0.0)),
+			(x1 - center.x, Vector2f::new(1.0, 0.0)),
+			(center.y - y0, Vector2f::new(0.0, -1.0)),
+			(y1 - center.y, Vector2f::new(0.0, 1.0)),
+		];
+		let mut best = sides[0];
+		for &side in sides.iter().skip(1) {
+			let facing = step.x * side.1.x + step.y * side.1.y < 0.0;
+			if side.0 < best.0 - EPSILON || (side.0 < best.0 + EPSILON && facing) {
+				best = side;
+			}
+		}
+		Some((best.1, best.0 + radius))
+	}
+
+	/// Records the contact and removes the part of the velocity that points into the obstacle
+	fn add_contact(result: &mut MoveResult, kind: ContactKind, normal: Vector2f) {
+		let into = result.velocity.x * normal.x + result.velocity.y * normal.y;
+		if into < 0.0 {
+			result.velocity = result.velocity - normal * into;
+		}
+		if !result.contacts.iter().any(|c| c.kind == kind) {
+			result.contacts.push(Contact {
+				kind: kind,
+				normal: normal,
+			});
+		}
+	}
+
+	fn bounds(body: &Body) -> (Vector2f, Vector2f) {
+		let extents = body.shape.extents();
+		(body.position - extents, body.position + extents)
+	}
+
+	/// Boxes (x0, y0, x1, y1) of everything solid near the given area, props count as the box around their footprint
+	fn obstacles(map: &Map, min: Vector2f, max: Vector2f) -> Vec<(ContactKind, (f32, f32, f32, f32))> {
+		let (width, height) = (map.width(), map.height());
+		let (w, h) = (width as f32, height as f32);
+		let mut obstacles = vec![
+			(ContactKind::Border, (-1.0, -1.0, 0.0, h + 1.0)),
+			(ContactKind::Border, (w, -1.0, w + 1.0, h + 1.0)),
+			(ContactKind::Border, (-1.0, -1.0, w + 1.0, 0.0)),
+			(ContactKind::Border, (-1.0, h, w + 1.0, h + 1.0)),
+		];
+		let clamp = |value: f32, limit: usize| (value.max(0.0) as usize).min(limit);
+		let (tx0, ty0) = (clamp(min.x.floor() - 1.0, width), clamp(min.y.floor() - 1.0, height));
+		let (tx1, ty1) = (clamp(max.x.floor() + 2.0, width), clamp(max.y.floor() + 2.0, height));
+		let collision = map.get_collision();
+		let half = WALL_THICKNESS / 2.0;
+		for y in ty0..ty1 {
+			for x in tx0..tx1 {
+				let (fx, fy) = (x as f32, y as f32);
+				if map.is_blocking(x, y) {
+					obstacles.push((ContactKind::Tile(x, y), (fx, fy, fx + 1.0, fy + 1.0)));
+				}
+				if collision.vertical_edges()[(x, y)] {
+					obstacles.push((ContactKind::VerticalWall(x, y), (fx - half, fy, fx + half, fy + 1.0)));
+				}
+				if collision.horizontal_edges()[(x, y)] {
+					obstacles.push((ContactKind::HorizontalWall(x, y), (fx, fy - half, fx + 1.0, fy + half)));
+				}
+			}
+			if tx1 == width && collision.vertical_edges()[(width, y)] {
+				obstacles.push((ContactKind::VerticalWall(width, y), (w - half, y as f32, w + half, y as f32 + 1.0)));
+			}
+		}
+		if ty1 == height {
+			for x in tx0..tx1 {
+				if collision.horizontal_edges()[(x, height)] {
+					obstacles.push((ContactKind::HorizontalWall(x, height), (x as f32, h - half, x as f32 + 1.0, h + half)));
+				}
+			}
+		}
+		let center = (min + max) / 2.0;
+		let radius = (max.x - min.x).hypot(max.y - min.y) / 2.0;
+		let objects = map.get_objects();
+		for i in objects.indices_in_circle(center.x, center.y, radius) {
+			let object = objects.get(i);
+			if object.kind.blocks_movement() {
+				let r = object.radius();
+				obstacles.push((ContactKind::Object(i), (object.x - r, object.y - r, object.x + r, object.y + r)));
+			}
+		}
+		obstacles
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use game::objects::{ObjectLayer, MapObject, PropKind};
+
+	fn open_map(width: usize, height: usize) -> Map {
+		Map::new_init(width, height, vec![0; width * height], vec![0; width * height], vec![0; width * height])
+	}
+
+	#[test]
+	fn blocking_props_stop_bodies() {
+		let mut map = open_map(8, 3);
+		let mut objects = ObjectLayer::new();
+		objects.add(MapObject::new(PropKind::Bush, 2.5, 1.5));
+		objects.add(MapObject::new(PropKind::Rock, 5.5, 1.5));
+		map.set_objects(objects);
+		let mut body = Body::new_circle(0.5, 1.5, 0.3);
+		body.velocity = Vector2f::new(8.0, 0.0);
+		let result = MovementSolver::new().move_body(&map, &mut body, 1.0);
+		// The bush is walked through, the rock is not
+		assert!((body.position.x - (5.5 - 0.35 - 0.3)).abs() < 0.01);
+		assert!(result.contacts.iter().any(|c| c.kind == ContactKind::Object(1)));
+		assert!(!result.contacts.iter().any(|c| c.kind == ContactKind::Object(0)));
+	}
+
+	#[test]
+	fn huge_velocity_is_cut_short() {
+		let map = open_map(200, 1);
+		let mut body = Body::new_circle(0.5, 0.5, 0.25);
+		body.velocity = Vector2f::new(1e9, 0.0);
+		MovementSolver::new().move_body(&map, &mut body, 1.0);
+		let step_limit = 0.25f32.min(0.125);
+		assert!(body.position.x <= 0.5 + step_limit * MAX_STEPS as f32 + EPSILON);
+		assert!(body.position.x > 1.0);
+	}
+}
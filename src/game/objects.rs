@@ -0,0 +1,200 @@
+use std::slice::Iter;
+
+/// What a loot container is filled with, picked by the zone of its building
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum LootTable {
+	Residential,
+	Commercial,
+	Industrial,
+	Park,
+	Farm,
+}
+
+const RESIDENTIAL_LOOT: &'static [(&'static str, u32)] = &[("canned_food", 3), ("bandage", 2), ("kitchen_knife", 1)];
+const COMMERCIAL_LOOT: &'static [(&'static str, u32)] = &[("canned_food", 5), ("water_bottle", 4), ("painkillers", 2)];
+const INDUSTRIAL_LOOT: &'static [(&'static str, u32)] = &[("scrap_metal", 6), ("crowbar", 1), ("fuel_can", 1)];
+const PARK_LOOT: &'static [(&'static str, u32)] = &[("water_bottle", 1), ("stick", 2)];
+const FARM_LOOT: &'static [(&'static str, u32)] = &[("seeds", 4), ("pitchfork", 1), ("canned_food", 2)];
+
+impl LootTable {
+	/// Items and counts a container of this table starts with
+	pub fn items(&self) -> &'static [(&'static str, u32)] {
+		match *self {
+			LootTable::Residential => RESIDENTIAL_LOOT,
+			LootTable::Commercial => COMMERCIAL_LOOT,
+			LootTable::Industrial => INDUSTRIAL_LOOT,
+			LootTable::Park => PARK_LOOT,
+			LootTable::Farm => FARM_LOOT,
+		}
+	}
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum PropKind {
+	Tree,
+	Bush,
+	Rock,
+	Car,
+	Debris,
+	ZombieSpawn,				// marker, World puts a zombie here
+	Loot(LootTable),			// marker, World puts a container filled from the table here
+}
+
+impl PropKind {
+	/// Radius of the collision footprint in tiles
+	pub fn footprint(&self) -> f32 {
+		match *self {
+			PropKind::Tree => 0.4,
+			PropKind::Bush => 0.3,
+			PropKind::Rock => 0.35,
+			PropKind::Car => 0.9,
+			PropKind::Debris => 0.25,
+			PropKind::ZombieSpawn | PropKind::Loot(_) => 0.0,
+		}
+	}
+
+	pub fn name(&self) -> &'static str {
+		match *self {
+			PropKind::Tree => "tree",
+			PropKind::Bush => "bush",
+			PropKind::Rock => "rock",
+			PropKind::Car => "car",
+			PropKind::Debris => "debris",
+			PropKind::ZombieSpawn => "zombie_spawn",
+			PropKind::Loot(LootTable::Residential) => "loot_residential",
+			PropKind::Loot(LootTable::Commercial) => "loot_commercial",
+			PropKind::Loot(LootTable::Industrial) => "loot_industrial",
+			PropKind::Loot(LootTable::Park) => "loot_park",
+			PropKind::Loot(LootTable::Farm) => "loot_farm",
+		}
+	}
+
+	pub fn from_name(name: &str) -> Option<PropKind> {
+		match name {
+			"tree" => Some(PropKind::Tree),
+			"bush" => Some(PropKind::Bush),
+			"rock" => Some(PropKind::Rock),
+			"car" => Some(PropKind::Car),
+			"debris" => Some(PropKind::Debris),
+			"zombie_spawn" => Some(PropKind::ZombieSpawn),
+			"loot_residential" => Some(PropKind::Loot(LootTable::Residential)),
+			"loot_commercial" => Some(PropKind::Loot(LootTable::Commercial)),
+			"loot_industrial" => Some(PropKind::Loot(LootTable::Industrial)),
+			"loot_park" => Some(PropKind::Loot(LootTable::Park)),
+			"loot_farm" => Some(PropKind::Loot(LootTable::Farm)),
+			_ => None,
+		}
+	}
+
+	pub fn blocks_movement(&self) -> bool {
+		match *self {
+			PropKind::Bush | PropKind::Debris | PropKind::ZombieSpawn | PropKind::Loot(_) => false,
+			_ => true,
+		}
+	}
+}
+
+pub struct MapObject {
+	pub kind: PropKind,
+	pub x: f32,
+	pub y: f32,
+}
+
+impl MapObject {
+	pub fn new(kind: PropKind, x: f32, y: f32) -> Self {
+		MapObject {
+			kind: kind,
+			x: x,
+			y: y,
+		}
+	}
+
+	pub fn radius(&self) -> f32 {
+		self.kind.footprint()
+	}
+
+	pub fn overlaps_circle(&self, x: f32, y: f32, radius: f32) -> bool {
+		let dx = self.x - x;
+		let dy = self.y - y;
+		let r = self.radius() + radius;
+		dx * dx + dy * dy < r * r
+	}
+}
+
+pub struct ObjectLayer {
+	objects: Vec<MapObject>,
+	cells: Vec<Vec<usize>>,		// object indices by the tile their centre is in, row by row
+	columns: usize,				// of cells, grows with the objects
+	rows: usize,
+	max_radius: f32,
+}
+
+impl ObjectLayer {
+	pub fn new() -> Self {
+		ObjectLayer {
+			objects: Vec::new(),
+			cells: Vec::new(),
+			columns: 0,
+			rows: 0,
+			max_radius: 0.0,
+		}
+	}
+
+	pub fn add(&mut self, object: MapObject) {
+		let (x, y) = Self::cell(object.x, object.y);
+		if x >= self.columns || y >= self.rows {
+			let columns = self.columns.max(x + 1).max(16).next_power_of_two();
+			let rows = self.rows.max(y + 1).max(16).next_power_of_two();
+			self.resize(columns, rows);
+		}
+		self.max_radius = self.max_radius.max(object.radius());
+		self.cells[y * self.columns + x].push(self.objects.len());
+		self.objects.push(object);
+	}
+
+	fn cell(x: f32, y: f32) -> (usize, usize) {
+		(x.max(0.0) as usize, y.max(0.0) as usize)
+	}
+
+	fn resize(&mut self, columns: usize, rows: usize) {
+		self.columns = columns;
+		self.rows = rows;
+		self.cells = vec![Vec::new(); columns * rows];
+		for (i, object) in self.objects.iter().enumerate() {
+			let (x, y) = Self::cell(object.x, object.y);
+			self.cells[y * columns + x].push(i);
+		}
+	}
+
+	pub fn len(&self) -> usize {
+		self.objects.len()
+	}
+
+	pub fn get(&self, i: usize) -> &MapObject {
+		&self.objects[i]
+	}
+
+	pub fn iter(&self) -> Iter<MapObject> {
+		self.objects.iter()
+	}
+
+	/// Objects whose footprint overlaps the circle
+	pub fn query_circle(&self, x: f32, y: f32, radius: f32) -> Vec<&MapObject> {
+		self.indices_in_circle(x, y, radius).into_iter().map(|i| &self.objects[i]).collect()
+	}
+
+	/// Like query_circle but the indices, in the order the objects were added
+	pub fn indices_in_circle(&self, x: f32, y: f32, radius: f32) -> Vec<usize> {
+		let reach = radius + self.max_radius;
+		let (x0, y0) = Self::cell(x - reach, y - reach);
+		let (x1, y1) = Self::cell(x + reach, y + reach);
+		let mut found = Vec::new();
+		for cy in y0..(y1 + 1).min(self.rows) {
+			for cx in x0..(x1 + 1).min(self.columns) {
+				found.extend(self.cells[cy * self.columns + cx].iter().cloned().filter(|&i| self.objects[i].overlaps_circle(x, y, radius)));
+			}
+		}
+		found.sort();
+		found
+	}
+}
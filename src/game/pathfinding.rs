@@ -0,0 +1,342 @@
+use std::cmp::Ordering;
+use std::collections::BinaryHeap;
+use std::f32;
+use game::map::Map;
+use game::map_generator::{ShallowWater, Road, Snow, Forest, Dirt, Sand, Swamp};
+use util::Vec2D;
+
+const SQRT_2: f32 = 1.41421356;
+
+/// What a kind of walker can enter and what it costs
+pub struct MovementProfile {
+	pub name: &'static str,
+	pub diagonal: bool,				// 8 instead of 4 directions
+	pub cut_corners: bool,			// diagonal steps only need one of the two side tiles open
+	pub impassable: Vec<u32>,		// tiles this profile can not enter on top of the blocking ones
+	pub costs: Vec<(u32, f32)>,		// replaces the tileset cost of these tiles
+}
+
+impl MovementProfile {
+	pub fn new_human() -> Self {
+		MovementProfile {
+			name: "human",
+			diagonal: true,
+			cut_corners: false,
+			impassable: Vec::new(),
+			costs: Vec::new(),
+		}
+	}
+
+	/// Zombies shamble straight through forest but hate water
+	pub fn new_zombie() -> Self {
+		MovementProfile {
+			name: "zombie",
+			diagonal: true,
+			cut_corners: true,
+			impassable: Vec::new(),
+			costs: vec![(Forest, 1.0), (ShallowWater, 6.0), (Road, 1.0)],
+		}
+	}
+
+	pub fn new_vehicle() -> Self {
+		MovementProfile {
+			name: "vehicle",
+			diagonal: false,
+			cut_corners: false,
+			impassable: vec![ShallowWater, Snow, Forest, Swamp],
+			costs: vec![(Road, 0.2), (Dirt, 1.0), (Sand, 2.0)],
+		}
+	}
+
+	pub fn from_name(name: &str) -> Option<Self> {
+		match name {
+			"human" => Some(MovementProfile::new_human()),
+			"zombie" => Some(MovementProfile::new_zombie()),
+			"vehicle" => Some(MovementProfile::new_vehicle()),
+			_ => None,
+		}
+	}
+
+	/// Cost of entering the tile, None if it can not be entered
+	pub fn tile_cost(&self, map: &Map, x: usize, y: usize) -> Option<f32> {
+		if map.is_blocking(x, y) {
+			return None;
+		}
+		let tile = map.top_tile(x, y);
+		if self.impassable.contains(&tile) {
+			return None;
+		}
+		match self.costs.iter().find(|&&(t, _)| t == tile) {
+			Some(&(_, cost)) => Some(cost),
+			None => Some(map.get_tileset().cost(tile)),
+		}
+	}
+
+	/// Lowest cost of any tile, keeps the A* heuristic admissible
+	pub fn min_cost(&self, map: &Map) -> f32 {
+		let tileset = map.get_tileset();
+		let mut min = f32::INFINITY;
+		for (id, info) in tileset.tiles().iter().enumerate() {
+			let id = id as u32;
+			if info.blocking || self.impassable.contains(&id) {
+				continue;
+			}
+			let cost = self.costs.iter().find(|&&(t, _)| t == id).map(|&(_, c)| c).unwrap_or(info.cost);
+			min = min.min(cost);
+		}
+		if min.is_finite() { min.max(0.01) } else { 1.0 }
+	}
+}
+
+pub struct Path {
+	pub tiles: Vec<(usize, usize)>,		// from start to goal, both included
+	pub cost: f32,
+}
+
+/// Tiles reachable in one step and the cost of the step
+pub fn neighbours(map: &Map, x: usize, y: usize, profile: &MovementProfile) -> Vec<((usize, usize), f32)> {
+	let mut result = Vec::with_capacity(8);
+	for &(dx, dy) in [(1, 0), (-1, 0), (0, 1), (0, -1)].iter() {
+		if let Some(next) = orthogonal_step(map, x, y, dx, dy, profile) {
+			result.push(next);
+		}
+	}
+	if profile.diagonal {
+		for &(dx, dy) in [(1, 1), (1, -1), (-1, 1), (-1, -1)].iter() {
+			let horizontal = orthogonal_step(map, x, y, dx, 0, profile).and_then(|((hx, hy), _)| orthogonal_step(map, hx, hy, 0, dy, profile));
+			let vertical = orthogonal_step(map, x, y, 0, dy, profile).and_then(|((vx, vy), _)| orthogonal_step(map, vx, vy, dx, 0, profile));
+			let open = if profile.cut_corners {
+				horizontal.or(vertical)
+			}
+			else {
+				horizontal.and(vertical)
+			};
+			if let Some((tile, cost)) = open {
+				result.push((tile, cost * SQRT_2));
+			}
+		}
+	}
+	result
+}
+
+/// One step left, right, up or down, through no wall and onto a passable tile
+pub fn orthogonal_step(map: &Map, x: usize, y: usize, dx: isize, dy: isize, profile: &MovementProfile) -> Option<((usize, usize), f32)> {
+	let nx = x as isize + dx;
+	let ny = y as isize + dy;
+	if nx < 0 || ny < 0 || nx >= map.width() as isize || ny >= map.height() as isize {
+		return None;
+	}
+	let collision = map.get_collision();
+	let open = match (dx, dy) {
+		(1, _) => collision.can_walk_right(x, y),
+		(-1, _) => collision.can_walk_left(x, y),
+		(_, 1) => collision.can_walk_down(x, y),
+		_ => collision.can_walk_up(x, y),
+	};
+	if !open {
+		return None;
+	}
+	let (nx, ny) = (nx as usize, ny as usize);
+	profile.tile_cost(map, nx, ny).map(|cost| ((nx, ny), cost))
+}
+
+/// Octile distance, or Manhattan distance without diagonal moves
+pub fn heuristic(a: (usize, usize), b: (usize, usize), profile: &MovementProfile) -> f32 {
+	let dx = (a.0 as f32 - b.0 as f32).abs();
+	let dy = (a.1 as f32 - b.1 as f32).abs();
+	if profile.diagonal {
+		dx.max(dy) + (SQRT_2 - 1.0) * dx.min(dy)
+	}
+	else {
+		dx + dy
+	}
+}
+
+/// Open list entry, ordered so the BinaryHeap pops the lowest cost first
+#[derive(PartialEq)]
+pub struct Candidate {
+	pub cost: f32,
+	pub tile: (usize, usize),
+}
+
+impl Eq for Candidate {}
+
+impl Ord for Candidate {
+	fn cmp(&self, other: &Self) -> Ordering {
+		other.cost.partial_cmp(&self.cost).unwrap_or(Ordering::Equal)
+	}
+}
+
+impl PartialOrd for Candidate {
+	fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+		Some(self.cmp(other))
+	}
+}
+
+pub fn find_path(map: &Map, start: (usize, usize), goal: (usize, usize), profile: &MovementProfile) -> Option<Path> {
+	find_path_within(map, start, goal, profile, (0, 0, map.width(), map.height()))
+}
+
+/// A* that never leaves the bounds x0, y0, x1, y1 exclusive
+pub fn find_path_within(map: &Map, start: (usize, usize), goal: (usize, usize), profile: &MovementProfile, bounds: (usize, usize, usize, usize)) -> Option<Path> {
+	let (x0, y0, x1, y1) = (bounds.0, bounds.1, bounds.2.min(map.width()), bounds.3.min(map.height()));
+	let inside = |tile: (usize, usize)| tile.0 >= x0 && tile.1 >= y0 && tile.0 < x1 && tile.1 < y1;
+	if !inside(start) || !inside(goal) || profile.tile_cost(map, goal.0, goal.1).is_none() {
+		return None;
+	}
+	let (width, height) = (x1 - x0, y1 - y0);
+	let local = |tile: (usize, usize)| (tile.0 - x0, tile.1 - y0);
+	let scale = profile.min_cost(map);
+	let mut costs: Vec2D<f32> = Vec2D::from_vec(width, height, vec![f32::INFINITY; width * height]);
+	let mut came_from: Vec2D<Option<(usize, usize)>> = Vec2D::from_vec(width, height, vec![None; width * height]);
+	let mut open = BinaryHeap::new();
+	costs[local(start)] = 0.0;
+	open.push(Candidate {
+		cost: heuristic(start, goal, profile) * scale,
+		tile: start,
+	});
+	while let Some(Candidate { cost, tile }) = open.pop() {
+		if tile == goal {
+			let mut tiles = vec![goal];
+			let mut current = goal;
+			while let Some(previous) = came_from[local(current)] {
+				tiles.push(previous);
+				current = previous;
+			}
+			tiles.reverse();
+			return Some(Path {
+				tiles: tiles,
+				cost: costs[local(goal)],
+			});
+		}
+		// Skip entries that were pushed before a cheaper way was found
+		if cost > costs[local(tile)] + heuristic(tile, goal, profile) * scale + 0.0001 {
+			continue;
+		}
+		for (next, step) in neighbours(map, tile.0, tile.1, profile) {
+			if !inside(next) {
+				continue;
+			}
+			let new_cost = costs[local(tile)] + step;
+			if new_cost < costs[local(next)] {
+				costs[local(next)] = new_cost;
+				came_from[local(next)] = Some(tile);
+				open.push(Candidate {
+					cost: new_cost + heuristic(next, goal, profile) * scale,
+					tile: next,
+				});
+			}
+		}
+	}
+	None
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use game::map::CollisionLayer;
+	use game::map_generator::{Grass, Building};
+
+	/// '.' grass, '#' building, 'r' road, 'f' forest, '~' shallow water
+	fn map_from(rows: &[&str]) -> Map {
+		let tiles: Vec<u32> = rows.iter().flat_map(|row| row.chars()).map(|c| match c {
+			'#' => Building,
+			'r' => Road,
+			'f' => Forest,
+			'~' => ShallowWater,
+			_ => Grass,
+		}).collect();
+		let size = tiles.len();
+		Map::new_init(rows[0].len(), rows.len(), tiles, vec![0; size], vec![0; size])
+	}
+
+	fn close(a: f32, b: f32) -> bool {
+		(a - b).abs() < 0.001
+	}
+
+	#[test]
+	fn straight_and_diagonal_paths() {
+		let map = map_from(&["....", "....", "...."]);
+		let human = MovementProfile::new_human();
+		let path = find_path(&map, (0, 0), (3, 0), &human).unwrap();
+		assert_eq!(path.tiles, vec![(0, 0), (1, 0), (2, 0), (3, 0)]);
+		assert!(close(path.cost, 3.0));
+		let path = find_path(&map, (0, 0), (2, 2), &human).unwrap();
+		assert_eq!(path.tiles, vec![(0, 0), (1, 1), (2, 2)]);
+		assert!(close(path.cost, 2.0 * SQRT_2));
+		let path = find_path(&map, (1, 1), (1, 1), &human).unwrap();
+		assert_eq!(path.tiles, vec![(1, 1)]);
+		assert!(close(path.cost, 0.0));
+	}
+
+	#[test]
+	fn blocking_tiles_are_walked_around() {
+		let map = map_from(&[".#..", ".#..", "...."]);
+		let path = find_path(&map, (0, 0), (2, 0), &MovementProfile::new_human()).unwrap();
+		assert_eq!(path.tiles.first(), Some(&(0, 0)));
+		assert_eq!(path.tiles.last(), Some(&(2, 0)));
+		assert!(path.tiles.iter().all(|&(x, y)| !map.is_blocking(x, y)));
+		assert!(path.tiles.contains(&(1, 2)));
+		assert!(find_path(&map, (0, 0), (1, 0), &MovementProfile::new_human()).is_none());
+	}
+
+	#[test]
+	fn enclosed_goal_is_unreachable() {
+		let map = map_from(&["..#..", "..#..", "..#.."]);
+		assert!(find_path(&map, (0, 1), (4, 1), &MovementProfile::new_zombie()).is_none());
+	}
+
+	#[test]
+	fn walls_block_steps() {
+		let mut map = map_from(&["...", "..."]);
+		// Walls on the right side of column 0, vertical edges are (width + 1) x height
+		map.set_walls(CollisionLayer::from_walls(3, 2, &[1, 5], &[]));
+		assert!(find_path(&map, (0, 0), (2, 0), &MovementProfile::new_human()).is_none());
+		map.set_walls(CollisionLayer::from_walls(3, 2, &[1], &[]));
+		let path = find_path(&map, (0, 0), (2, 0), &MovementProfile::new_human()).unwrap();
+		assert!(path.tiles.contains(&(0, 1)));
+	}
+
+	#[test]
+	fn only_zombies_cut_corners() {
+		let map = map_from(&[".#", ".."]);
+		let human = find_path(&map, (0, 0), (1, 1), &MovementProfile::new_human()).unwrap();
+		assert_eq!(human.tiles, vec![(0, 0), (0, 1), (1, 1)]);
+		let zombie = find_path(&map, (0, 0), (1, 1), &MovementProfile::new_zombie()).unwrap();
+		assert_eq!(zombie.tiles, vec![(0, 0), (1, 1)]);
+	}
+
+	#[test]
+	fn profiles_change_the_route() {
+		let map = map_from(&[
+			"r...",
+			"rfff",
+			"rrrr",
+		]);
+		let vehicle = MovementProfile::new_vehicle();
+		assert!(vehicle.tile_cost(&map, 1, 1).is_none());
+		let path = find_path(&map, (0, 0), (3, 2), &vehicle).unwrap();
+		assert_eq!(path.tiles, vec![(0, 0), (0, 1), (0, 2), (1, 2), (2, 2), (3, 2)]);
+		assert!(close(path.cost, 5.0 * 0.2));
+		assert!(find_path(&map, (0, 0), (2, 1), &vehicle).is_none());
+	}
+
+	#[test]
+	fn search_stays_inside_bounds() {
+		let map = map_from(&[".#.", ".#.", "..."]);
+		let human = MovementProfile::new_human();
+		assert!(find_path(&map, (0, 0), (2, 0), &human).is_some());
+		assert!(find_path_within(&map, (0, 0), (2, 0), &human, (0, 0, 3, 2)).is_none());
+		assert!(find_path_within(&map, (0, 0), (2, 0), &human, (1, 0, 3, 3)).is_none());
+	}
+
+	#[test]
+	fn heuristic_never_overestimates() {
+		let human = MovementProfile::new_human();
+		let vehicle = MovementProfile::new_vehicle();
+		assert!(close(heuristic((0, 0), (3, 1), &human), 2.0 + SQRT_2));
+		assert!(close(heuristic((0, 0), (3, 1), &vehicle), 4.0));
+		let map = map_from(&["r~", ".."]);
+		assert!(close(vehicle.min_cost(&map), 0.2));
+	}
+}
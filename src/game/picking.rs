@@ -0,0 +1,171 @@
+use sfml::system::{Vector2f, Vector2i};
+use sfml::graphics::{RenderTarget, RectangleShape, Shape, Transformable, Color};
+use sfml::window::MouseButton;
+use game::camera::Camera;
+use game::components::Components;
+use game::ecs::Entity;
+use game::map::Map;
+
+const EDGE_TOLERANCE: f32 = 0.2;		// how close to an edge in tiles counts as on it
+pub const PICK_RADIUS: f32 = 1.0;		// largest entity that can be picked, in tiles
+
+/// Window pixel, 0, 0 is the top left corner of the window
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct ScreenPoint {
+	pub x: i32,
+	pub y: i32,
+}
+
+/// Point in the world, one unit per tile
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct WorldPoint {
+	pub x: f32,
+	pub y: f32,
+}
+
+/// A tile on the map
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct TilePoint {
+	pub x: usize,
+	pub y: usize,
+}
+
+/// Edge between two tiles, indexed like CollisionLayer::vertical_edges and horizontal_edges
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum TileEdge {
+	Vertical(usize, usize),			// left side of tile x, y
+	Horizontal(usize, usize),		// top side of tile x, y
+}
+
+impl ScreenPoint {
+	pub fn new(x: i32, y: i32) -> Self {
+		ScreenPoint {
+			x: x,
+			y: y,
+		}
+	}
+
+	/// Through the camera's view as it is drawn to the target
+	pub fn to_world<RT: RenderTarget>(&self, target: &RT, camera: &Camera) -> WorldPoint {
+		WorldPoint::from_vector(target.map_pixel_to_coords(&Vector2i::new(self.x, self.y), camera.get_view()))
+	}
+}
+
+impl WorldPoint {
+	pub fn new(x: f32, y: f32) -> Self {
+		WorldPoint {
+			x: x,
+			y: y,
+		}
+	}
+
+	pub fn from_vector(vector: Vector2f) -> Self {
+		WorldPoint::new(vector.x, vector.y)
+	}
+
+	pub fn to_vector(&self) -> Vector2f {
+		Vector2f::new(self.x, self.y)
+	}
+
+	pub fn to_screen<RT: RenderTarget>(&self, target: &RT, camera: &Camera) -> ScreenPoint {
+		let pixel = target.map_coords_to_pixel(&self.to_vector(), camera.get_view());
+		ScreenPoint::new(pixel.x, pixel.y)
+	}
+
+	/// None off the map
+	pub fn to_tile(&self, map: &Map) -> Option<TilePoint> {
+		if self.x < 0.0 || self.y < 0.0 || self.x >= map.width() as f32 || self.y >= map.height() as f32 {
+			None
+		}
+		else {
+			Some(TilePoint::new(self.x as usize, self.y as usize))
+		}
+	}
+
+	/// The closest edge, if the point is near enough to one that lies on the map
+	pub fn to_edge(&self, map: &Map) -> Option<TileEdge> {
+		let (width, height) = (map.width() as f32, map.height() as f32);
+		let (column, row) = (self.x.round(), self.y.round());
+		let vertical = (self.x - column).abs();
+		let horizontal = (self.y - row).abs();
+		let on_vertical = vertical <= EDGE_TOLERANCE && column >= 0.0 && column <= width && self.y >= 0.0 && self.y < height;
+		let on_horizontal = horizontal <= EDGE_TOLERANCE && row >= 0.0 && row <= height && self.x >= 0.0 && self.x < width;
+		match (on_vertical, on_horizontal) {
+			(true, true) if horizontal < vertical => Some(TileEdge::Horizontal(self.x as usize, row as usize)),
+			(true, _) => Some(TileEdge::Vertical(column as usize, self.y as usize)),
+			(false, true) => Some(TileEdge::Horizontal(self.x as usize, row as usize)),
+			(false, false) => None,
+		}
+	}
+}
+
+impl TilePoint {
+	pub fn new(x: usize, y: usize) -> Self {
+		TilePoint {
+			x: x,
+			y: y,
+		}
+	}
+
+	pub fn top_left(&self) -> WorldPoint {
+		WorldPoint::new(self.x as f32, self.y as f32)
+	}
+}
+
+/// Everything under one point of the world
+#[derive(Clone, Copy, Debug)]
+pub struct Pick {
+	pub point: WorldPoint,
+	pub tile: Option<TilePoint>,
+	pub edge: Option<TileEdge>,
+	pub entity: Option<Entity>,
+}
+
+impl Pick {
+	pub fn new(map: &Map, point: WorldPoint, entity: Option<Entity>) -> Self {
+		Pick {
+			point: point,
+			tile: point.to_tile(map),
+			edge: point.to_edge(map),
+			entity: entity,
+		}
+	}
+}
+
+/// A mouse button press and what was under the cursor
+#[derive(Clone, Copy, Debug)]
+pub struct Click {
+	pub button: MouseButton,
+	pub pick: Pick,
+}
+
+/// Outline just inside the tile, to show the selection
+pub fn draw_tile_outline<RT: RenderTarget>(target: &mut RT, tile: TilePoint) {
+	let mut shape = RectangleShape::new_init(&Vector2f::new(1.0, 1.0)).expect("Could not create RectangleShape");
+	shape.set_position(&tile.top_left().to_vector());
+	shape.set_fill_color(&Color::transparent());
+	shape.set_outline_color(&Color::yellow());
+	shape.set_outline_thickness(-0.08);
+	target.draw(&shape);
+}
+
+/// The candidate whose sprite covers the point, the one with its centre closest if several do
+pub fn entity_at<I: IntoIterator<Item = Entity>>(components: &Components, candidates: I, point: WorldPoint) -> Option<Entity> {
+	let mut best: Option<(Entity, f32)> = None;
+	for entity in candidates {
+		let (position, sprite) = match (components.positions.get(entity), components.sprites.get(entity)) {
+			(Some(position), Some(sprite)) => (position.position, sprite),
+			_ => continue,
+		};
+		let half = sprite.size / 2.0;
+		let offset = point.to_vector() - position;
+		if offset.x.abs() > half || offset.y.abs() > half {
+			continue;
+		}
+		let distance = offset.x * offset.x + offset.y * offset.y;
+		if best.map(|(_, d)| distance < d).unwrap_or(true) {
+			best = Some((entity, distance));
+		}
+	}
+	best.map(|(entity, _)| entity)
+}
@@ -0,0 +1,186 @@
+use std::f32;
+use sfml::system::Vector2f;
+use game::map::Map;
+use game::movement::ContactKind;
+
+pub struct RaycastHit {
+	pub kind: ContactKind,
+	pub point: Vector2f,
+	pub fraction: f32,			// 0 at the start of the ray, 1 at the end
+	pub normal: Vector2f,		// points back along the ray
+}
+
+/// Walks the tiles on the line with DDA (Amanatides & Woo), checking every edge it crosses
+/// against the placed walls and every tile it enters against the tileset's blocks sight flag
+pub fn raycast(map: &Map, from: Vector2f, to: Vector2f) -> Option<RaycastHit> {
+	let (width, height) = (map.width() as isize, map.height() as isize);
+	let direction = to - from;
+	let hit = |kind: ContactKind, fraction: f32, normal: Vector2f| {
+		Some(RaycastHit {
+			kind: kind,
+			point: from + direction * fraction,
+			fraction: fraction,
+			normal: normal,
+		})
+	};
+	let mut x = from.x.floor() as isize;
+	let mut y = from.y.floor() as isize;
+	if x < 0 || y < 0 || x >= width || y >= height {
+		return hit(ContactKind::Border, 0.0, Vector2f::new(0.0, 0.0));
+	}
+	let (step_x, mut t_max_x, t_delta_x) = axis(from.x, direction.x);
+	let (step_y, mut t_max_y, t_delta_y) = axis(from.y, direction.y);
+	let walls = map.get_walls();
+	loop {
+		let fraction = t_max_x.min(t_max_y);
+		if fraction > 1.0 {
+			return None;
+		}
+		if t_max_x < t_max_y {
+			let edge = if step_x > 0 { x + 1 } else { x };
+			let normal = Vector2f::new(-step_x as f32, 0.0);
+			if walls.vertical_edges()[(edge as usize, y as usize)] {
+				return hit(ContactKind::VerticalWall(edge as usize, y as usize), fraction, normal);
+			}
+			x += step_x;
+			t_max_x += t_delta_x;
+			if x < 0 || x >= width {
+				return hit(ContactKind::Border, fraction, normal);
+			}
+			if map.blocks_sight(x as usize, y as usize) {
+				return hit(ContactKind::Tile(x as usize, y as usize), fraction, normal);
+			}
+		}
+		else {
+			let edge = if step_y > 0 { y + 1 } else { y };
+			let normal = Vector2f::new(0.0, -step_y as f32);
+			if walls.horizontal_edges()[(x as usize, edge as usize)] {
+				return hit(ContactKind::HorizontalWall(x as usize, edge as usize), fraction, normal);
+			}
+			y += step_y;
+			t_max_y += t_delta_y;
+			if y < 0 || y >= height {
+				return hit(ContactKind::Border, fraction, normal);
+			}
+			if map.blocks_sight(x as usize, y as usize) {
+				return hit(ContactKind::Tile(x as usize, y as usize), fraction, normal);
+			}
+		}
+	}
+}
+
+/// Step direction, fraction of the ray to the first tile border and fraction per tile on one axis
+fn axis(start: f32, direction: f32) -> (isize, f32, f32) {
+	if direction > 0.0 {
+		(1, (start.floor() + 1.0 - start) / direction, 1.0 / direction)
+	}
+	else if direction < 0.0 {
+		(-1, (start - start.floor()) / -direction, 1.0 / -direction)
+	}
+	else {
+		(0, f32::INFINITY, f32::INFINITY)
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use game::map::CollisionLayer;
+	use game::map_generator::{Grass, Building, Forest};
+
+	/// '.' grass, '#' building, 'f' forest
+	fn map_from(rows: &[&str]) -> Map {
+		let tiles: Vec<u32> = rows.iter().flat_map(|row| row.chars()).map(|c| match c {
+			'#' => Building,
+			'f' => Forest,
+			_ => Grass,
+		}).collect();
+		let size = tiles.len();
+		Map::new_init(rows[0].len(), rows.len(), tiles, vec![0; size], vec![0; size])
+	}
+
+	fn close(a: f32, b: f32) -> bool {
+		(a - b).abs() < 0.001
+	}
+
+	fn assert_hit(hit: Option<RaycastHit>, kind: ContactKind, fraction: f32, normal: (f32, f32)) {
+		let hit = hit.expect("ray should hit");
+		assert_eq!(hit.kind, kind);
+		assert!(close(hit.fraction, fraction), "fraction {} instead of {}", hit.fraction, fraction);
+		assert!(close(hit.normal.x, normal.0) && close(hit.normal.y, normal.1), "normal {:?}", (hit.normal.x, hit.normal.y));
+	}
+
+	#[test]
+	fn vertical_wall_stops_rays_both_ways() {
+		let mut map = map_from(&["........", "........", "........"]);
+		let mut walls = CollisionLayer::new(8, 3);
+		walls.set_collision_left(5, 1);
+		map.set_walls(walls);
+		let (a, b) = (Vector2f::new(1.5, 1.5), Vector2f::new(7.5, 1.5));
+		assert_hit(raycast(&map, a, b), ContactKind::VerticalWall(5, 1), 3.5 / 6.0, (-1.0, 0.0));
+		let hit = raycast(&map, a, b).unwrap();
+		assert!(close(hit.point.x, 5.0) && close(hit.point.y, 1.5));
+		assert_hit(raycast(&map, b, a), ContactKind::VerticalWall(5, 1), 2.5 / 6.0, (1.0, 0.0));
+		assert!(raycast(&map, Vector2f::new(1.5, 0.5), Vector2f::new(7.5, 0.5)).is_none());
+	}
+
+	#[test]
+	fn horizontal_wall_stops_rays_both_ways() {
+		let mut map = map_from(&["...", "...", "...", "...", "...", "..."]);
+		let mut walls = CollisionLayer::new(3, 6);
+		walls.set_collision_top(1, 3);
+		map.set_walls(walls);
+		let (a, b) = (Vector2f::new(1.5, 0.5), Vector2f::new(1.5, 4.5));
+		assert_hit(raycast(&map, a, b), ContactKind::HorizontalWall(1, 3), 2.5 / 4.0, (0.0, -1.0));
+		assert_hit(raycast(&map, b, a), ContactKind::HorizontalWall(1, 3), 1.5 / 4.0, (0.0, 1.0));
+	}
+
+	#[test]
+	fn sight_blocking_tiles_stop_rays() {
+		let map = map_from(&[".f..#.", "......"]);
+		assert_hit(raycast(&map, Vector2f::new(2.5, 0.5), Vector2f::new(5.5, 0.5)), ContactKind::Tile(4, 0), 0.5, (-1.0, 0.0));
+		assert_hit(raycast(&map, Vector2f::new(2.5, 0.5), Vector2f::new(0.5, 0.5)), ContactKind::Tile(1, 0), 0.25, (1.0, 0.0));
+		assert_hit(raycast(&map, Vector2f::new(1.5, 1.5), Vector2f::new(1.5, 0.0)), ContactKind::Tile(1, 0), 0.5 / 1.5, (0.0, 1.0));
+		// The start tile is ignored and the ray ends before the building
+		assert!(raycast(&map, Vector2f::new(1.5, 0.5), Vector2f::new(3.9, 0.5)).is_none());
+	}
+
+	#[test]
+	fn rays_leaving_the_map_hit_the_border() {
+		let map = map_from(&["....", "....", "....", "...."]);
+		assert_hit(raycast(&map, Vector2f::new(1.5, 1.5), Vector2f::new(6.5, 1.5)), ContactKind::Border, 0.5, (-1.0, 0.0));
+		assert_hit(raycast(&map, Vector2f::new(1.5, 1.5), Vector2f::new(1.5, -0.5)), ContactKind::Border, 0.75, (0.0, 1.0));
+		assert_hit(raycast(&map, Vector2f::new(-1.0, 1.5), Vector2f::new(2.5, 1.5)), ContactKind::Border, 0.0, (0.0, 0.0));
+	}
+
+	#[test]
+	fn zero_length_ray_hits_nothing() {
+		let map = map_from(&[".f.", "..."]);
+		assert!(raycast(&map, Vector2f::new(0.5, 0.5), Vector2f::new(0.5, 0.5)).is_none());
+		assert!(raycast(&map, Vector2f::new(1.5, 0.5), Vector2f::new(1.5, 0.5)).is_none());
+	}
+
+	#[test]
+	fn line_of_sight_goes_both_ways() {
+		let mut map = map_from(&[
+			"......",
+			"..f...",
+			"......",
+			"......",
+		]);
+		let mut walls = CollisionLayer::new(6, 4);
+		walls.set_collision_bottom(4, 2);
+		map.set_walls(walls);
+		let pairs = [
+			((0.5, 0.5), (5.5, 0.5), true),
+			((0.5, 1.5), (5.5, 1.5), false),		// forest between
+			((4.5, 0.5), (4.5, 3.5), false),		// wall between
+			((0.5, 3.5), (5.5, 2.5), true),
+		];
+		for &((ax, ay), (bx, by), expected) in pairs.iter() {
+			let (a, b) = (Vector2f::new(ax, ay), Vector2f::new(bx, by));
+			assert_eq!(map.has_line_of_sight(a, b), expected, "{:?} to {:?}", (ax, ay), (bx, by));
+			assert_eq!(map.has_line_of_sight(b, a), expected, "{:?} to {:?}", (bx, by), (ax, ay));
+		}
+	}
+}
@@ -0,0 +1,234 @@
+use std::fs::File;
+use std::io::{Read, Write};
+use rustc_serialize::json;
+use game::map::MapLoader;
+use game::simulation::Command;
+use game::world::World;
+
+pub const HASH_INTERVAL: u64 = 60;		// ticks between two state hashes
+const VERSION: u32 = 2;			// 2 since state hashes are FNV-1a
+
+/// Commands applied at the start of one tick, ticks without any are left out
+#[derive(RustcDecodable, RustcEncodable)]
+pub struct TickCommands {
+	pub tick: u64,
+	pub commands: Vec<Command>,
+}
+
+/// World::state_hash after the tick
+#[derive(RustcDecodable, RustcEncodable)]
+pub struct StateHash {
+	pub tick: u64,
+	pub hash: u64,
+}
+
+/// Everything needed to run a match again: the starting map and seed plus the commands of every tick
+#[derive(RustcDecodable, RustcEncodable)]
+pub struct Replay {
+	pub version: u32,
+	pub seed: Vec<usize>,
+	pub map: MapLoader,
+	pub length: u64,			// ticks recorded
+	pub hash_interval: u64,
+	pub ticks: Vec<TickCommands>,
+	pub hashes: Vec<StateHash>,
+}
+
+impl Replay {
+	/// Has to start with the world before its first tick
+	pub fn new(world: &World, hash_interval: u64) -> Self {
+		Replay {
+			version: VERSION,
+			seed: world.get_seed().to_vec(),
+			map: MapLoader::from_map(world.get_map(), "Replay", ""),
+			length: 0,
+			hash_interval: hash_interval.max(1),
+			ticks: Vec::new(),
+			hashes: vec![StateHash {
+				tick: 0,
+				hash: world.state_hash(),
+			}],
+		}
+	}
+
+	/// The world the recording started with
+	pub fn to_world(&self) -> World {
+		World::from_map_seed(self.map.clone().into_map(), &self.seed)
+	}
+
+	/// Called for every tick in order, with the commands it applied
+	pub fn record(&mut self, tick: u64, commands: &[Command], world: &World) {
+		if !commands.is_empty() {
+			self.ticks.push(TickCommands {
+				tick: tick,
+				commands: commands.to_vec(),
+			});
+		}
+		self.length = tick + 1;
+		if self.length % self.hash_interval == 0 {
+			self.hashes.push(StateHash {
+				tick: self.length,
+				hash: world.state_hash(),
+			});
+		}
+	}
+
+	pub fn save(&self, path: &str) {
+		let encoded = json::encode(self).expect("Could not encode replay");
+		let mut file = File::create(path).expect("Could not create file!");
+		file.write_all(encoded.as_bytes()).expect("Could not write file!");
+	}
+
+	pub fn load(path: &str) -> Result<Replay, String> {
+		let mut text = String::new();
+		File::open(path).and_then(|mut file| file.read_to_string(&mut text)).map_err(|e| format!("Could not read {}: {}", path, e))?;
+		let replay: Replay = json::decode(&text).map_err(|e| format!("Could not decode {}: {}", path, e))?;
+		if replay.version != VERSION {
+			return Err(format!("Replay version {} is not supported", replay.version));
+		}
+		Ok(replay)
+	}
+}
+
+/// Feeds a Replay back into a simulation tick by tick and compares the state hashes on the way
+pub struct Playback {
+	replay: Replay,
+	next_commands: usize,		// index into replay.ticks
+	next_hash: usize,			// index into replay.hashes
+	divergence: Option<u64>,	// first tick whose hash did not match
+}
+
+impl Playback {
+	pub fn new(replay: Replay) -> Self {
+		Playback {
+			replay: replay,
+			next_commands: 0,
+			next_hash: 0,
+			divergence: None,
+		}
+	}
+
+	/// Commands recorded for the tick, ticks have to be asked for in order
+	pub fn commands(&mut self, tick: u64) -> Vec<Command> {
+		let ticks = &self.replay.ticks;
+		while self.next_commands < ticks.len() && ticks[self.next_commands].tick < tick {
+			self.next_commands += 1;
+		}
+		match ticks.get(self.next_commands) {
+			Some(recorded) if recorded.tick == tick => recorded.commands.clone(),
+			_ => Vec::new(),
+		}
+	}
+
+	/// Compares the world with the hash recorded for the tick, if there is one
+	pub fn check(&mut self, tick: u64, world: &World) {
+		let hashes = &self.replay.hashes;
+		while self.next_hash < hashes.len() && hashes[self.next_hash].tick < tick {
+			self.next_hash += 1;
+		}
+		match hashes.get(self.next_hash) {
+			Some(recorded) if recorded.tick == tick => {
+				if self.divergence.is_none() && recorded.hash != world.state_hash() {
+					self.divergence = Some(tick);
+				}
+			},
+			_ => (),
+		}
+	}
+
+	/// All recorded ticks have been played
+	pub fn is_finished(&self, tick: u64) -> bool {
+		tick >= self.replay.length
+	}
+
+	pub fn get_divergence(&self) -> Option<u64> {
+		self.divergence
+	}
+
+	pub fn get_replay(&self) -> &Replay {
+		&self.replay
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use std::env;
+	use game::map::Map;
+	use game::simulation::Simulation;
+
+	fn small_world() -> World {
+		let size = 24 * 24;
+		World::from_map_seed(Map::new_init(24, 24, vec![0; size], vec![0; size], vec![0; size]), &[7, 8, 9])
+	}
+
+	/// Walks in a square and drops a zombie now and then
+	fn recorded(ticks: u64) -> (Replay, u64) {
+		let mut simulation = Simulation::new(small_world());
+		simulation.start_recording(20).unwrap();
+		let directions = [(1.0, 0.0), (0.0, 1.0), (-1.0, 0.0), (0.0, -1.0)];
+		for tick in 0..ticks {
+			if tick % 25 == 0 {
+				let (x, y) = directions[(tick / 25) as usize % 4];
+				simulation.push(Command::Move(x, y));
+				simulation.push(Command::Aim(12.0, tick as f32 % 24.0));
+			}
+			if tick % 70 == 0 {
+				simulation.push(Command::SpawnZombie(3.0, 3.0));
+			}
+			simulation.tick();
+		}
+		let hash = simulation.get_world().state_hash();
+		(simulation.stop_recording().unwrap(), hash)
+	}
+
+	fn play(replay: Replay) -> (Option<u64>, u64) {
+		let length = replay.length;
+		let mut simulation = Simulation::from_replay(replay);
+		simulation.run(length);
+		assert!(simulation.is_replay_finished());
+		let playback = simulation.get_playback().unwrap();
+		(playback.get_divergence(), simulation.get_world().state_hash())
+	}
+
+	#[test]
+	fn playback_matches_the_recording() {
+		let (replay, hash) = recorded(300);
+		assert_eq!(replay.length, 300);
+		assert_eq!(replay.hashes.len(), 16);
+		assert_eq!(play(replay), (None, hash));
+	}
+
+	#[test]
+	fn changed_commands_are_detected() {
+		let (mut replay, hash) = recorded(300);
+		let index = replay.ticks.iter().position(|t| t.tick == 100).unwrap();
+		replay.ticks[index].commands = vec![Command::Move(-1.0, -1.0)];
+		let (divergence, final_hash) = play(replay);
+		assert_eq!(divergence, Some(120));
+		assert!(final_hash != hash);
+	}
+
+	#[test]
+	fn saved_replays_play_back() {
+		let (replay, hash) = recorded(120);
+		let path = env::temp_dir().join("zombie_replay_test.json");
+		let path = path.to_str().unwrap();
+		replay.save(path);
+		let loaded = Replay::load(path).unwrap();
+		assert_eq!(loaded.seed, vec![7, 8, 9]);
+		assert_eq!(play(loaded), (None, hash));
+		let mut other = json::decode::<Replay>(&json::encode(&replay).unwrap()).unwrap();
+		other.version = VERSION + 1;
+		other.save(path);
+		assert!(Replay::load(path).is_err());
+	}
+
+	#[test]
+	fn same_seed_same_world() {
+		assert_eq!(small_world().state_hash(), small_world().state_hash());
+		let size = 24 * 24;
+		let other = World::from_map_seed(Map::new_init(24, 24, vec![0; size], vec![0; size], vec![0; size]), &[1, 2, 3]);
+		assert!(other.state_hash() != small_world().state_hash());
+	}
+}
@@ -0,0 +1,279 @@
+use rand::{Rng, SeedableRng, StdRng};
+use std::f32::consts::PI;
+use game::objects::{ObjectLayer, MapObject, PropKind};
+use game::map_generator::{Grass, Forest, Dirt, Sand, Swamp, Road, Building};
+use util::Vec2D;
+
+/// Bridson's Poisson-disk sampling over a rectangle
+pub struct PoissonDisk {
+	width: f32,
+	height: f32,
+	min_distance: f32,
+	attempts: usize,
+}
+
+impl PoissonDisk {
+	pub fn new(width: f32, height: f32, min_distance: f32) -> Self {
+		PoissonDisk {
+			width: width,
+			height: height,
+			min_distance: min_distance,
+			attempts: 30,
+		}
+	}
+
+	pub fn sample<R: Rng>(&self, rng: &mut R) -> Vec<(f32, f32)> {
+		let cell_size = self.min_distance / 2f32.sqrt();
+		let grid_width = (self.width / cell_size).ceil() as usize;
+		let grid_height = (self.height / cell_size).ceil() as usize;
+		let mut grid: Vec2D<Option<usize>> = Vec2D::from_vec(grid_width, grid_height, vec![None; grid_width * grid_height]);
+		let mut points: Vec<(f32, f32)> = Vec::new();
+		let mut active: Vec<usize> = Vec::new();
+
+		let first = (rng.gen::<f32>() * self.width, rng.gen::<f32>() * self.height);
+		grid[((first.0 / cell_size) as usize, (first.1 / cell_size) as usize)] = Some(0);
+		points.push(first);
+		active.push(0);
+
+		while !active.is_empty() {
+			let i = rng.gen::<usize>() % active.len();
+			let (px, py) = points[active[i]];
+			let mut found = false;
+			for _ in 0..self.attempts {
+				let angle = rng.gen::<f32>() * 2f32 * PI;
+				let distance = self.min_distance * (1f32 + rng.gen::<f32>());
+				let (x, y) = (px + angle.cos() * distance, py + angle.sin() * distance);
+				if x < 0f32 || y < 0f32 || x >= self.width || y >= self.height {
+					continue;
+				}
+				let (gx, gy) = ((x / cell_size) as usize, (y / cell_size) as usize);
+				if self.is_free(&grid, &points, x, y, gx, gy) {
+					grid[(gx, gy)] = Some(points.len());
+					active.push(points.len());
+					points.push((x, y));
+					found = true;
+					break;
+				}
+			}
+			if !found {
+				active.swap_remove(i);
+			}
+		}
+		points
+	}
+
+	fn is_free(&self, grid: &Vec2D<Option<usize>>, points: &Vec<(f32, f32)>, x: f32, y: f32, gx: usize, gy: usize) -> bool {
+		let x0 = if gx >= 2 { gx - 2 } else { 0 };
+		let y0 = if gy >= 2 { gy - 2 } else { 0 };
+		for cy in y0..(gy + 3).min(grid.height()) {
+			for cx in x0..(gx + 3).min(grid.width()) {
+				if let Some(j) = grid[(cx, cy)] {
+					let dx = points[j].0 - x;
+					let dy = points[j].1 - y;
+					if dx * dx + dy * dy < self.min_distance * self.min_distance {
+						return false;
+					}
+				}
+			}
+		}
+		true
+	}
+}
+
+/// How props are scattered over one kind of ground tile
+pub struct ScatterRule {
+	pub biome: u32,
+	pub min_spacing: f32,
+	pub density: f32,						// chance that a sample point gets a prop
+	pub props: &'static [(PropKind, u32)],	// prop and weight
+}
+
+const FOREST_PROPS: &'static [(PropKind, u32)] = &[(PropKind::Tree, 8), (PropKind::Bush, 3), (PropKind::Rock, 1)];
+const GRASS_PROPS: &'static [(PropKind, u32)] = &[(PropKind::Tree, 2), (PropKind::Bush, 4), (PropKind::Rock, 2), (PropKind::Car, 1), (PropKind::Debris, 1)];
+const SWAMP_PROPS: &'static [(PropKind, u32)] = &[(PropKind::Tree, 2), (PropKind::Bush, 5)];
+const SAND_PROPS: &'static [(PropKind, u32)] = &[(PropKind::Rock, 3), (PropKind::Bush, 1)];
+const DIRT_PROPS: &'static [(PropKind, u32)] = &[(PropKind::Car, 3), (PropKind::Debris, 5), (PropKind::Rock, 1)];
+
+pub fn default_rules() -> Vec<ScatterRule> {
+	vec![
+		ScatterRule { biome: Forest, min_spacing: 1.2, density: 0.9, props: FOREST_PROPS },
+		ScatterRule { biome: Grass, min_spacing: 4.0, density: 0.25, props: GRASS_PROPS },
+		ScatterRule { biome: Swamp, min_spacing: 2.5, density: 0.5, props: SWAMP_PROPS },
+		ScatterRule { biome: Sand, min_spacing: 5.0, density: 0.2, props: SAND_PROPS },
+		ScatterRule { biome: Dirt, min_spacing: 3.0, density: 0.3, props: DIRT_PROPS },
+	]
+}
+
+pub struct PropScatterer {
+	rng: StdRng,
+	rules: Vec<ScatterRule>,
+}
+
+impl PropScatterer {
+	pub fn new(seed: &[usize]) -> Self {
+		PropScatterer::new_with_rules(seed, default_rules())
+	}
+
+	pub fn new_with_rules(seed: &[usize], rules: Vec<ScatterRule>) -> Self {
+		PropScatterer {
+			rng: SeedableRng::from_seed(seed),
+			rules: rules,
+		}
+	}
+
+	pub fn scatter(&mut self, tiles: &Vec2D<u32>) -> ObjectLayer {
+		let mut objects = ObjectLayer::new();
+		// Indices of the placed objects, bucketed by the tile of their centre
+		let mut buckets: Vec2D<Vec<usize>> = Vec2D::from_vec(tiles.width(), tiles.height(), vec![Vec::new(); tiles.len()]);
+		for rule in self.rules.iter() {
+			let sampler = PoissonDisk::new(tiles.width() as f32, tiles.height() as f32, rule.min_spacing);
+			for (x, y) in sampler.sample(&mut self.rng) {
+				if tiles[(x as usize, y as usize)] != rule.biome || self.rng.gen::<f32>() >= rule.density {
+					continue;
+				}
+				let kind = Self::pick(&mut self.rng, rule.props);
+				if Self::fits(tiles, &buckets, &objects, x, y, kind.footprint()) {
+					buckets[(x as usize, y as usize)].push(objects.len());
+					objects.add(MapObject::new(kind, x, y));
+				}
+			}
+		}
+		objects
+	}
+
+	fn pick(rng: &mut StdRng, props: &[(PropKind, u32)]) -> PropKind {
+		let total: u32 = props.iter().map(|&(_, weight)| weight).sum();
+		let mut n = rng.gen::<u32>() % total;
+		for &(kind, weight) in props.iter() {
+			if n < weight {
+				return kind;
+			}
+			n -= weight;
+		}
+		props[0].0
+	}
+
+	fn fits(tiles: &Vec2D<u32>, buckets: &Vec2D<Vec<usize>>, objects: &ObjectLayer, x: f32, y: f32, radius: f32) -> bool {
+		if x - radius < 0f32 || y - radius < 0f32 || x + radius >= tiles.width() as f32 || y + radius >= tiles.height() as f32 {
+			return false;
+		}
+		for ty in ((y - radius) as usize)..((y + radius) as usize + 1) {
+			for tx in ((x - radius) as usize)..((x + radius) as usize + 1) {
+				let tile = tiles[(tx, ty)];
+				if tile == Road || tile == Building {
+					return false;
+				}
+			}
+		}
+		// Footprint radii are below one tile, so overlapping centres are at most two tiles away
+		let (cx, cy) = (x as usize, y as usize);
+		let x0 = if cx >= 2 { cx - 2 } else { 0 };
+		let y0 = if cy >= 2 { cy - 2 } else { 0 };
+		for by in y0..(cy + 3).min(tiles.height()) {
+			for bx in x0..(cx + 3).min(tiles.width()) {
+				for &i in buckets[(bx, by)].iter() {
+					if objects.get(i).overlaps_circle(x, y, radius) {
+						return false;
+					}
+				}
+			}
+		}
+		true
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	/// Forest, grass, sand and dirt stripes crossed by a road, with two buildings
+	fn terrain() -> Vec2D<u32> {
+		let (width, height) = (40, 40);
+		let mut tiles = Vec::with_capacity(width * height);
+		for y in 0..height {
+			for x in 0..width {
+				tiles.push(if y == 18 || y == 19 {
+					Road
+				}
+				else if (x >= 12 && x < 16 && y >= 5 && y < 9) || (x >= 33 && x < 37 && y >= 25 && y < 29) {
+					Building
+				}
+				else {
+					[Forest, Grass, Sand, Dirt][x / 10]
+				});
+			}
+		}
+		Vec2D::from_vec(width, height, tiles)
+	}
+
+	#[test]
+	fn samples_keep_their_distance_inside_the_rectangle() {
+		let mut rng: StdRng = SeedableRng::from_seed(&[1, 2, 3][..]);
+		let (width, height, min_distance) = (30.0, 20.0, 2.0);
+		let points = PoissonDisk::new(width, height, min_distance).sample(&mut rng);
+		assert!(points.len() > 50);
+		for (i, &(x, y)) in points.iter().enumerate() {
+			assert!(x >= 0.0 && y >= 0.0 && x < width && y < height, "({}, {}) outside", x, y);
+			for &(ox, oy) in points[(i + 1)..].iter() {
+				let (dx, dy) = (x - ox, y - oy);
+				assert!(dx * dx + dy * dy >= min_distance * min_distance, "({}, {}) and ({}, {})", x, y, ox, oy);
+			}
+		}
+	}
+
+	#[test]
+	fn props_do_not_overlap() {
+		let tiles = terrain();
+		let objects = PropScatterer::new(&[4, 5, 6]).scatter(&tiles);
+		assert!(objects.len() > 100);
+		for i in 0..objects.len() {
+			let a = objects.get(i);
+			for j in (i + 1)..objects.len() {
+				assert!(!objects.get(j).overlaps_circle(a.x, a.y, a.radius()), "objects {} and {}", i, j);
+			}
+		}
+	}
+
+	#[test]
+	fn props_stay_off_roads_and_buildings() {
+		let tiles = terrain();
+		let objects = PropScatterer::new(&[7, 8, 9]).scatter(&tiles);
+		for object in objects.iter() {
+			let r = object.radius();
+			assert!(object.x - r >= 0.0 && object.y - r >= 0.0);
+			for ty in ((object.y - r) as usize)..((object.y + r) as usize + 1) {
+				for tx in ((object.x - r) as usize)..((object.x + r) as usize + 1) {
+					assert!(tiles[(tx, ty)] != Road && tiles[(tx, ty)] != Building, "{:?} at ({}, {})", object.kind, object.x, object.y);
+				}
+			}
+		}
+	}
+
+	#[test]
+	fn props_only_grow_on_their_biome() {
+		const TREES: &'static [(PropKind, u32)] = &[(PropKind::Tree, 1)];
+		const ROCKS: &'static [(PropKind, u32)] = &[(PropKind::Rock, 1)];
+		let rules = vec![
+			ScatterRule { biome: Forest, min_spacing: 1.5, density: 1.0, props: TREES },
+			ScatterRule { biome: Sand, min_spacing: 2.0, density: 1.0, props: ROCKS },
+		];
+		let tiles = terrain();
+		let objects = PropScatterer::new_with_rules(&[1, 1, 2], rules).scatter(&tiles);
+		let mut counts = (0, 0);
+		for object in objects.iter() {
+			let tile = tiles[(object.x as usize, object.y as usize)];
+			match object.kind {
+				PropKind::Tree => {
+					assert_eq!(tile, Forest);
+					counts.0 += 1;
+				},
+				PropKind::Rock => {
+					assert_eq!(tile, Sand);
+					counts.1 += 1;
+				},
+				kind => panic!("unexpected {:?}", kind),
+			}
+		}
+		assert!(counts.0 > 20 && counts.1 > 20);
+	}
+}
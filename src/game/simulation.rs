@@ -0,0 +1,233 @@
+use sfml::system::Vector2f;
+use game::world::World;
+use game::timestep::{FixedTimestep, MAX_CATCH_UP};
+use game::replay::{Replay, Playback};
+
+/// Input to the simulation, from a player, a script or the network
+#[derive(Clone, Copy, Debug, PartialEq, RustcDecodable, RustcEncodable)]
+pub enum Command {
+	Move(f32, f32),				// direction the player walks in, length 1 or 0
+	Aim(f32, f32),				// point in tiles
+	SpawnZombie(f32, f32),
+}
+
+/// Owns the World and steps it on a fixed timestep, needs no window so it also runs headless
+pub struct Simulation {
+	world: World,
+	timestep: FixedTimestep,
+	tick: u64,
+	commands: Vec<Command>,		// applied in order at the start of the next tick
+	recording: Option<Replay>,
+	playback: Option<Playback>,	// while set the commands come from the replay and pushed ones are dropped
+	speed: f32,					// game seconds per real second
+	paused: bool,
+}
+
+impl Simulation {
+	pub fn new(world: World) -> Self {
+		Simulation {
+			world: world,
+			timestep: FixedTimestep::new_default(),
+			tick: 0,
+			commands: Vec::new(),
+			recording: None,
+			playback: None,
+			speed: 1.0,
+			paused: false,
+		}
+	}
+
+	/// Plays the replay back from its first tick
+	pub fn from_replay(replay: Replay) -> Self {
+		let mut simulation = Simulation::new(replay.to_world());
+		let mut playback = Playback::new(replay);
+		playback.check(0, &simulation.world);
+		simulation.playback = Some(playback);
+		simulation
+	}
+
+	pub fn new_default() -> Self {
+		Simulation::new(World::new_empty())
+	}
+
+	/// Move and aim only set state, so a newer one replaces one still waiting for the same tick.
+	/// Ignored while playing back a replay
+	pub fn push(&mut self, command: Command) {
+		if self.playback.is_some() {
+			return;
+		}
+		match command {
+			Command::Move(..) => self.commands.retain(|queued| match *queued {
+				Command::Move(..) => false,
+				_ => true,
+			}),
+			Command::Aim(..) => self.commands.retain(|queued| match *queued {
+				Command::Aim(..) => false,
+				_ => true,
+			}),
+			Command::SpawnZombie(..) => (),
+		}
+		self.commands.push(command);
+	}
+
+	/// Only possible before the first tick, the replay starts from the world as it is now
+	pub fn start_recording(&mut self, hash_interval: u64) -> Result<(), String> {
+		if self.tick > 0 {
+			return Err("Recording has to start before the first tick".to_string());
+		}
+		if self.playback.is_some() {
+			return Err("Cannot record while playing back a replay".to_string());
+		}
+		self.recording = Some(Replay::new(&self.world, hash_interval));
+		Ok(())
+	}
+
+	pub fn stop_recording(&mut self) -> Option<Replay> {
+		self.recording.take()
+	}
+
+	/// Applies the queued commands, then updates the world by one step
+	pub fn tick(&mut self) {
+		let commands = match self.playback {
+			Some(ref mut playback) => playback.commands(self.tick),
+			None => self.commands.drain(..).collect(),
+		};
+		for &command in commands.iter() {
+			match command {
+				Command::Move(x, y) => self.world.set_player_direction(Vector2f::new(x, y)),
+				Command::Aim(x, y) => self.world.set_player_aim(Vector2f::new(x, y)),
+				Command::SpawnZombie(x, y) => {
+					self.world.spawn_zombie(Vector2f::new(x, y));
+				},
+			}
+		}
+		let step = self.timestep.step();
+		self.world.update(step);
+		if let Some(ref mut replay) = self.recording {
+			replay.record(self.tick, &commands, &self.world);
+		}
+		self.tick += 1;
+		if let Some(ref mut playback) = self.playback {
+			playback.check(self.tick, &self.world);
+		}
+	}
+
+	/// As fast as possible, for soak tests and balance runs
+	pub fn run(&mut self, ticks: u64) {
+		for _ in 0..ticks {
+			self.tick();
+		}
+	}
+
+	/// Runs the ticks that fit into the frame time and returns how many that were. Nothing
+	/// happens while paused or once a replay has been played to the end
+	pub fn advance(&mut self, delta: f32) -> usize {
+		if self.paused || self.is_replay_finished() {
+			return 0;
+		}
+		let ticks = self.timestep.advance(delta * self.speed);
+		for i in 0..ticks {
+			if self.is_replay_finished() {
+				return i;
+			}
+			self.tick();
+		}
+		ticks
+	}
+
+	/// Above 1 runs faster than real time
+	pub fn set_speed(&mut self, speed: f32) {
+		self.speed = speed.max(0.0);
+		self.timestep.set_max_steps(MAX_CATCH_UP * self.speed.ceil() as usize);
+	}
+
+	pub fn get_speed(&self) -> f32 {
+		self.speed
+	}
+
+	pub fn set_paused(&mut self, paused: bool) {
+		self.paused = paused;
+	}
+
+	pub fn is_paused(&self) -> bool {
+		self.paused
+	}
+
+	pub fn get_playback(&self) -> Option<&Playback> {
+		self.playback.as_ref()
+	}
+
+	pub fn is_replay_finished(&self) -> bool {
+		self.playback.as_ref().map(|playback| playback.is_finished(self.tick)).unwrap_or(false)
+	}
+
+	/// See FixedTimestep::alpha
+	pub fn alpha(&self) -> f32 {
+		self.timestep.alpha()
+	}
+
+	/// Ticks run so far
+	pub fn get_tick(&self) -> u64 {
+		self.tick
+	}
+
+	/// Seconds of game time simulated so far
+	pub fn get_time(&self) -> f32 {
+		self.tick as f32 * self.timestep.step()
+	}
+
+	pub fn get_world(&self) -> &World {
+		&self.world
+	}
+
+	pub fn get_mut_world(&mut self) -> &mut World {
+		&mut self.world
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use game::map::Map;
+	use game::timestep::TICK_RATE;
+
+	fn small_world() -> World {
+		let size = 24 * 24;
+		World::from_map_seed(Map::new_init(24, 24, vec![0; size], vec![0; size], vec![0; size]), &[7, 8, 9])
+	}
+
+	#[test]
+	fn advance_runs_the_ticks_of_the_frame() {
+		let mut simulation = Simulation::new(small_world());
+		assert_eq!(simulation.advance(2.5 / TICK_RATE), 2);
+		assert_eq!(simulation.get_tick(), 2);
+		simulation.set_speed(2.0);
+		assert_eq!(simulation.advance(3.0 / TICK_RATE), 6);
+		assert_eq!(simulation.get_tick(), 8);
+	}
+
+	#[test]
+	fn nothing_happens_while_paused() {
+		let mut simulation = Simulation::new(small_world());
+		simulation.set_paused(true);
+		assert_eq!(simulation.advance(1.0), 0);
+		assert_eq!(simulation.get_tick(), 0);
+		simulation.set_paused(false);
+		assert_eq!(simulation.advance(1.0), MAX_CATCH_UP);
+	}
+
+	#[test]
+	fn playback_stops_at_the_end_of_the_replay() {
+		let mut recording = Simulation::new(small_world());
+		recording.start_recording(10).unwrap();
+		recording.push(Command::Move(1.0, 0.0));
+		recording.run(30);
+		let mut simulation = Simulation::from_replay(recording.stop_recording().unwrap());
+		simulation.set_speed(8.0);
+		assert_eq!(simulation.advance(1.0), 30);
+		assert!(simulation.is_replay_finished());
+		assert_eq!(simulation.advance(1.0), 0);
+		assert_eq!(simulation.get_tick(), 30);
+		assert_eq!(simulation.get_playback().unwrap().get_divergence(), None);
+	}
+}
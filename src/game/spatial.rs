@@ -0,0 +1,291 @@
+use std::collections::HashMap;
+use std::hash::Hash;
+use sfml::system::Vector2f;
+
+struct Entry {
+	position: Vector2f,
+	radius: f32,
+	cell: usize,
+}
+
+/// Uniform grid over the map, cells are square blocks of tiles. Entries are filed under the cell
+/// of their centre, queries look further by the largest radius ever inserted
+pub struct SpatialHash<K> {
+	cell_size: f32,
+	columns: usize,
+	rows: usize,
+	cells: Vec<Vec<K>>,
+	entries: HashMap<K, Entry>,
+	max_radius: f32,
+}
+
+impl<K: Copy + Eq + Hash> SpatialHash<K> {
+	pub fn new(width: usize, height: usize, cell_size: usize) -> Self {
+		let cell_size = cell_size.max(1);
+		let columns = ((width + cell_size - 1) / cell_size).max(1);
+		let rows = ((height + cell_size - 1) / cell_size).max(1);
+		SpatialHash {
+			cell_size: cell_size as f32,
+			columns: columns,
+			rows: rows,
+			cells: (0..(columns * rows)).map(|_| Vec::new()).collect(),
+			entries: HashMap::new(),
+			max_radius: 0.0,
+		}
+	}
+
+	pub fn len(&self) -> usize {
+		self.entries.len()
+	}
+
+	pub fn contains(&self, key: K) -> bool {
+		self.entries.contains_key(&key)
+	}
+
+	pub fn position(&self, key: K) -> Option<Vector2f> {
+		self.entries.get(&key).map(|entry| entry.position)
+	}
+
+	/// Position in tiles, radius of the body, 0 for points. Inserting a key again moves it
+	pub fn insert(&mut self, key: K, position: Vector2f, radius: f32) {
+		self.remove(key);
+		let cell = self.cell_index(position);
+		self.cells[cell].push(key);
+		self.max_radius = self.max_radius.max(radius);
+		self.entries.insert(key, Entry {
+			position: position,
+			radius: radius,
+			cell: cell,
+		});
+	}
+
+	/// Returns false if the key is not in the index
+	pub fn set_position(&mut self, key: K, position: Vector2f) -> bool {
+		let new_cell = self.cell_index(position);
+		let old_cell = match self.entries.get_mut(&key) {
+			Some(entry) => {
+				entry.position = position;
+				let old_cell = entry.cell;
+				entry.cell = new_cell;
+				old_cell
+			},
+			None => return false,
+		};
+		if old_cell != new_cell {
+			Self::remove_from_cell(&mut self.cells[old_cell], key);
+			self.cells[new_cell].push(key);
+		}
+		true
+	}
+
+	pub fn remove(&mut self, key: K) -> bool {
+		match self.entries.remove(&key) {
+			Some(entry) => {
+				Self::remove_from_cell(&mut self.cells[entry.cell], key);
+				true
+			},
+			None => false,
+		}
+	}
+
+	/// Moves every given key, inserting the ones that are new as points and removing the ones not given
+	pub fn sync<I: IntoIterator<Item = (K, Vector2f)>>(&mut self, positions: I) {
+		let mut seen: HashMap<K, ()> = HashMap::with_capacity(self.entries.len());
+		for (key, position) in positions {
+			if !self.set_position(key, position) {
+				self.insert(key, position, 0.0);
+			}
+			seen.insert(key, ());
+		}
+		let gone: Vec<K> = self.entries.keys().filter(|key| !seen.contains_key(key)).cloned().collect();
+		for key in gone {
+			self.remove(key);
+		}
+	}
+
+	/// Everything whose body touches the circle
+	pub fn query_radius(&self, center: Vector2f, radius: f32) -> Vec<K> {
+		let reach = radius + self.max_radius;
+		let mut result = Vec::new();
+		self.for_cells(center.x - reach, center.y - reach, center.x + reach, center.y + reach, |key, entry| {
+			let offset = entry.position - center;
+			let limit = radius + entry.radius;
+			if offset.x * offset.x + offset.y * offset.y <= limit * limit {
+				result.push(key);
+			}
+		});
+		result
+	}
+
+	/// Everything whose body touches the rectangle, x and y are the top left corner
+	pub fn query_rect(&self, x: f32, y: f32, width: f32, height: f32) -> Vec<K> {
+		let reach = self.max_radius;
+		let mut result = Vec::new();
+		self.for_cells(x - reach, y - reach, x + width + reach, y + height + reach, |key, entry| {
+			let closest = Vector2f::new(entry.position.x.max(x).min(x + width), entry.position.y.max(y).min(y + height));
+			let offset = entry.position - closest;
+			if offset.x * offset.x + offset.y * offset.y <= entry.radius * entry.radius {
+				result.push(key);
+			}
+		});
+		result
+	}
+
+	/// The k closest centres no further than max_distance, closest first. Searches rings of cells
+	/// around the centre until no closer entry can be left
+	pub fn nearest(&self, center: Vector2f, k: usize, max_distance: f32) -> Vec<(K, f32)> {
+		let mut found: Vec<(K, f32)> = Vec::new();
+		if k == 0 {
+			return found;
+		}
+		let (cx, cy) = self.cell_coords(center);
+		let max_ring = self.columns.max(self.rows);
+		for ring in 0..(max_ring + 1) {
+			// Nothing in this ring is closer than the inner edge of the ring
+			let closest_possible = (ring as f32 - 1.0).max(0.0) * self.cell_size;
+			if closest_possible > max_distance || (found.len() >= k && closest_possible > found[k - 1].1) {
+				break;
+			}
+			let (x0, y0) = (cx as isize - ring as isize, cy as isize - ring as isize);
+			let (x1, y1) = (cx as isize + ring as isize, cy as isize + ring as isize);
+			for y in y0..(y1 + 1) {
+				for x in x0..(x1 + 1) {
+					let on_ring = x == x0 || x == x1 || y == y0 || y == y1;
+					if !on_ring || x < 0 || y < 0 || x >= self.columns as isize || y >= self.rows as isize {
+						continue;
+					}
+					for &key in self.cells[y as usize * self.columns + x as usize].iter() {
+						let offset = self.entries[&key].position - center;
+						let distance = (offset.x * offset.x + offset.y * offset.y).sqrt();
+						if distance <= max_distance {
+							found.push((key, distance));
+						}
+					}
+				}
+			}
+			found.sort_by(|a, b| a.1.partial_cmp(&b.1).unwrap_or(::std::cmp::Ordering::Equal));
+			found.truncate(k);
+		}
+		found
+	}
+
+	fn for_cells<F: FnMut(K, &Entry)>(&self, x0: f32, y0: f32, x1: f32, y1: f32, mut f: F) {
+		let (cx0, cy0) = self.cell_coords(Vector2f::new(x0, y0));
+		let (cx1, cy1) = self.cell_coords(Vector2f::new(x1, y1));
+		for cy in cy0..(cy1 + 1) {
+			for cx in cx0..(cx1 + 1) {
+				for &key in self.cells[cy * self.columns + cx].iter() {
+					f(key, &self.entries[&key]);
+				}
+			}
+		}
+	}
+
+	/// Positions off the map are filed under the border cells
+	fn cell_coords(&self, position: Vector2f) -> (usize, usize) {
+		let clamp = |value: f32, limit: usize| ((value / self.cell_size).floor().max(0.0) as usize).min(limit - 1);
+		(clamp(position.x, self.columns), clamp(position.y, self.rows))
+	}
+
+	fn cell_index(&self, position: Vector2f) -> usize {
+		let (x, y) = self.cell_coords(position);
+		y * self.columns + x
+	}
+
+	fn remove_from_cell(cell: &mut Vec<K>, key: K) {
+		if let Some(i) = cell.iter().position(|&k| k == key) {
+			cell.swap_remove(i);
+		}
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	/// Deterministic points spread over a 50 x 30 map, some of them off its edges
+	fn points() -> Vec<(u32, Vector2f)> {
+		let mut state = 12345u32;
+		let mut next = || {
+			state = state.wrapping_mul(1103515245).wrapping_add(12345);
+			(state >> 8) as f32 / (1 << 24) as f32
+		};
+		(0..300).map(|i| (i, Vector2f::new(next() * 54.0 - 2.0, next() * 34.0 - 2.0))).collect()
+	}
+
+	fn filled(radius: f32) -> SpatialHash<u32> {
+		let mut hash = SpatialHash::new(50, 30, 4);
+		for (key, position) in points() {
+			hash.insert(key, position, radius);
+		}
+		hash
+	}
+
+	fn sorted(mut keys: Vec<u32>) -> Vec<u32> {
+		keys.sort();
+		keys
+	}
+
+	fn distance(a: Vector2f, b: Vector2f) -> f32 {
+		let offset = a - b;
+		(offset.x * offset.x + offset.y * offset.y).sqrt()
+	}
+
+	#[test]
+	fn query_radius_matches_brute_force() {
+		let hash = filled(0.5);
+		for &(center, radius) in [(Vector2f::new(10.0, 10.0), 3.0), (Vector2f::new(0.0, 0.0), 6.5), (Vector2f::new(49.0, 29.0), 10.0)].iter() {
+			let expected: Vec<u32> = points().into_iter().filter(|&(_, p)| distance(p, center) <= radius + 0.5).map(|(key, _)| key).collect();
+			assert_eq!(sorted(hash.query_radius(center, radius)), expected);
+		}
+	}
+
+	#[test]
+	fn query_rect_matches_brute_force() {
+		let hash = filled(0.0);
+		let expected: Vec<u32> = points().into_iter().filter(|&(_, p)| p.x >= 5.0 && p.x <= 17.0 && p.y >= -1.0 && p.y <= 8.0).map(|(key, _)| key).collect();
+		assert_eq!(sorted(hash.query_rect(5.0, -1.0, 12.0, 9.0)), expected);
+	}
+
+	#[test]
+	fn nearest_matches_brute_force() {
+		let hash = filled(0.0);
+		let center = Vector2f::new(20.0, 12.0);
+		let mut expected: Vec<(u32, f32)> = points().into_iter().map(|(key, p)| (key, distance(p, center))).filter(|&(_, d)| d <= 15.0).collect();
+		expected.sort_by(|a, b| a.1.partial_cmp(&b.1).unwrap());
+		expected.truncate(7);
+		let found = hash.nearest(center, 7, 15.0);
+		assert_eq!(found.iter().map(|f| f.0).collect::<Vec<_>>(), expected.iter().map(|e| e.0).collect::<Vec<_>>());
+		assert!(hash.nearest(center, 0, 15.0).is_empty());
+		assert!(hash.nearest(center, 5, 0.0).len() <= 1);
+	}
+
+	#[test]
+	fn moving_and_removing() {
+		let mut hash: SpatialHash<u32> = SpatialHash::new(20, 20, 5);
+		hash.insert(1, Vector2f::new(1.0, 1.0), 0.5);
+		hash.insert(2, Vector2f::new(2.0, 1.0), 0.5);
+		assert!(hash.set_position(1, Vector2f::new(18.0, 18.0)));
+		assert!(!hash.set_position(3, Vector2f::new(0.0, 0.0)));
+		assert_eq!(hash.query_radius(Vector2f::new(1.0, 1.0), 1.0), vec![2]);
+		assert_eq!(hash.query_radius(Vector2f::new(18.0, 18.0), 1.0), vec![1]);
+		assert_eq!(hash.position(1), Some(Vector2f::new(18.0, 18.0)));
+		assert!(hash.remove(2));
+		assert!(!hash.remove(2));
+		assert!(hash.query_radius(Vector2f::new(1.0, 1.0), 1.0).is_empty());
+		hash.insert(1, Vector2f::new(3.0, 3.0), 0.5);
+		assert_eq!(hash.len(), 1);
+		assert!(hash.query_radius(Vector2f::new(18.0, 18.0), 1.0).is_empty());
+	}
+
+	#[test]
+	fn sync_inserts_moves_and_removes() {
+		let mut hash: SpatialHash<u32> = SpatialHash::new(20, 20, 5);
+		hash.sync(vec![(1, Vector2f::new(1.0, 1.0)), (2, Vector2f::new(10.0, 10.0))]);
+		assert_eq!(hash.len(), 2);
+		hash.sync(vec![(2, Vector2f::new(15.0, 2.0)), (3, Vector2f::new(4.0, 4.0))]);
+		assert!(!hash.contains(1));
+		assert_eq!(hash.position(2), Some(Vector2f::new(15.0, 2.0)));
+		assert_eq!(sorted(hash.query_rect(0.0, 0.0, 20.0, 5.0)), vec![2, 3]);
+	}
+}
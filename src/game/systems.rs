@@ -0,0 +1,134 @@
+use std::f32::consts::PI;
+use rand::{Rng, StdRng};
+use sfml::system::Vector2f;
+use game::components::{Components, Ai, Behaviour};
+use game::ecs::Entity;
+use game::flowfield::FlowFields;
+use game::map::Map;
+use game::movement::{Body, BodyShape, MovementSolver};
+use game::spatial::SpatialHash;
+
+const WANDER_INTERVAL: (f32, f32) = (1.0, 4.0);		// seconds between new directions
+
+/// Remembers where everything was before the tick moves it
+pub fn snapshot_system(components: &mut Components) {
+	for (_, position) in components.positions.iter_mut() {
+		position.previous = position.position;
+	}
+}
+
+/// Velocity from the input direction, facing towards the aim
+pub fn player_system(components: &mut Components) {
+	let Components { ref players, ref mut positions, ref mut velocities, .. } = *components;
+	for (&entity, control) in players.iter() {
+		if let Some(velocity) = velocities.get_mut(entity) {
+			velocity.velocity = control.direction * control.speed;
+		}
+		if let Some(position) = positions.get_mut(entity) {
+			let look = control.aim - position.position;
+			if look.x != 0.0 || look.y != 0.0 {
+				position.facing = look.y.atan2(look.x);
+			}
+		}
+	}
+}
+
+/// Sets the velocity of every entity with an AI
+pub fn ai_system(components: &mut Components, map: &Map, flow_fields: &FlowFields, rng: &mut StdRng, delta: f32) {
+	let Components { ref mut ais, ref positions, ref mut velocities, .. } = *components;
+	for (&entity, ai) in ais.iter_mut() {
+		let position = match positions.get(entity) {
+			Some(position) => position.position,
+			None => continue,
+		};
+		let velocity = match velocities.get_mut(entity) {
+			Some(velocity) => velocity,
+			None => continue,
+		};
+		match ai.behaviour {
+			Behaviour::Idle => velocity.velocity = Vector2f::new(0.0, 0.0),
+			Behaviour::Wander => wander(ai, &mut velocity.velocity, rng, delta),
+			Behaviour::Chase(field) | Behaviour::Flee(field) => {
+				let inside = position.x >= 0.0 && position.y >= 0.0 && position.x < map.width() as f32 && position.y < map.height() as f32;
+				let step = if inside {
+					flow_fields.get(field).and_then(|distance_map| distance_map.next_step(position.x as usize, position.y as usize))
+				}
+				else {
+					None
+				};
+				match step {
+					Some((x, y)) => {
+						let offset = Vector2f::new(x as f32 + 0.5, y as f32 + 0.5) - position;
+						let length = (offset.x * offset.x + offset.y * offset.y).sqrt();
+						if length > 0.0 {
+							velocity.velocity = offset * (ai.speed / length);
+						}
+						ai.timer = 0.0;
+					},
+					None => match ai.behaviour {
+						Behaviour::Chase(_) => wander(ai, &mut velocity.velocity, rng, delta),
+						_ => velocity.velocity = Vector2f::new(0.0, 0.0),
+					},
+				}
+			},
+		}
+	}
+}
+
+fn wander(ai: &mut Ai, velocity: &mut Vector2f, rng: &mut StdRng, delta: f32) {
+	ai.timer -= delta;
+	if ai.timer <= 0.0 {
+		let angle = rng.gen_range(0.0, 2.0 * PI);
+		*velocity = Vector2f::new(angle.cos(), angle.sin()) * (ai.speed * 0.5);
+		ai.timer = rng.gen_range(WANDER_INTERVAL.0, WANDER_INTERVAL.1);
+	}
+}
+
+/// Moves everything with a velocity, through the MovementSolver if it has a collider
+pub fn movement_system(components: &mut Components, map: &Map, solver: &MovementSolver, delta: f32) {
+	let Components { ref mut velocities, ref mut positions, ref colliders, ref players, .. } = *components;
+	for (&entity, velocity) in velocities.iter_mut() {
+		let position = match positions.get_mut(entity) {
+			Some(position) => position,
+			None => continue,
+		};
+		if velocity.velocity.x == 0.0 && velocity.velocity.y == 0.0 {
+			continue;
+		}
+		// Players face where they aim instead
+		if !players.contains(entity) {
+			position.facing = velocity.velocity.y.atan2(velocity.velocity.x);
+		}
+		match colliders.get(entity) {
+			Some(collider) => {
+				let mut body = Body {
+					position: position.position,
+					velocity: velocity.velocity,
+					shape: collider.shape,
+				};
+				let result = solver.move_body(map, &mut body, delta);
+				position.position = body.position;
+				velocity.velocity = result.velocity;
+			},
+			None => position.position = position.position + velocity.velocity * delta,
+		}
+	}
+}
+
+/// Entities whose health ran out, to be despawned
+pub fn health_system(components: &Components) -> Vec<Entity> {
+	components.healths.iter().filter(|&(_, health)| health.is_dead()).map(|(&entity, _)| entity).collect()
+}
+
+/// Files every positioned entity under its current cell, with the collider as its radius
+pub fn spatial_system(components: &Components, spatial: &mut SpatialHash<Entity>) {
+	for (&entity, position) in components.positions.iter() {
+		if !spatial.set_position(entity, position.position) {
+			let radius = components.colliders.get(entity).map(|collider| match collider.shape {
+				BodyShape::Circle(radius) => radius,
+				BodyShape::Aabb(half_width, half_height) => (half_width * half_width + half_height * half_height).sqrt(),
+			}).unwrap_or(0.0);
+			spatial.insert(entity, position.position, radius);
+		}
+	}
+}
@@ -0,0 +1,1201 @@
+use rustc_serialize::json::Json;
+use std::collections::BTreeMap;
+use std::fs::File;
+use std::io::{Read, Write};
+use std::path::Path;
+use game::map::Map;
+use game::objects::{ObjectLayer, MapObject, PropKind};
+use game::tileset::{Tileset, TileInfo};
+use game::image_import::parse_color;
+
+// Tiled stores flipping in the top bits of a gid
+const FLIP_MASK: u32 = 0xE0000000;
+
+/// Custom property, kind is the Tiled type ("string", "int", "float", "bool", ...)
+#[derive(Clone)]
+pub struct TiledProperty {
+	pub name: String,
+	pub kind: String,
+	pub value: String,
+}
+
+impl TiledProperty {
+	pub fn new(name: &str, kind: &str, value: &str) -> Self {
+		TiledProperty {
+			name: name.to_string(),
+			kind: kind.to_string(),
+			value: value.to_string(),
+		}
+	}
+}
+
+pub struct TiledTileset {
+	pub firstgid: u32,
+	pub name: String,
+	pub image: String,
+	pub image_width: u32,
+	pub image_height: u32,
+	pub tile_width: u32,
+	pub tile_height: u32,
+	pub columns: u32,
+	pub tile_count: u32,
+	pub tiles: Vec<(u32, Vec<TiledProperty>)>,		// custom properties per local tile id
+	pub properties: Vec<TiledProperty>,
+}
+
+pub struct TiledObject {
+	pub id: u32,
+	pub name: String,
+	pub kind: String,
+	pub x: f32,			// pixels, top left of the bounding box
+	pub y: f32,
+	pub width: f32,
+	pub height: f32,
+	pub ellipse: bool,
+	pub properties: Vec<TiledProperty>,
+}
+
+pub enum TiledLayerData {
+	Tiles(Vec<u32>),		// gids, 0 is empty
+	Objects(Vec<TiledObject>),
+}
+
+pub struct TiledLayer {
+	pub id: u32,
+	pub name: String,
+	pub visible: bool,
+	pub opacity: f32,
+	pub data: TiledLayerData,
+	pub properties: Vec<TiledProperty>,
+}
+
+/// A Tiled map (TMX or JSON), kept close to the file so it can be written back unchanged
+pub struct TiledMap {
+	pub width: usize,
+	pub height: usize,
+	pub tile_width: u32,
+	pub tile_height: u32,
+	pub properties: Vec<TiledProperty>,
+	pub tilesets: Vec<TiledTileset>,
+	pub layers: Vec<TiledLayer>,
+}
+
+impl TiledMap {
+	/// Layer 0 uses every tile, on layers 1 and 2 tile 0 is left empty
+	pub fn from_map(map: &Map, name: &str, description: &str, tileset: &Tileset) -> Self {
+		let tile_size = tileset.tile_size();
+		let image = Path::new(tileset.image_path()).file_name().and_then(|f| f.to_str()).unwrap_or("tileset.png").to_string();
+		let tiles = tileset.tiles().iter().enumerate().map(|(id, info)| {
+			let (r, g, b) = info.color;
+			(id as u32, vec![
+				TiledProperty::new("name", "string", &info.name),
+				TiledProperty::new("color", "string", &format!("#{:02x}{:02x}{:02x}", r, g, b)),
+				TiledProperty::new("blocking", "bool", &info.blocking.to_string()),
+				TiledProperty::new("blocks_sight", "bool", &info.blocks_sight.to_string()),
+				TiledProperty::new("cost", "float", &info.cost.to_string()),
+			])
+		}).collect();
+		let rows = (tileset.tile_count() + tileset.columns() - 1) / tileset.columns();
+		let mut layers: Vec<TiledLayer> = (0..3).map(|i| {
+			let data = map.get_layer(i).tiles().iter().map(|&tile| {
+				if i > 0 && tile == 0 { 0 } else { tile + 1 }
+			}).collect();
+			TiledLayer {
+				id: i as u32 + 1,
+				name: format!("layer{}", i),
+				visible: true,
+				opacity: 1.0,
+				data: TiledLayerData::Tiles(data),
+				properties: Vec::new(),
+			}
+		}).collect();
+		let objects = map.get_objects().iter().enumerate().map(|(i, o)| {
+			let r = o.radius();
+			TiledObject {
+				id: i as u32 + 1,
+				name: String::new(),
+				kind: o.kind.name().to_string(),
+				x: (o.x - r) * tile_size as f32,
+				y: (o.y - r) * tile_size as f32,
+				width: 2f32 * r * tile_size as f32,
+				height: 2f32 * r * tile_size as f32,
+				ellipse: true,
+				properties: vec![TiledProperty::new("blocks_movement", "bool", &o.kind.blocks_movement().to_string())],
+			}
+		}).collect();
+		layers.push(TiledLayer {
+			id: 4,
+			name: "objects".to_string(),
+			visible: true,
+			opacity: 1.0,
+			data: TiledLayerData::Objects(objects),
+			properties: Vec::new(),
+		});
+		TiledMap {
+			width: map.width(),
+			height: map.height(),
+			tile_width: tile_size,
+			tile_height: tile_size,
+			properties: vec![
+				TiledProperty::new("name", "string", name),
+				TiledProperty::new("description", "string", description),
+			],
+			tilesets: vec![TiledTileset {
+				firstgid: 1,
+				name: "tileset".to_string(),
+				image: image,
+				image_width: tileset.columns() * tile_size,
+				image_height: rows * tile_size,
+				tile_width: tile_size,
+				tile_height: tile_size,
+				columns: tileset.columns(),
+				tile_count: tileset.tile_count(),
+				tiles: tiles,
+				properties: Vec::new(),
+			}],
+			layers: layers,
+		}
+	}
+
+	/// The first three tile layers become the map layers, every object layer is merged into the map objects.
+	/// Tile ids of every tileset follow on those of the tileset before it, see tile_id
+	pub fn to_map(&self) -> Result<Map, String> {
+		let size = self.width * self.height;
+		let mut tile_layers: Vec<Vec<u32>> = Vec::new();
+		let mut objects = ObjectLayer::new();
+		for layer in self.layers.iter() {
+			match layer.data {
+				TiledLayerData::Tiles(ref data) => {
+					if data.len() != size {
+						return Err(format!("Layer '{}' has {} tiles, expected {}", layer.name, data.len(), size));
+					}
+					if tile_layers.len() < 3 {
+						tile_layers.push(data.iter().map(|&gid| self.tile_id(gid).unwrap_or(0)).collect());
+					}
+				},
+				TiledLayerData::Objects(ref list) => {
+					for object in list.iter() {
+						let kind = PropKind::from_name(&object.kind).or(PropKind::from_name(&object.name));
+						if let Some(kind) = kind {
+							let x = (object.x + object.width / 2f32) / self.tile_width as f32;
+							let y = (object.y + object.height / 2f32) / self.tile_height as f32;
+							objects.add(MapObject::new(kind, x, y));
+						}
+					}
+				},
+			}
+		}
+		while tile_layers.len() < 3 {
+			tile_layers.push(vec![0; size]);
+		}
+		let layer2 = tile_layers.pop().unwrap();
+		let layer1 = tile_layers.pop().unwrap();
+		let layer0 = tile_layers.pop().unwrap();
+		let mut map = Map::new_init(self.width, self.height, layer0, layer1, layer2);
+		map.set_objects(objects);
+		map.set_tileset(self.to_tileset());
+		Ok(map)
+	}
+
+	/// Tilesets in the order their gids start
+	fn sorted_tilesets(&self) -> Vec<&TiledTileset> {
+		let mut tilesets: Vec<&TiledTileset> = self.tilesets.iter().collect();
+		tilesets.sort_by_key(|t| t.firstgid);
+		tilesets
+	}
+
+	/// Game tile id of a gid: the local id in its tileset plus the tile counts of all tilesets before it.
+	/// None for empty tiles and gids outside of every tileset
+	pub fn tile_id(&self, gid: u32) -> Option<u32> {
+		let gid = gid & !FLIP_MASK;
+		let mut offset = 0;
+		for tileset in self.sorted_tilesets() {
+			if gid >= tileset.firstgid && gid - tileset.firstgid < tileset.tile_count {
+				return Some(offset + gid - tileset.firstgid);
+			}
+			offset += tileset.tile_count;
+		}
+		None
+	}
+
+	/// Game tileset numbered like tile_id, with the image of the first Tiled tileset. Tiles without colour or
+	/// blocking properties keep the defaults
+	pub fn to_tileset(&self) -> Tileset {
+		let default = Tileset::new_default();
+		let tilesets = self.sorted_tilesets();
+		let first = match tilesets.first() {
+			Some(first) => *first,
+			None => return default,
+		};
+		let mut tiles = Vec::new();
+		for (i, tileset) in tilesets.iter().enumerate() {
+			let offset = tiles.len() as u32;
+			// All but the last tileset take their full tile count so the ids of the next one line up
+			let count = if i + 1 < tilesets.len() {
+				tileset.tile_count
+			}
+			else {
+				tileset.tiles.iter().map(|&(id, _)| id + 1).max().unwrap_or(0).max(tileset.tile_count).max((default.len() as u32).saturating_sub(offset))
+			};
+			for local in 0..count {
+				let id = offset + local;
+				let properties = tileset.tiles.iter().find(|&&(tile, _)| tile == local).map(|&(_, ref p)| p.as_slice()).unwrap_or(&[]);
+				let find = |name: &str| properties.iter().find(|p| p.name == name).map(|p| p.value.clone());
+				tiles.push(TileInfo {
+					name: find("name").or(default.get(id).map(|info| info.name.clone())).unwrap_or(format!("{} {}", tileset.name, local)),
+					color: find("color").and_then(|c| parse_color(&c)).unwrap_or(default.color(id)),
+					blocking: find("blocking").map(|b| b == "true").unwrap_or(default.is_blocking(id)),
+					blocks_sight: find("blocks_sight").map(|b| b == "true").unwrap_or(default.blocks_sight(id)),
+					cost: find("cost").and_then(|c| c.parse().ok()).unwrap_or(default.cost(id)),
+				});
+			}
+		}
+		let rows = (first.tile_count + first.columns.max(1) - 1) / first.columns.max(1);
+		Tileset::new(&format!("assets/{}", first.image), first.tile_width, first.columns, rows, tiles)
+	}
+
+	pub fn property(&self, name: &str) -> Option<&str> {
+		self.properties.iter().find(|p| p.name == name).map(|p| p.value.as_str())
+	}
+
+	/// Everything in the file that can not be represented by a game map
+	pub fn warnings(&self) -> Vec<String> {
+		let mut warnings = Vec::new();
+		let tilesets = self.sorted_tilesets();
+		if let Some(first) = tilesets.first() {
+			for tileset in tilesets.iter().skip(1) {
+				if tileset.image != first.image {
+					warnings.push(format!("Tiles of tileset '{}' are drawn from the image of tileset '{}', the game has one tileset image",
+						tileset.name, first.name));
+				}
+			}
+		}
+		for tileset in tilesets.iter() {
+			if !tileset.properties.is_empty() {
+				warnings.push(format!("Properties of tileset '{}' are dropped", tileset.name));
+			}
+		}
+		let default = Tileset::new_default();
+		let tileset = self.to_tileset();
+		let changed = tileset.tiles().iter().enumerate().filter(|&(id, info)| {
+			let id = id as u32;
+			info.blocking != default.is_blocking(id) || info.blocks_sight != default.blocks_sight(id) || info.cost != default.cost(id)
+		}).count();
+		if changed > 0 {
+			warnings.push(format!("{} tiles differ from the game tileset, map files only keep tile ids", changed));
+		}
+		if self.properties.iter().any(|p| p.name != "name" && p.name != "description") {
+			warnings.push("Map properties other than name and description are dropped".to_string());
+		}
+		let mut tile_layers = 0;
+		let mut flipped = false;
+		for layer in self.layers.iter() {
+			match layer.data {
+				TiledLayerData::Tiles(ref data) => {
+					tile_layers += 1;
+					if tile_layers > 3 {
+						warnings.push(format!("Tile layer '{}' is dropped, maps only have 3 layers", layer.name));
+					}
+					flipped = flipped || data.iter().any(|&gid| gid & FLIP_MASK != 0);
+					if data.iter().any(|&gid| gid & !FLIP_MASK != 0 && self.tile_id(gid).is_none()) {
+						warnings.push(format!("Tile layer '{}' has tiles outside of every tileset, they become tile 0", layer.name));
+					}
+				},
+				TiledLayerData::Objects(ref list) => {
+					for object in list.iter() {
+						if PropKind::from_name(&object.kind).or(PropKind::from_name(&object.name)).is_none() {
+							warnings.push(format!("Object {} of type '{}' is not a known prop", object.id, object.kind));
+						}
+						// blocks_movement is written on export and follows from the prop kind
+						if object.properties.iter().any(|p| p.name != "blocks_movement") {
+							warnings.push(format!("Properties of object {} are dropped", object.id));
+						}
+					}
+				},
+			}
+			if !layer.properties.is_empty() {
+				warnings.push(format!("Properties of layer '{}' are dropped", layer.name));
+			}
+		}
+		if flipped {
+			warnings.push("Flipped and rotated tiles lose their orientation".to_string());
+		}
+		warnings
+	}
+
+	/// Chooses TMX or JSON by the file extension
+	pub fn load(path: &str) -> Result<TiledMap, String> {
+		let mut file = File::open(path).map_err(|e| format!("Could not open {}: {}", path, e))?;
+		let mut buf = String::new();
+		file.read_to_string(&mut buf).map_err(|e| format!("Could not read {}: {}", path, e))?;
+		if path.ends_with(".tmx") {
+			TiledMap::from_tmx(&buf)
+		}
+		else {
+			TiledMap::from_json(&buf)
+		}
+	}
+
+	pub fn save(&self, path: &str) {
+		let text = if path.ends_with(".tmx") { self.to_tmx() } else { self.to_json() };
+		let mut file = File::create(path).expect("Could not create file!");
+		file.write_all(text.as_bytes()).expect("Could not write file!");
+	}
+
+	pub fn to_json(&self) -> String {
+		let layers = self.layers.iter().map(|layer| {
+			let mut fields = vec![
+				("id", Json::U64(layer.id as u64)),
+				("name", Json::String(layer.name.clone())),
+				("visible", Json::Boolean(layer.visible)),
+				("opacity", Json::F64(layer.opacity as f64)),
+				("x", Json::U64(0)),
+				("y", Json::U64(0)),
+			];
+			match layer.data {
+				TiledLayerData::Tiles(ref data) => {
+					fields.push(("type", Json::String("tilelayer".to_string())));
+					fields.push(("width", Json::U64(self.width as u64)));
+					fields.push(("height", Json::U64(self.height as u64)));
+					fields.push(("data", Json::Array(data.iter().map(|&gid| Json::U64(gid as u64)).collect())));
+				},
+				TiledLayerData::Objects(ref list) => {
+					fields.push(("type", Json::String("objectgroup".to_string())));
+					fields.push(("draworder", Json::String("topdown".to_string())));
+					fields.push(("objects", Json::Array(list.iter().map(|o| {
+						let mut object = vec![
+							("id", Json::U64(o.id as u64)),
+							("name", Json::String(o.name.clone())),
+							("type", Json::String(o.kind.clone())),
+							("x", Json::F64(o.x as f64)),
+							("y", Json::F64(o.y as f64)),
+							("width", Json::F64(o.width as f64)),
+							("height", Json::F64(o.height as f64)),
+							("rotation", Json::U64(0)),
+							("visible", Json::Boolean(true)),
+						];
+						if o.ellipse {
+							object.push(("ellipse", Json::Boolean(true)));
+						}
+						if !o.properties.is_empty() {
+							object.push(("properties", properties_to_json(&o.properties)));
+						}
+						json_object(object)
+					}).collect())));
+				},
+			}
+			if !layer.properties.is_empty() {
+				fields.push(("properties", properties_to_json(&layer.properties)));
+			}
+			json_object(fields)
+		}).collect();
+		let tilesets = self.tilesets.iter().map(|t| {
+			let mut fields = vec![
+				("firstgid", Json::U64(t.firstgid as u64)),
+				("name", Json::String(t.name.clone())),
+				("image", Json::String(t.image.clone())),
+				("imagewidth", Json::U64(t.image_width as u64)),
+				("imageheight", Json::U64(t.image_height as u64)),
+				("tilewidth", Json::U64(t.tile_width as u64)),
+				("tileheight", Json::U64(t.tile_height as u64)),
+				("columns", Json::U64(t.columns as u64)),
+				("tilecount", Json::U64(t.tile_count as u64)),
+				("margin", Json::U64(0)),
+				("spacing", Json::U64(0)),
+			];
+			if !t.tiles.is_empty() {
+				fields.push(("tiles", Json::Array(t.tiles.iter().map(|&(id, ref properties)| {
+					json_object(vec![
+						("id", Json::U64(id as u64)),
+						("properties", properties_to_json(properties)),
+					])
+				}).collect())));
+			}
+			if !t.properties.is_empty() {
+				fields.push(("properties", properties_to_json(&t.properties)));
+			}
+			json_object(fields)
+		}).collect();
+		let mut fields = vec![
+			("type", Json::String("map".to_string())),
+			("version", Json::String("1.10".to_string())),
+			("orientation", Json::String("orthogonal".to_string())),
+			("renderorder", Json::String("right-down".to_string())),
+			("infinite", Json::Boolean(false)),
+			("width", Json::U64(self.width as u64)),
+			("height", Json::U64(self.height as u64)),
+			("tilewidth", Json::U64(self.tile_width as u64)),
+			("tileheight", Json::U64(self.tile_height as u64)),
+			("nextlayerid", Json::U64(self.next_layer_id() as u64)),
+			("nextobjectid", Json::U64(self.next_object_id() as u64)),
+			("layers", Json::Array(layers)),
+			("tilesets", Json::Array(tilesets)),
+		];
+		if !self.properties.is_empty() {
+			fields.push(("properties", properties_to_json(&self.properties)));
+		}
+		json_object(fields).pretty().to_string()
+	}
+
+	pub fn from_json(text: &str) -> Result<TiledMap, String> {
+		let json = Json::from_str(text).map_err(|e| format!("Invalid JSON: {}", e))?;
+		if json.find("infinite").and_then(|i| i.as_boolean()).unwrap_or(false) {
+			return Err("Infinite maps are not supported".to_string());
+		}
+		let mut tilesets = Vec::new();
+		for t in json_array(&json, "tilesets")?.iter() {
+			if t.find("source").is_some() {
+				return Err("External tilesets are not supported, embed them in the map".to_string());
+			}
+			let mut tiles = Vec::new();
+			if let Some(list) = t.find("tiles").and_then(|l| l.as_array()) {
+				for tile in list.iter() {
+					tiles.push((json_u32(tile, "id")?, properties_from_json(tile)?));
+				}
+			}
+			tilesets.push(TiledTileset {
+				firstgid: json_u32(t, "firstgid")?,
+				name: json_string(t, "name").unwrap_or(String::new()),
+				image: json_string(t, "image")?,
+				image_width: json_u32(t, "imagewidth")?,
+				image_height: json_u32(t, "imageheight")?,
+				tile_width: json_u32(t, "tilewidth")?,
+				tile_height: json_u32(t, "tileheight")?,
+				columns: json_u32(t, "columns")?,
+				tile_count: json_u32(t, "tilecount")?,
+				tiles: tiles,
+				properties: properties_from_json(t)?,
+			});
+		}
+		let mut layers = Vec::new();
+		for l in json_array(&json, "layers")?.iter() {
+			let data = match json_string(l, "type")?.as_str() {
+				"tilelayer" => {
+					let data = match l.find("data") {
+						Some(&Json::Array(ref data)) => data,
+						Some(_) => return Err("Only uncompressed csv tile data is supported".to_string()),
+						None => return Err("Tile layer without data".to_string()),
+					};
+					let mut gids = Vec::with_capacity(data.len());
+					for gid in data.iter() {
+						gids.push(gid.as_u64().ok_or("Tile data must be numbers".to_string())? as u32);
+					}
+					TiledLayerData::Tiles(gids)
+				},
+				"objectgroup" => {
+					let mut objects = Vec::new();
+					for o in json_array(l, "objects")?.iter() {
+						objects.push(TiledObject {
+							id: json_u32(o, "id")?,
+							name: json_string(o, "name").unwrap_or(String::new()),
+							kind: json_string(o, "type").or(json_string(o, "class")).unwrap_or(String::new()),
+							x: json_f32(o, "x")?,
+							y: json_f32(o, "y")?,
+							width: json_f32(o, "width").unwrap_or(0f32),
+							height: json_f32(o, "height").unwrap_or(0f32),
+							ellipse: o.find("ellipse").and_then(|e| e.as_boolean()).unwrap_or(false),
+							properties: properties_from_json(o)?,
+						});
+					}
+					TiledLayerData::Objects(objects)
+				},
+				// Image layers and groups have no map data
+				_ => continue,
+			};
+			layers.push(TiledLayer {
+				id: json_u32(l, "id").unwrap_or(layers.len() as u32 + 1),
+				name: json_string(l, "name").unwrap_or(String::new()),
+				visible: l.find("visible").and_then(|v| v.as_boolean()).unwrap_or(true),
+				opacity: json_f32(l, "opacity").unwrap_or(1f32),
+				data: data,
+				properties: properties_from_json(l)?,
+			});
+		}
+		Ok(TiledMap {
+			width: json_u32(&json, "width")? as usize,
+			height: json_u32(&json, "height")? as usize,
+			tile_width: json_u32(&json, "tilewidth")?,
+			tile_height: json_u32(&json, "tileheight")?,
+			properties: properties_from_json(&json)?,
+			tilesets: tilesets,
+			layers: layers,
+		})
+	}
+
+	pub fn to_tmx(&self) -> String {
+		let mut out = String::new();
+		out.push_str("<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n");
+		out.push_str(&format!("<map version=\"1.10\" orientation=\"orthogonal\" renderorder=\"right-down\" width=\"{}\" height=\"{}\" tilewidth=\"{}\" tileheight=\"{}\" infinite=\"0\" nextlayerid=\"{}\" nextobjectid=\"{}\">\n",
+			self.width, self.height, self.tile_width, self.tile_height, self.next_layer_id(), self.next_object_id()));
+		properties_to_tmx(&mut out, &self.properties, 1);
+		for t in self.tilesets.iter() {
+			out.push_str(&format!(" <tileset firstgid=\"{}\" name=\"{}\" tilewidth=\"{}\" tileheight=\"{}\" tilecount=\"{}\" columns=\"{}\">\n",
+				t.firstgid, escape(&t.name), t.tile_width, t.tile_height, t.tile_count, t.columns));
+			properties_to_tmx(&mut out, &t.properties, 2);
+			out.push_str(&format!("  <image source=\"{}\" width=\"{}\" height=\"{}\"/>\n", escape(&t.image), t.image_width, t.image_height));
+			for &(id, ref properties) in t.tiles.iter() {
+				out.push_str(&format!("  <tile id=\"{}\">\n", id));
+				properties_to_tmx(&mut out, properties, 3);
+				out.push_str("  </tile>\n");
+			}
+			out.push_str(" </tileset>\n");
+		}
+		for layer in self.layers.iter() {
+			let visible = if layer.visible { "" } else { " visible=\"0\"" };
+			let opacity = if layer.opacity < 1f32 { format!(" opacity=\"{}\"", layer.opacity) } else { String::new() };
+			match layer.data {
+				TiledLayerData::Tiles(ref data) => {
+					out.push_str(&format!(" <layer id=\"{}\" name=\"{}\" width=\"{}\" height=\"{}\"{}{}>\n",
+						layer.id, escape(&layer.name), self.width, self.height, visible, opacity));
+					properties_to_tmx(&mut out, &layer.properties, 2);
+					out.push_str("  <data encoding=\"csv\">\n");
+					let rows: Vec<String> = data.chunks(self.width.max(1)).map(|row| {
+						row.iter().map(|gid| gid.to_string()).collect::<Vec<String>>().join(",")
+					}).collect();
+					out.push_str(&rows.join(",\n"));
+					out.push_str("\n</data>\n");
+					out.push_str(" </layer>\n");
+				},
+				TiledLayerData::Objects(ref list) => {
+					out.push_str(&format!(" <objectgroup id=\"{}\" name=\"{}\"{}{}>\n", layer.id, escape(&layer.name), visible, opacity));
+					properties_to_tmx(&mut out, &layer.properties, 2);
+					for o in list.iter() {
+						out.push_str(&format!("  <object id=\"{}\"", o.id));
+						if !o.name.is_empty() {
+							out.push_str(&format!(" name=\"{}\"", escape(&o.name)));
+						}
+						if !o.kind.is_empty() {
+							out.push_str(&format!(" type=\"{}\"", escape(&o.kind)));
+						}
+						out.push_str(&format!(" x=\"{}\" y=\"{}\" width=\"{}\" height=\"{}\">\n", o.x, o.y, o.width, o.height));
+						properties_to_tmx(&mut out, &o.properties, 3);
+						if o.ellipse {
+							out.push_str("   <ellipse/>\n");
+						}
+						out.push_str("  </object>\n");
+					}
+					out.push_str(" </objectgroup>\n");
+				},
+			}
+		}
+		out.push_str("</map>\n");
+		out
+	}
+
+	pub fn from_tmx(text: &str) -> Result<TiledMap, String> {
+		let root = XmlParser::new(text).parse()?;
+		if root.name != "map" {
+			return Err(format!("Expected <map>, found <{}>", root.name));
+		}
+		if root.attribute("infinite") == Some("1") {
+			return Err("Infinite maps are not supported".to_string());
+		}
+		let (width, height) = (root.number("width")? as usize, root.number("height")? as usize);
+		let mut tilesets = Vec::new();
+		let mut layers = Vec::new();
+		for child in root.children.iter() {
+			match child.name.as_str() {
+				"tileset" => {
+					if child.attribute("source").is_some() {
+						return Err("External tilesets are not supported, embed them in the map".to_string());
+					}
+					let image = child.child("image").ok_or("Tileset without image".to_string())?;
+					let mut tiles = Vec::new();
+					for tile in child.children.iter().filter(|c| c.name == "tile") {
+						tiles.push((tile.number("id")?, properties_from_tmx(tile)));
+					}
+					tilesets.push(TiledTileset {
+						firstgid: child.number("firstgid")?,
+						name: child.attribute("name").unwrap_or("").to_string(),
+						image: image.attribute("source").ok_or("Tileset image without source".to_string())?.to_string(),
+						image_width: image.number("width")?,
+						image_height: image.number("height")?,
+						tile_width: child.number("tilewidth")?,
+						tile_height: child.number("tileheight")?,
+						columns: child.number("columns")?,
+						tile_count: child.number("tilecount")?,
+						tiles: tiles,
+						properties: properties_from_tmx(child),
+					});
+				},
+				"layer" => {
+					let data = child.child("data").ok_or("Layer without data".to_string())?;
+					let gids = match data.attribute("encoding") {
+						Some("csv") => {
+							let mut gids = Vec::with_capacity(width * height);
+							for value in data.text.split(',').map(|v| v.trim()).filter(|v| !v.is_empty()) {
+								gids.push(value.parse::<u32>().map_err(|_| format!("Invalid tile '{}'", value))?);
+							}
+							gids
+						},
+						None => data.children.iter().filter(|c| c.name == "tile").map(|c| c.number("gid").unwrap_or(0)).collect(),
+						Some(encoding) => return Err(format!("Tile data encoding '{}' is not supported, save as csv", encoding)),
+					};
+					layers.push(TiledLayer {
+						id: child.number("id").unwrap_or(layers.len() as u32 + 1),
+						name: child.attribute("name").unwrap_or("").to_string(),
+						visible: child.attribute("visible") != Some("0"),
+						opacity: child.attribute("opacity").and_then(|o| o.parse().ok()).unwrap_or(1f32),
+						data: TiledLayerData::Tiles(gids),
+						properties: properties_from_tmx(child),
+					});
+				},
+				"objectgroup" => {
+					let mut objects = Vec::new();
+					for o in child.children.iter().filter(|c| c.name == "object") {
+						let float = |name: &str| o.attribute(name).and_then(|v| v.parse::<f32>().ok()).unwrap_or(0f32);
+						objects.push(TiledObject {
+							id: o.number("id")?,
+							name: o.attribute("name").unwrap_or("").to_string(),
+							kind: o.attribute("type").or(o.attribute("class")).unwrap_or("").to_string(),
+							x: float("x"),
+							y: float("y"),
+							width: float("width"),
+							height: float("height"),
+							ellipse: o.child("ellipse").is_some(),
+							properties: properties_from_tmx(o),
+						});
+					}
+					layers.push(TiledLayer {
+						id: child.number("id").unwrap_or(layers.len() as u32 + 1),
+						name: child.attribute("name").unwrap_or("").to_string(),
+						visible: child.attribute("visible") != Some("0"),
+						opacity: child.attribute("opacity").and_then(|o| o.parse().ok()).unwrap_or(1f32),
+						data: TiledLayerData::Objects(objects),
+						properties: properties_from_tmx(child),
+					});
+				},
+				_ => (),
+			}
+		}
+		Ok(TiledMap {
+			width: width,
+			height: height,
+			tile_width: root.number("tilewidth")?,
+			tile_height: root.number("tileheight")?,
+			properties: properties_from_tmx(&root),
+			tilesets: tilesets,
+			layers: layers,
+		})
+	}
+
+	fn next_layer_id(&self) -> u32 {
+		self.layers.iter().map(|l| l.id + 1).max().unwrap_or(1)
+	}
+
+	fn next_object_id(&self) -> u32 {
+		self.layers.iter().filter_map(|l| match l.data {
+			TiledLayerData::Objects(ref list) => list.iter().map(|o| o.id + 1).max(),
+			_ => None,
+		}).max().unwrap_or(1)
+	}
+}
+
+fn json_object(fields: Vec<(&str, Json)>) -> Json {
+	let mut map = BTreeMap::new();
+	for (key, value) in fields {
+		map.insert(key.to_string(), value);
+	}
+	Json::Object(map)
+}
+
+fn json_array<'a>(json: &'a Json, key: &str) -> Result<&'a Vec<Json>, String> {
+	json.find(key).and_then(|v| v.as_array()).ok_or(format!("Missing list '{}'", key))
+}
+
+fn json_u32(json: &Json, key: &str) -> Result<u32, String> {
+	json.find(key).and_then(|v| v.as_u64()).map(|v| v as u32).ok_or(format!("Missing number '{}'", key))
+}
+
+fn json_f32(json: &Json, key: &str) -> Result<f32, String> {
+	json.find(key).and_then(|v| v.as_f64()).map(|v| v as f32).ok_or(format!("Missing number '{}'", key))
+}
+
+fn json_string(json: &Json, key: &str) -> Result<String, String> {
+	json.find(key).and_then(|v| v.as_string()).map(|v| v.to_string()).ok_or(format!("Missing text '{}'", key))
+}
+
+fn properties_to_json(properties: &[TiledProperty]) -> Json {
+	Json::Array(properties.iter().map(|p| {
+		let value = match p.kind.as_str() {
+			"int" => p.value.parse::<i64>().map(Json::I64).unwrap_or(Json::I64(0)),
+			"float" => p.value.parse::<f64>().map(Json::F64).unwrap_or(Json::F64(0.0)),
+			"bool" => Json::Boolean(p.value == "true"),
+			_ => Json::String(p.value.clone()),
+		};
+		json_object(vec![
+			("name", Json::String(p.name.clone())),
+			("type", Json::String(p.kind.clone())),
+			("value", value),
+		])
+	}).collect())
+}
+
+fn properties_from_json(json: &Json) -> Result<Vec<TiledProperty>, String> {
+	let list = match json.find("properties").and_then(|p| p.as_array()) {
+		Some(list) => list,
+		None => return Ok(Vec::new()),
+	};
+	let mut properties = Vec::new();
+	for p in list.iter() {
+		let value = match p.find("value") {
+			Some(&Json::String(ref s)) => s.clone(),
+			Some(&Json::Boolean(b)) => b.to_string(),
+			Some(&Json::I64(n)) => n.to_string(),
+			Some(&Json::U64(n)) => n.to_string(),
+			Some(&Json::F64(n)) => n.to_string(),
+			_ => String::new(),
+		};
+		properties.push(TiledProperty {
+			name: json_string(p, "name")?,
+			kind: json_string(p, "type").unwrap_or("string".to_string()),
+			value: value,
+		});
+	}
+	Ok(properties)
+}
+
+fn properties_to_tmx(out: &mut String, properties: &[TiledProperty], indent: usize) {
+	if properties.is_empty() {
+		return;
+	}
+	let pad = " ".repeat(indent);
+	out.push_str(&format!("{}<properties>\n", pad));
+	for p in properties.iter() {
+		let kind = if p.kind == "string" { String::new() } else { format!(" type=\"{}\"", escape(&p.kind)) };
+		out.push_str(&format!("{} <property name=\"{}\"{} value=\"{}\"/>\n", pad, escape(&p.name), kind, escape(&p.value)));
+	}
+	out.push_str(&format!("{}</properties>\n", pad));
+}
+
+fn properties_from_tmx(element: &XmlElement) -> Vec<TiledProperty> {
+	match element.child("properties") {
+		Some(properties) => properties.children.iter().filter(|c| c.name == "property").map(|p| {
+			TiledProperty {
+				name: p.attribute("name").unwrap_or("").to_string(),
+				kind: p.attribute("type").unwrap_or("string").to_string(),
+				// Multi line strings are stored as text instead of an attribute
+				value: p.attribute("value").map(|v| v.to_string()).unwrap_or(p.text.clone()),
+			}
+		}).collect(),
+		None => Vec::new(),
+	}
+}
+
+fn escape(text: &str) -> String {
+	text.replace('&', "&amp;").replace('<', "&lt;").replace('>', "&gt;").replace('"', "&quot;").replace('\n', "&#10;")
+}
+
+/// Named and numeric entities in one pass, unknown ones are kept as they are
+fn unescape(text: &str) -> String {
+	let mut out = String::with_capacity(text.len());
+	let mut rest = text;
+	while let Some(start) = rest.find('&') {
+		out.push_str(&rest[..start]);
+		rest = &rest[start..];
+		let end = match rest.find(';') {
+			Some(end) => end,
+			None => break,
+		};
+		let decoded = match &rest[1..end] {
+			"lt" => Some('<'),
+			"gt" => Some('>'),
+			"amp" => Some('&'),
+			"quot" => Some('"'),
+			"apos" => Some('\''),
+			entity if entity.starts_with("#x") => u32::from_str_radix(&entity[2..], 16).ok().and_then(::std::char::from_u32),
+			entity if entity.starts_with('#') => entity[1..].parse::<u32>().ok().and_then(::std::char::from_u32),
+			_ => None,
+		};
+		match decoded {
+			Some(c) => {
+				out.push(c);
+				rest = &rest[end + 1..];
+			},
+			None => {
+				out.push('&');
+				rest = &rest[1..];
+			},
+		}
+	}
+	out.push_str(rest);
+	out
+}
+
+struct XmlElement {
+	name: String,
+	attributes: Vec<(String, String)>,
+	children: Vec<XmlElement>,
+	text: String,
+}
+
+impl XmlElement {
+	fn attribute(&self, name: &str) -> Option<&str> {
+		self.attributes.iter().find(|a| a.0 == name).map(|a| a.1.as_str())
+	}
+
+	fn number(&self, name: &str) -> Result<u32, String> {
+		match self.attribute(name).map(|v| v.parse::<u32>()) {
+			Some(Ok(value)) => Ok(value),
+			_ => Err(format!("<{}> needs a number '{}'", self.name, name)),
+		}
+	}
+
+	fn child(&self, name: &str) -> Option<&XmlElement> {
+		self.children.iter().find(|c| c.name == name)
+	}
+}
+
+/// Just enough XML for TMX files: elements, attributes, text and comments
+struct XmlParser {
+	chars: Vec<char>,
+	pos: usize,
+}
+
+impl XmlParser {
+	fn new(text: &str) -> Self {
+		XmlParser {
+			chars: text.chars().collect(),
+			pos: 0,
+		}
+	}
+
+	fn parse(&mut self) -> Result<XmlElement, String> {
+		loop {
+			self.skip_whitespace();
+			if self.starts_with("<?") {
+				self.skip_past("?>")?;
+			}
+			else if self.starts_with("<!--") {
+				self.skip_past("-->")?;
+			}
+			else if self.starts_with("<!") {
+				self.skip_past(">")?;
+			}
+			else {
+				return self.element();
+			}
+		}
+	}
+
+	fn element(&mut self) -> Result<XmlElement, String> {
+		self.expect("<")?;
+		let mut element = XmlElement {
+			name: self.name(),
+			attributes: Vec::new(),
+			children: Vec::new(),
+			text: String::new(),
+		};
+		loop {
+			self.skip_whitespace();
+			if self.starts_with("/>") {
+				self.pos += 2;
+				return Ok(element);
+			}
+			if self.starts_with(">") {
+				self.pos += 1;
+				break;
+			}
+			let name = self.name();
+			if name.is_empty() {
+				return Err(format!("Broken attribute in <{}>", element.name));
+			}
+			self.skip_whitespace();
+			self.expect("=")?;
+			self.skip_whitespace();
+			let quote = self.next().ok_or("Unexpected end of file".to_string())?;
+			if quote != '"' && quote != '\'' {
+				return Err(format!("Attribute '{}' is not quoted", name));
+			}
+			let mut value = String::new();
+			loop {
+				match self.next() {
+					Some(c) if c == quote => break,
+					Some(c) => value.push(c),
+					None => return Err("Unexpected end of file".to_string()),
+				}
+			}
+			element.attributes.push((name, unescape(&value)));
+		}
+		let mut text = String::new();
+		loop {
+			if self.pos >= self.chars.len() {
+				return Err(format!("<{}> is never closed", element.name));
+			}
+			if self.starts_with("</") {
+				self.pos += 2;
+				let name = self.name();
+				if name != element.name {
+					return Err(format!("<{}> closed by </{}>", element.name, name));
+				}
+				self.skip_whitespace();
+				self.expect(">")?;
+				break;
+			}
+			else if self.starts_with("<!--") {
+				self.skip_past("-->")?;
+			}
+			else if self.starts_with("<![CDATA[") {
+				self.pos += 9;
+				while !self.starts_with("]]>") {
+					text.push(self.next().ok_or("Unexpected end of file".to_string())?);
+				}
+				self.pos += 3;
+			}
+			else if self.starts_with("<") {
+				let child = self.element()?;
+				element.children.push(child);
+			}
+			else {
+				text.push(self.chars[self.pos]);
+				self.pos += 1;
+			}
+		}
+		element.text = unescape(&text);
+		Ok(element)
+	}
+
+	fn name(&mut self) -> String {
+		let mut name = String::new();
+		while let Some(&c) = self.chars.get(self.pos) {
+			if c.is_alphanumeric() || c == '_' || c == '-' || c == ':' || c == '.' {
+				name.push(c);
+				self.pos += 1;
+			}
+			else {
+				break;
+			}
+		}
+		name
+	}
+
+	fn next(&mut self) -> Option<char> {
+		let c = self.chars.get(self.pos).cloned();
+		self.pos += 1;
+		c
+	}
+
+	fn starts_with(&self, text: &str) -> bool {
+		let mut i = self.pos;
+		for c in text.chars() {
+			if self.chars.get(i) != Some(&c) {
+				return false;
+			}
+			i += 1;
+		}
+		true
+	}
+
+	fn expect(&mut self, text: &str) -> Result<(), String> {
+		if self.starts_with(text) {
+			self.pos += text.chars().count();
+			Ok(())
+		}
+		else {
+			Err(format!("Expected '{}' at character {}", text, self.pos))
+		}
+	}
+
+	fn skip_past(&mut self, text: &str) -> Result<(), String> {
+		while !self.starts_with(text) {
+			if self.pos >= self.chars.len() {
+				return Err(format!("Missing '{}'", text));
+			}
+			self.pos += 1;
+		}
+		self.pos += text.chars().count();
+		Ok(())
+	}
+
+	fn skip_whitespace(&mut self) {
+		while self.chars.get(self.pos).map(|c| c.is_whitespace()).unwrap_or(false) {
+			self.pos += 1;
+		}
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use game::map_generator::{Grass, ShallowWater, Road, Forest, Building};
+
+	fn small_map() -> Map {
+		let mut map = Map::new_init(3, 2,
+			vec![Grass, Road, Building, ShallowWater, Road, Forest],
+			vec![0, 0, 3, 0, 0, 0],
+			vec![0; 6]);
+		let mut objects = ObjectLayer::new();
+		objects.add(MapObject::new(PropKind::Tree, 0.5, 0.5));
+		objects.add(MapObject::new(PropKind::Loot(::game::objects::LootTable::Farm), 2.5, 1.5));
+		map.set_objects(objects);
+		map
+	}
+
+	fn tiles(map: &Map, layer: usize) -> Vec<u32> {
+		map.get_layer(layer).tiles().iter().cloned().collect()
+	}
+
+	fn assert_same_map(a: &Map, b: &Map) {
+		for layer in 0..3 {
+			assert_eq!(tiles(a, layer), tiles(b, layer));
+		}
+		assert_eq!(a.get_objects().len(), b.get_objects().len());
+		for (x, y) in a.get_objects().iter().zip(b.get_objects().iter()) {
+			assert_eq!(x.kind, y.kind);
+			assert!((x.x - y.x).abs() < 1e-4 && (x.y - y.y).abs() < 1e-4);
+		}
+	}
+
+	fn tileset(firstgid: u32, name: &str, tile_count: u32) -> TiledTileset {
+		TiledTileset {
+			firstgid: firstgid,
+			name: name.to_string(),
+			image: format!("{}.png", name),
+			image_width: 32 * tile_count,
+			image_height: 32,
+			tile_width: 32,
+			tile_height: 32,
+			columns: tile_count,
+			tile_count: tile_count,
+			tiles: Vec::new(),
+			properties: Vec::new(),
+		}
+	}
+
+	fn one_layer(data: Vec<u32>, tilesets: Vec<TiledTileset>) -> TiledMap {
+		TiledMap {
+			width: data.len(),
+			height: 1,
+			tile_width: 32,
+			tile_height: 32,
+			properties: Vec::new(),
+			tilesets: tilesets,
+			layers: vec![TiledLayer {
+				id: 1,
+				name: "ground".to_string(),
+				visible: true,
+				opacity: 1.0,
+				data: TiledLayerData::Tiles(data),
+				properties: Vec::new(),
+			}],
+		}
+	}
+
+	#[test]
+	fn tmx_round_trip() {
+		let map = small_map();
+		let tiled = TiledMap::from_map(&map, "Town & <river>", "\"quoted\"\nsecond line", &Tileset::new_default());
+		let loaded = TiledMap::from_tmx(&tiled.to_tmx()).unwrap();
+		assert_eq!(loaded.property("name"), Some("Town & <river>"));
+		assert_eq!(loaded.property("description"), Some("\"quoted\"\nsecond line"));
+		assert!(loaded.warnings().is_empty());
+		assert_same_map(&map, &loaded.to_map().unwrap());
+	}
+
+	#[test]
+	fn json_round_trip() {
+		let map = small_map();
+		let tiled = TiledMap::from_map(&map, "Town & <river>", "\"quoted\"\nsecond line", &Tileset::new_default());
+		let loaded = TiledMap::from_json(&tiled.to_json()).unwrap();
+		assert_eq!(loaded.property("name"), Some("Town & <river>"));
+		assert_eq!(loaded.property("description"), Some("\"quoted\"\nsecond line"));
+		assert!(loaded.warnings().is_empty());
+		assert_same_map(&map, &loaded.to_map().unwrap());
+	}
+
+	#[test]
+	fn gids_continue_per_tileset() {
+		let tiled = one_layer(vec![0, 1, 10, 11, 14, 15], vec![tileset(11, "props", 4), tileset(1, "ground", 10)]);
+		assert_eq!(tiled.tile_id(0), None);
+		assert_eq!(tiled.tile_id(1), Some(0));
+		assert_eq!(tiled.tile_id(10), Some(9));
+		assert_eq!(tiled.tile_id(11), Some(10));
+		assert_eq!(tiled.tile_id(14), Some(13));
+		assert_eq!(tiled.tile_id(15), None);
+		assert_eq!(tiles(&tiled.to_map().unwrap(), 0), vec![0, 0, 9, 10, 13, 0]);
+	}
+
+	#[test]
+	fn flip_bits_are_ignored() {
+		let tiled = one_layer(vec![3 | 0x80000000, 3 | 0x40000000], vec![tileset(1, "ground", 10)]);
+		assert_eq!(tiles(&tiled.to_map().unwrap(), 0), vec![2, 2]);
+		assert!(tiled.warnings().iter().any(|w| w.contains("Flipped")));
+	}
+
+	#[test]
+	fn later_tileset_properties_follow_their_ids() {
+		let mut props = tileset(11, "props", 4);
+		props.tiles.push((2, vec![
+			TiledProperty::new("color", "string", "#ff0000"),
+			TiledProperty::new("blocking", "bool", "true"),
+			TiledProperty::new("name", "string", "Crate"),
+		]));
+		let tiled = one_layer(vec![13], vec![tileset(1, "ground", 10), props]);
+		let result = tiled.to_tileset();
+		assert_eq!(result.len(), 14);
+		assert_eq!(result.get(12).unwrap().name, "Crate");
+		assert_eq!(result.color(12), (255, 0, 0));
+		assert!(result.is_blocking(12));
+		assert_eq!(result.get(11).unwrap().name, "props 1");
+		assert!(!result.is_blocking(11));
+		assert!(tiled.to_map().unwrap().get_tileset().is_blocking(12));
+	}
+
+	#[test]
+	fn warns_about_what_is_dropped() {
+		let mut tiled = one_layer(vec![1, 20], vec![tileset(1, "ground", 10), tileset(11, "props", 4)]);
+		tiled.layers.push(TiledLayer {
+			id: 2,
+			name: "objects".to_string(),
+			visible: true,
+			opacity: 1.0,
+			data: TiledLayerData::Objects(vec![TiledObject {
+				id: 7,
+				name: String::new(),
+				kind: "tree".to_string(),
+				x: 0.0,
+				y: 0.0,
+				width: 16.0,
+				height: 16.0,
+				ellipse: true,
+				properties: vec![TiledProperty::new("health", "int", "3")],
+			}]),
+			properties: Vec::new(),
+		});
+		let warnings = tiled.warnings();
+		assert!(warnings.iter().any(|w| w.contains("'props'") && w.contains("image")));
+		assert!(warnings.iter().any(|w| w.contains("outside of every tileset")));
+		assert!(warnings.iter().any(|w| w.contains("object 7")));
+	}
+
+	#[test]
+	fn unescape_entities() {
+		assert_eq!(unescape("&lt;a&gt; &amp;&quot;&apos;"), "<a> &\"'");
+		assert_eq!(unescape("&#10;&#x41;&#66;"), "\nAB");
+		assert_eq!(unescape("&amp;lt;"), "&lt;");
+		assert_eq!(unescape("a & b &unknown; &"), "a & b &unknown; &");
+		let text = "<\"line\" & 'more'>\nend";
+		assert_eq!(unescape(&escape(text)), text);
+	}
+
+	#[test]
+	fn xml_parser_elements() {
+		let text = "<?xml version=\"1.0\"?>\n<!-- head -->\n<map a=\"1\" b='x &amp; y'>\
+			<!-- inside --><empty/><data><![CDATA[1,<2>]]> &lt;3</data></map>";
+		let root = XmlParser::new(text).parse().unwrap();
+		assert_eq!(root.name, "map");
+		assert_eq!(root.attribute("a"), Some("1"));
+		assert_eq!(root.attribute("b"), Some("x & y"));
+		assert_eq!(root.children.len(), 2);
+		assert!(root.child("empty").unwrap().children.is_empty());
+		assert_eq!(root.child("data").unwrap().text, "1,<2> <3");
+	}
+
+	#[test]
+	fn xml_parser_errors() {
+		assert!(XmlParser::new("<map><layer></map>").parse().is_err());
+		assert!(XmlParser::new("<map>").parse().is_err());
+		assert!(XmlParser::new("<map a=1/>").parse().is_err());
+		assert!(XmlParser::new("<map><![CDATA[open").parse().is_err());
+	}
+}
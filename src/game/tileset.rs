@@ -0,0 +1,121 @@
+use game::map_generator;
+
+pub struct TileInfo {
+	pub name: String,
+	pub color: (u8, u8, u8),		// used where the tileset image is not available
+	pub blocking: bool,				// walls are added between blocking and open tiles
+	pub blocks_sight: bool,			// stops rays, see Map::raycast
+	pub cost: f32,					// movement cost per tile, 1 is grass
+}
+
+pub struct Tileset {
+	image: String,
+	tile_size: u32,
+	columns: u32,
+	rows: u32,
+	tiles: Vec<TileInfo>,
+}
+
+const UNKNOWN_COLOR: (u8, u8, u8) = (255, 0, 255);
+
+impl Tileset {
+	pub fn new(image: &str, tile_size: u32, columns: u32, rows: u32, tiles: Vec<TileInfo>) -> Self {
+		Tileset {
+			image: image.to_string(),
+			tile_size: tile_size,
+			columns: columns.max(1),
+			rows: rows,
+			tiles: tiles,
+		}
+	}
+
+	pub fn new_default() -> Self {
+		let tiles = [
+			((78, 154, 52), 1.0),		// Grass
+			((82, 160, 200), 3.0),		// ShallowWater
+			((110, 110, 110), 0.5),		// Road
+			((235, 240, 245), 2.0),		// Snow
+			((24, 56, 140), 1.0),		// DeepWater
+			((28, 90, 36), 1.5),		// Forest
+			((130, 96, 60), 1.0),		// Dirt
+			((218, 200, 140), 1.5),		// Sand
+			((84, 100, 56), 3.0),		// Swamp
+			((150, 60, 50), 1.0),		// Building
+		];
+		let tiles = tiles.iter().enumerate().map(|(id, &(color, cost))| {
+			TileInfo {
+				name: map_generator::tile_name(id as u32).to_string(),
+				color: color,
+				cost: cost,
+				blocking: !map_generator::is_walkable(id as u32),
+				blocks_sight: id as u32 == map_generator::Building || id as u32 == map_generator::Forest,
+			}
+		}).collect();
+		Tileset::new("assets/tileset.png", 64, 10, 10, tiles)
+	}
+
+	pub fn get(&self, id: u32) -> Option<&TileInfo> {
+		self.tiles.get(id as usize)
+	}
+
+	pub fn color(&self, id: u32) -> (u8, u8, u8) {
+		match self.get(id) {
+			Some(info) => info.color,
+			None => UNKNOWN_COLOR,
+		}
+	}
+
+	/// Tiles without metadata never block
+	pub fn is_blocking(&self, id: u32) -> bool {
+		match self.get(id) {
+			Some(info) => info.blocking,
+			None => false,
+		}
+	}
+
+	pub fn blocks_sight(&self, id: u32) -> bool {
+		match self.get(id) {
+			Some(info) => info.blocks_sight,
+			None => false,
+		}
+	}
+
+	/// Tiles without metadata cost as much as grass
+	pub fn cost(&self, id: u32) -> f32 {
+		match self.get(id) {
+			Some(info) => info.cost,
+			None => 1.0,
+		}
+	}
+
+	/// Number of tiles with metadata, the image can hold more
+	pub fn len(&self) -> usize {
+		self.tiles.len()
+	}
+
+	pub fn tiles(&self) -> &[TileInfo] {
+		&self.tiles
+	}
+
+	pub fn columns(&self) -> u32 {
+		self.columns
+	}
+
+	/// Number of tiles in the tileset image
+	pub fn tile_count(&self) -> u32 {
+		self.columns * self.rows
+	}
+
+	pub fn image_path(&self) -> &str {
+		&self.image
+	}
+
+	pub fn tile_size(&self) -> u32 {
+		self.tile_size
+	}
+
+	/// Top left pixel of a tile in the tileset image
+	pub fn image_position(&self, id: u32) -> (u32, u32) {
+		((id % self.columns) * self.tile_size, (id / self.columns) * self.tile_size)
+	}
+}
@@ -0,0 +1,102 @@
+pub const TICK_RATE: f32 = 60.0;			// simulation ticks per second
+pub const MAX_CATCH_UP: usize = 5;			// ticks run at most per frame, the rest of a long frame is dropped
+
+/// Turns variable frame times into a whole number of fixed simulation ticks
+pub struct FixedTimestep {
+	step: f32,
+	max_steps: usize,
+	accumulator: f32,
+}
+
+impl FixedTimestep {
+	pub fn new(tick_rate: f32, max_steps: usize) -> Self {
+		FixedTimestep {
+			step: 1.0 / tick_rate,
+			max_steps: max_steps.max(1),
+			accumulator: 0.0,
+		}
+	}
+
+	pub fn new_default() -> Self {
+		FixedTimestep::new(TICK_RATE, MAX_CATCH_UP)
+	}
+
+	/// More catch up ticks for running faster than real time
+	pub fn set_max_steps(&mut self, max_steps: usize) {
+		self.max_steps = max_steps.max(1);
+	}
+
+	/// Seconds per tick, the delta every update gets
+	pub fn step(&self) -> f32 {
+		self.step
+	}
+
+	/// Adds the frame time and returns how many ticks to run. After a stall the simulation
+	/// slows down instead of running ever more ticks to catch up
+	pub fn advance(&mut self, delta: f32) -> usize {
+		self.accumulator += delta.max(0.0);
+		let ticks = (self.accumulator / self.step) as usize;
+		if ticks > self.max_steps {
+			self.accumulator = 0.0;
+			return self.max_steps;
+		}
+		self.accumulator -= ticks as f32 * self.step;
+		ticks
+	}
+
+	/// How far the frame is between the last tick and the next one, 0 to 1. Renderers draw the state
+	/// blended between the last two ticks by this much, so motion stays smooth at any frame rate
+	pub fn alpha(&self) -> f32 {
+		(self.accumulator / self.step).min(1.0)
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	// A quarter second step keeps the sums exact
+	#[test]
+	fn advance_counts_whole_steps() {
+		let mut timestep = FixedTimestep::new(4.0, 5);
+		assert_eq!(timestep.advance(0.125), 0);
+		assert_eq!(timestep.alpha(), 0.5);
+		assert_eq!(timestep.advance(0.125), 1);
+		assert_eq!(timestep.alpha(), 0.0);
+		assert_eq!(timestep.advance(0.875), 3);
+		assert_eq!(timestep.alpha(), 0.5);
+		assert_eq!(timestep.advance(-1.0), 0);
+		assert_eq!(timestep.alpha(), 0.5);
+	}
+
+	#[test]
+	fn long_frames_are_capped() {
+		let mut timestep = FixedTimestep::new(4.0, 5);
+		assert_eq!(timestep.advance(10.0), 5);
+		assert_eq!(timestep.alpha(), 0.0);
+		assert_eq!(timestep.advance(0.25), 1);
+		assert_eq!(timestep.advance(1.5), 5);
+		assert_eq!(timestep.advance(1.75), 5);
+		assert_eq!(timestep.alpha(), 0.0);
+	}
+
+	#[test]
+	fn alpha_stays_between_0_and_1() {
+		let mut timestep = FixedTimestep::new_default();
+		for i in 0..1000 {
+			timestep.advance((i % 37) as f32 * 0.0013);
+			let alpha = timestep.alpha();
+			assert!(alpha >= 0.0 && alpha <= 1.0, "alpha {} after frame {}", alpha, i);
+		}
+	}
+
+	#[test]
+	fn max_steps_is_at_least_1() {
+		let mut timestep = FixedTimestep::new(4.0, 0);
+		assert_eq!(timestep.advance(1.0), 1);
+		timestep.set_max_steps(0);
+		assert_eq!(timestep.advance(1.0), 1);
+		timestep.set_max_steps(3);
+		assert_eq!(timestep.advance(1.0), 3);
+	}
+}
@@ -1,41 +1,373 @@
 use game::camera::Camera;
-use game::map::{Map, Layer, MapLoader};
-use sfml::graphics::{Drawable, RenderStates, RenderTarget, RectangleShape, Color};
-//use sfml::system::{Vector2f};
-use game::map_generator::{Noise, NoiseRenderer};
+use game::map::{Map, MapLoader};
+use sfml::graphics::Color;
+use sfml::system::{Vector2f};
+use rand::{Rng, SeedableRng, StdRng};
+use game::fov::{FieldOfView, ExploredTiles};
+use game::spatial::SpatialHash;
+use game::ecs::{Entity, Entities};
+use game::components::{Components, Position, Velocity, Collider, Sprite, Health, Ai, Behaviour, Inventory, PlayerControl};
+use game::movement::{BodyShape, MovementSolver};
+use game::flowfield::{FlowFields, DistanceMap};
+use game::pathfinding::MovementProfile;
+use game::systems;
+use game::objects::{PropKind, LootTable};
+use game::picking::{self, Pick, WorldPoint, PICK_RADIUS};
 
 pub struct World {
 	camera: Camera,
 	map: Map,
-	noise_renderer: NoiseRenderer,
+	fov: FieldOfView,
+	explored: ExploredTiles,
+	spatial: SpatialHash<Entity>,
+	entities: Entities,
+	components: Components,
+	solver: MovementSolver,
+	flow_fields: FlowFields,
+	rng: StdRng,
+	seed: Vec<usize>,
+	player: Entity,
+	player_tile: Option<(usize, usize)>,		// goal of the "players" flow field
+	player_health: f32,							// at the end of the last update, to notice hits
 }
 
+const VIEW_RADIUS: f32 = 24.0;
+const SPATIAL_CELL_SIZE: usize = 8;
+const ZOMBIE_COUNT: usize = 200;		// at least, zombie spawn markers come first and random tiles fill up the rest
+const PLAYER_SPEED: f32 = 4.0;
+const CHASE_DISTANCE: f32 = 40.0;		// zombies further away from the player wander
+const HIT_TRAUMA: f32 = 0.02;			// screen shake per point of damage the player takes
+const DEFAULT_SEED: [usize; 4] = [1, 2, 3, 4];
+
 
 impl World {
 	pub fn new() -> Self {
-		World {
+		World::from_map(MapLoader::load("testmap2.json"))
+	}
+
+	pub fn new_empty() -> Self {
+		World::from_map(Map::new_default())
+	}
+
+	pub fn from_map(map: Map) -> Self {
+		World::from_map_seed(map, &DEFAULT_SEED)
+	}
+
+	/// The seed drives everything random, the same map and seed give the same start
+	pub fn from_map_seed(mut map: Map, seed: &[usize]) -> Self {
+		map.add_path_hierarchy(MovementProfile::new_human());
+		let mut flow_fields = FlowFields::new();
+		let mut players = DistanceMap::new(&map, MovementProfile::new_zombie());
+		players.limit = CHASE_DISTANCE;
+		flow_fields.insert("players", players);
+		let mut entities = Entities::new();
+		let player = entities.create();
+		let mut world = World {
 			camera: Camera::new(64f32, 36f32, 50f32),
-			map: MapLoader::load("testmap2.json"),
-			noise_renderer: {
-				let x = Noise::new_perlin_from_seed(&[4,5,6,7], 64, 64, 6, 0.8);
-				NoiseRenderer::new(&x)
+			fov: FieldOfView::new(&map, VIEW_RADIUS),
+			explored: ExploredTiles::new(map.width(), map.height()),
+			spatial: SpatialHash::new(map.width(), map.height(), SPATIAL_CELL_SIZE),
+			entities: entities,
+			components: Components::new(),
+			solver: MovementSolver::new(),
+			flow_fields: flow_fields,
+			rng: SeedableRng::from_seed(seed),
+			seed: seed.to_vec(),
+			player: player,
+			player_tile: None,
+			player_health: 0.0,
+			map: map,
+		};
+		world.camera.fit_to_map(&world.map);
+		let start = world.find_walkable(world.map.width() / 2, world.map.height() / 2);
+		world.add_player(player, start);
+		world.camera.snap_to(start);
+		world.populate(ZOMBIE_COUNT);
+		world
+	}
+
+	/// Centre of the walkable tile closest to the given one, searching outwards ring by ring
+	fn find_walkable(&self, x: usize, y: usize) -> Vector2f {
+		let (width, height) = (self.map.width() as isize, self.map.height() as isize);
+		for ring in 0..width.max(height) {
+			for dy in -ring..(ring + 1) {
+				for dx in -ring..(ring + 1) {
+					if dx.abs() != ring && dy.abs() != ring {
+						continue;
+					}
+					let (tx, ty) = (x as isize + dx, y as isize + dy);
+					if tx >= 0 && ty >= 0 && tx < width && ty < height && !self.map.is_blocking(tx as usize, ty as usize) {
+						return Vector2f::new(tx as f32 + 0.5, ty as f32 + 0.5);
+					}
+				}
 			}
 		}
+		Vector2f::new(x as f32 + 0.5, y as f32 + 0.5)
 	}
 
-	pub fn new_empty() -> Self {
-		World {
-			camera: Camera::new(64f32, 36f32, 50f32),
-			map: Map::new_default(),
-			noise_renderer: {
-				let x = Noise::new_perlin_from_seed(&[1,2,3,4], 64, 64, 6, 0.5);
-				NoiseRenderer::new(&x)
+	fn add_player(&mut self, entity: Entity, position: Vector2f) {
+		self.components.positions.insert(entity, Position::new(position));
+		self.components.velocities.insert(entity, Velocity {
+			velocity: Vector2f::new(0.0, 0.0),
+		});
+		self.components.colliders.insert(entity, Collider {
+			shape: BodyShape::Circle(0.3),
+		});
+		self.components.sprites.insert(entity, Sprite {
+			color: Color::new_rgb(60, 120, 220),
+			size: 0.6,
+		});
+		self.components.healths.insert(entity, Health::new(100.0));
+		self.components.inventories.insert(entity, Inventory::new(20));
+		self.components.players.insert(entity, PlayerControl {
+			direction: Vector2f::new(0.0, 0.0),
+			aim: position,
+			speed: PLAYER_SPEED,
+		});
+	}
+
+	/// Zombies and loot on the markers of the map, then zombies on random walkable tiles until
+	/// there are count of them
+	fn populate(&mut self, count: usize) {
+		let markers: Vec<(PropKind, Vector2f)> = self.map.get_objects().iter().map(|o| (o.kind, Vector2f::new(o.x, o.y))).collect();
+		let mut spawns = 0;
+		for &(kind, position) in markers.iter() {
+			match kind {
+				PropKind::ZombieSpawn => {
+					if !self.map.is_blocking(position.x as usize, position.y as usize) {
+						self.spawn_zombie(position);
+						spawns += 1;
+					}
+				},
+				PropKind::Loot(table) => {
+					self.spawn_loot(position, table);
+				},
+				_ => (),
+			}
+		}
+		let (width, height) = (self.map.width(), self.map.height());
+		let mut placed = spawns;
+		for _ in 0..(count * 10) {
+			if placed >= count {
+				break;
+			}
+			let (x, y) = (self.rng.gen_range(0, width), self.rng.gen_range(0, height));
+			if !self.map.is_blocking(x, y) {
+				self.spawn_zombie(Vector2f::new(x as f32 + 0.5, y as f32 + 0.5));
+				placed += 1;
 			}
 		}
 	}
 
+	/// One simulation tick, systems run in this order
 	pub fn update(&mut self, delta: f32) {
+		systems::snapshot_system(&mut self.components);
+		systems::player_system(&mut self.components);
+		systems::ai_system(&mut self.components, &self.map, &self.flow_fields, &mut self.rng, delta);
+		systems::movement_system(&mut self.components, &self.map, &self.solver, delta);
+		for entity in systems::health_system(&self.components) {
+			self.despawn(entity);
+		}
+		systems::spatial_system(&self.components, &mut self.spatial);
+		self.update_player_goal();
+		let health = self.components.healths.get(self.player).map(|health| health.current).unwrap_or(0.0);
+		if health < self.player_health {
+			self.camera.add_trauma((self.player_health - health) * HIT_TRAUMA);
+		}
+		self.player_health = health;
+		let observer = match self.get_player_position() {
+			Some(position) => {
+				self.camera.follow(position);
+				position
+			},
+			None => {
+				self.camera.stop_following();
+				self.camera.get_view().get_center()
+			},
+		};
 		self.camera.update(delta);
+		self.fov.compute(&self.map, observer);
+		self.explored.reveal(&self.fov);
+	}
+
+	/// Moves the camera between its last two positions
+	pub fn interpolate(&mut self, alpha: f32) {
+		self.camera.interpolate(alpha);
+	}
+
+	/// The flow field is only touched when the player enters another tile
+	fn update_player_goal(&mut self) {
+		let tile = self.get_player_position().and_then(|position| {
+			if position.x < 0.0 || position.y < 0.0 || position.x >= self.map.width() as f32 || position.y >= self.map.height() as f32 {
+				None
+			}
+			else {
+				Some((position.x as usize, position.y as usize))
+			}
+		});
+		if tile == self.player_tile {
+			return;
+		}
+		self.player_tile = tile;
+		let goals: Vec<(usize, usize)> = tile.into_iter().collect();
+		if let Some(players) = self.flow_fields.get_mut("players") {
+			players.set_goal_tiles(&self.map, &goals);
+		}
+	}
+
+	pub fn spawn(&mut self) -> Entity {
+		self.entities.create()
+	}
+
+	/// Returns false if the entity was already gone
+	pub fn despawn(&mut self, entity: Entity) -> bool {
+		if !self.entities.destroy(entity) {
+			return false;
+		}
+		self.components.remove_all(entity);
+		self.spatial.remove(entity);
+		true
+	}
+
+	pub fn spawn_zombie(&mut self, position: Vector2f) -> Entity {
+		let entity = self.spawn();
+		self.components.positions.insert(entity, Position::new(position));
+		self.components.velocities.insert(entity, Velocity {
+			velocity: Vector2f::new(0.0, 0.0),
+		});
+		self.components.colliders.insert(entity, Collider {
+			shape: BodyShape::Circle(0.3),
+		});
+		self.components.sprites.insert(entity, Sprite {
+			color: Color::new_rgb(90, 140, 60),
+			size: 0.6,
+		});
+		self.components.healths.insert(entity, Health::new(50.0));
+		self.components.ais.insert(entity, Ai::new(Behaviour::Chase("players"), 1.5));
+		entity
+	}
+
+	/// Container with the items of the loot table
+	pub fn spawn_loot(&mut self, position: Vector2f, table: LootTable) -> Entity {
+		let entity = self.spawn();
+		self.components.positions.insert(entity, Position::new(position));
+		self.components.sprites.insert(entity, Sprite {
+			color: Color::new_rgb(200, 170, 60),
+			size: 0.4,
+		});
+		let items = table.items();
+		let mut inventory = Inventory::new(items.len());
+		for &(name, count) in items.iter() {
+			inventory.add(name, count);
+		}
+		self.components.inventories.insert(entity, inventory);
+		entity
+	}
+
+	/// Tile, edge and visible entity under the point
+	pub fn pick(&self, point: WorldPoint) -> Pick {
+		let fov = &self.fov;
+		let candidates = self.spatial.query_radius(point.to_vector(), PICK_RADIUS).into_iter().filter(|&entity| {
+			self.components.positions.get(entity).map(|position| fov.can_see(position.position)).unwrap_or(false)
+		});
+		Pick::new(&self.map, point, picking::entity_at(&self.components, candidates, point))
+	}
+
+	/// Digest of the entities and their state, two worlds that hash differently have diverged.
+	/// Stored in replays, so it has to stay the same across compilers and platforms
+	pub fn state_hash(&self) -> u64 {
+		let mut hasher = StateHasher::new();
+		hasher.write_u32(self.entities.len() as u32);
+		for (entity, position) in self.components.positions.iter() {
+			hasher.write_u32(entity.index() as u32);
+			hasher.write_u32(entity.generation());
+			hasher.write_u32(position.position.x.to_bits());
+			hasher.write_u32(position.position.y.to_bits());
+			hasher.write_u32(position.facing.to_bits());
+		}
+		for (entity, velocity) in self.components.velocities.iter() {
+			hasher.write_u32(entity.index() as u32);
+			hasher.write_u32(velocity.velocity.x.to_bits());
+			hasher.write_u32(velocity.velocity.y.to_bits());
+		}
+		for (entity, health) in self.components.healths.iter() {
+			hasher.write_u32(entity.index() as u32);
+			hasher.write_u32(health.current.to_bits());
+		}
+		for (entity, ai) in self.components.ais.iter() {
+			hasher.write_u32(entity.index() as u32);
+			hasher.write_u32(ai.timer.to_bits());
+		}
+		hasher.finish()
+	}
+
+	pub fn get_seed(&self) -> &[usize] {
+		&self.seed
+	}
+
+	pub fn get_player(&self) -> Entity {
+		self.player
+	}
+
+	pub fn get_player_position(&self) -> Option<Vector2f> {
+		self.components.positions.get(self.player).map(|position| position.position)
+	}
+
+	/// Direction the player walks in, from the input handler
+	pub fn set_player_direction(&mut self, direction: Vector2f) {
+		if let Some(control) = self.components.players.get_mut(self.player) {
+			control.direction = direction;
+		}
+	}
+
+	/// Point in tiles the player aims at
+	pub fn set_player_aim(&mut self, aim: Vector2f) {
+		if let Some(control) = self.components.players.get_mut(self.player) {
+			control.aim = aim;
+		}
+	}
+
+	pub fn is_alive(&self, entity: Entity) -> bool {
+		self.entities.is_alive(entity)
+	}
+
+	pub fn get_entities(&self) -> &Entities {
+		&self.entities
+	}
+
+	pub fn get_components(&self) -> &Components {
+		&self.components
+	}
+
+	pub fn get_mut_components(&mut self) -> &mut Components {
+		&mut self.components
+	}
+
+	pub fn get_map(&self) -> &Map {
+		&self.map
+	}
+
+	pub fn get_flow_fields(&self) -> &FlowFields {
+		&self.flow_fields
+	}
+
+	pub fn get_mut_flow_fields(&mut self) -> &mut FlowFields {
+		&mut self.flow_fields
+	}
+
+	/// Entities are only drawn where this is true
+	pub fn get_fov(&self) -> &FieldOfView {
+		&self.fov
+	}
+
+	/// Tiles the player has seen at some point
+	pub fn get_explored(&self) -> &ExploredTiles {
+		&self.explored
+	}
+
+	/// Who is near a point or inside an area
+	pub fn get_spatial(&self) -> &SpatialHash<Entity> {
+		&self.spatial
 	}
 
 	pub fn get_mut_camera(&mut self) -> &mut Camera {
@@ -47,11 +379,51 @@ impl World {
 	}
 }
 
-impl Drawable for World {
-	fn draw<RT: RenderTarget>(&self, render_target: &mut RT, _: &mut RenderStates) {
-		//render_target.draw(&self.test);
-		//render_target.draw(&self.map);
-		render_target.draw(self.map.get_layer(0));
-		//render_target.draw(&self.noise_renderer)
+/// 64 bit FNV-1a over little endian numbers, unlike the std hashers its output is fixed
+struct StateHasher {
+	hash: u64,
+}
+
+impl StateHasher {
+	fn new() -> Self {
+		StateHasher {
+			hash: 0xcbf29ce484222325,
+		}
+	}
+
+	fn write(&mut self, bytes: &[u8]) {
+		for &byte in bytes.iter() {
+			self.hash ^= byte as u64;
+			self.hash = self.hash.wrapping_mul(0x100000001b3);
+		}
 	}
-}
\ No newline at end of file
+
+	fn write_u32(&mut self, value: u32) {
+		self.write(&[value as u8, (value >> 8) as u8, (value >> 16) as u8, (value >> 24) as u8]);
+	}
+
+	fn finish(&self) -> u64 {
+		self.hash
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	fn fnv(bytes: &[u8]) -> u64 {
+		let mut hasher = StateHasher::new();
+		hasher.write(bytes);
+		hasher.finish()
+	}
+
+	#[test]
+	fn state_hasher_is_fnv1a() {
+		assert_eq!(fnv(b""), 0xcbf29ce484222325);
+		assert_eq!(fnv(b"a"), 0xaf63dc4c8601ec8c);
+		assert_eq!(fnv(b"foobar"), 0x85944171f73967e8);
+		let mut hasher = StateHasher::new();
+		hasher.write_u32(0x64636261);
+		assert_eq!(hasher.finish(), fnv(b"abcd"));
+	}
+}
@@ -0,0 +1,53 @@
+use sfml::graphics::{Drawable, RenderStates, RenderTarget};
+use game::world::World;
+use game::map::LayerRenderer;
+use game::map_generator::{Noise, NoiseRenderer};
+use game::fov::FogRenderer;
+use game::components::EntityRenderer;
+use game::picking::{self, TilePoint};
+
+/// Everything needed to draw a World, kept apart so the World itself runs without a window
+pub struct WorldRenderer {
+	layer_renderer: LayerRenderer,
+	noise_renderer: NoiseRenderer,
+	entity_renderer: EntityRenderer,
+	fog_renderer: FogRenderer,
+	selection: Option<TilePoint>,
+}
+
+impl WorldRenderer {
+	pub fn new(world: &World) -> Self {
+		let map = world.get_map();
+		let x = Noise::new_perlin_from_seed(&[1,2,3,4], 64, 64, 6, 0.5);
+		WorldRenderer {
+			layer_renderer: LayerRenderer::new(map.get_layer(0)),
+			noise_renderer: NoiseRenderer::new(&x),
+			entity_renderer: EntityRenderer::new(),
+			fog_renderer: FogRenderer::new(map.width(), map.height()),
+			selection: None,
+		}
+	}
+
+	/// Tile drawn with an outline, None for none
+	pub fn set_selection(&mut self, tile: Option<TilePoint>) {
+		self.selection = tile;
+	}
+
+	/// Refreshes the fog and places the entities the player can see between their last two positions
+	pub fn update(&mut self, world: &World, alpha: f32) {
+		self.fog_renderer.update(world.get_fov(), world.get_explored());
+		self.entity_renderer.update(world.get_components(), Some(world.get_fov()), alpha);
+	}
+}
+
+impl Drawable for WorldRenderer {
+	fn draw<RT: RenderTarget>(&self, render_target: &mut RT, _: &mut RenderStates) {
+		render_target.draw(&self.layer_renderer);
+		//render_target.draw(&self.noise_renderer)
+		render_target.draw(&self.entity_renderer);
+		render_target.draw(&self.fog_renderer);
+		if let Some(tile) = self.selection {
+			picking::draw_tile_outline(render_target, tile);
+		}
+	}
+}
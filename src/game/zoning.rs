@@ -0,0 +1,391 @@
+use rand::{Rng, SeedableRng, StdRng};
+use game::building::Building;
+use game::map_generator::{Noise, Rect, RoadGenerator};
+use game::map_generator::{self, Grass, Forest, Dirt, Road, ShallowWater, DeepWater, Snow, Swamp};
+use game::objects::{MapObject, PropKind, LootTable};
+use util::Vec2D;
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Zone {
+	Residential,
+	Commercial,
+	Industrial,
+	Park,
+	Farmland,
+}
+
+/// Everything that is decided by the zone of a block
+pub struct ZoneProfile {
+	pub min_building_size: usize,
+	pub max_building_size: usize,
+	pub lot_size: usize,				// side of the square lots a block is divided into
+	pub building_chance: f32,			// chance that a lot gets a building
+	pub prefabs: &'static [&'static str],
+	pub loot_table: LootTable,
+	pub zombie_density: f32,			// zombies per 100 tiles
+	pub ground: Option<u32>,			// replaces grass inside the block
+}
+
+const RESIDENTIAL_PREFABS: &'static [&'static str] = &["house", "bungalow", "townhouse", "garage"];
+const COMMERCIAL_PREFABS: &'static [&'static str] = &["shop", "supermarket", "office", "gas_station", "pharmacy"];
+const INDUSTRIAL_PREFABS: &'static [&'static str] = &["warehouse", "factory", "workshop"];
+const PARK_PREFABS: &'static [&'static str] = &["gazebo", "toilet_block"];
+const FARMLAND_PREFABS: &'static [&'static str] = &["farmhouse", "barn", "silo"];
+
+impl Zone {
+	pub fn profile(&self) -> ZoneProfile {
+		match *self {
+			Zone::Residential => ZoneProfile {
+				min_building_size: 3,
+				max_building_size: 5,
+				lot_size: 7,
+				building_chance: 0.8,
+				prefabs: RESIDENTIAL_PREFABS,
+				loot_table: LootTable::Residential,
+				zombie_density: 1.5,
+				ground: None,
+			},
+			Zone::Commercial => ZoneProfile {
+				min_building_size: 5,
+				max_building_size: 9,
+				lot_size: 10,
+				building_chance: 0.9,
+				prefabs: COMMERCIAL_PREFABS,
+				loot_table: LootTable::Commercial,
+				zombie_density: 3.0,
+				ground: None,
+			},
+			Zone::Industrial => ZoneProfile {
+				min_building_size: 6,
+				max_building_size: 12,
+				lot_size: 14,
+				building_chance: 0.6,
+				prefabs: INDUSTRIAL_PREFABS,
+				loot_table: LootTable::Industrial,
+				zombie_density: 1.0,
+				ground: Some(Dirt),
+			},
+			Zone::Park => ZoneProfile {
+				min_building_size: 2,
+				max_building_size: 3,
+				lot_size: 12,
+				building_chance: 0.15,
+				prefabs: PARK_PREFABS,
+				loot_table: LootTable::Park,
+				zombie_density: 0.5,
+				ground: Some(Forest),
+			},
+			Zone::Farmland => ZoneProfile {
+				min_building_size: 3,
+				max_building_size: 6,
+				lot_size: 16,
+				building_chance: 0.3,
+				prefabs: FARMLAND_PREFABS,
+				loot_table: LootTable::Farm,
+				zombie_density: 0.3,
+				ground: Some(Dirt),
+			},
+		}
+	}
+}
+
+pub struct Block {
+	pub rect: Rect,
+	pub zone: Zone,
+}
+
+impl Block {
+	pub fn zombie_count(&self) -> usize {
+		let area = (self.rect.width() * self.rect.height()) as f32;
+		(area * self.zone.profile().zombie_density / 100f32) as usize
+	}
+}
+
+/// Labels road blocks with a zone, based on noise and the distance from the town centre
+pub struct ZoneGenerator {
+	noise: Noise,
+	center: (usize, usize),
+}
+
+impl ZoneGenerator {
+	pub fn new(seed: &[usize], width: usize, height: usize) -> Self {
+		ZoneGenerator {
+			noise: Noise::new_perlin_from_seed(seed, width, height, 4, 0.6),
+			center: (width / 2, height / 2),
+		}
+	}
+
+	pub fn set_center(&mut self, x: usize, y: usize) {
+		self.center = (x, y);
+	}
+
+	pub fn classify(&self, rect: &Rect) -> Zone {
+		let (x, y) = rect.center();
+		let n = self.noise[(x.min(self.noise.width() - 1), y.min(self.noise.height() - 1))];
+		let dx = x as f32 - self.center.0 as f32;
+		let dy = y as f32 - self.center.1 as f32;
+		let max_distance = (self.noise.width().max(self.noise.height()) / 2) as f32;
+		let distance = (dx * dx + dy * dy).sqrt() / max_distance;
+		match distance {
+			d if d < 0.25 => if n > 0.7 { Zone::Park } else { Zone::Commercial },
+			d if d < 0.55 => match n {
+				n if n > 0.65 => Zone::Park,
+				n if n < 0.25 => Zone::Commercial,
+				_ => Zone::Residential,
+			},
+			d if d < 0.8 => if n < 0.4 { Zone::Industrial } else { Zone::Residential },
+			_ => if n < 0.2 { Zone::Industrial } else { Zone::Farmland },
+		}
+	}
+
+	pub fn zone_blocks(&self, road_generator: &RoadGenerator) -> Vec<Block> {
+		road_generator.blocks().iter().map(|rect| {
+			Block {
+				rect: Rect::new(rect.x1, rect.y1, rect.x2, rect.y2),
+				zone: self.classify(rect),
+			}
+		}).collect()
+	}
+}
+
+/// Fills zoned blocks with buildings picked from the zone profile
+pub struct TownGenerator {
+	rng: StdRng,
+}
+
+impl TownGenerator {
+	pub fn new(seed: &[usize]) -> Self {
+		TownGenerator {
+			rng: SeedableRng::from_seed(seed),
+		}
+	}
+
+	pub fn generate(&mut self, blocks: &[Block], tiles: &Vec2D<u32>) -> Vec<Building> {
+		let mut buildings = Vec::new();
+		for block in blocks.iter() {
+			self.fill_block(block, tiles, &mut buildings);
+		}
+		buildings
+	}
+
+	fn fill_block(&mut self, block: &Block, tiles: &Vec2D<u32>, buildings: &mut Vec<Building>) {
+		let profile = block.zone.profile();
+		// Keep one tile between the road and the buildings
+		if block.rect.width() < profile.min_building_size + 2 || block.rect.height() < profile.min_building_size + 2 {
+			return;
+		}
+		let inner = Rect::new(block.rect.x1 + 1, block.rect.y1 + 1, block.rect.x2 - 1, block.rect.y2 - 1);
+		let mut y = inner.y1;
+		while y + profile.min_building_size <= inner.y2 {
+			let lot_height = profile.lot_size.min(inner.y2 - y);
+			let mut x = inner.x1;
+			while x + profile.min_building_size <= inner.x2 {
+				let lot_width = profile.lot_size.min(inner.x2 - x);
+				if self.rng.gen::<f32>() < profile.building_chance {
+					let width = self.building_size(&profile, lot_width);
+					let height = self.building_size(&profile, lot_height);
+					if width >= profile.min_building_size && height >= profile.min_building_size && Self::is_buildable(tiles, x, y, width, height) {
+						let prefab = profile.prefabs[self.rng.gen::<usize>() % profile.prefabs.len()];
+						buildings.push(Building::new_zoned(x as u32, y as u32, width, height, block.zone, prefab));
+					}
+				}
+				x += lot_width + 1;
+			}
+			y += lot_height + 1;
+		}
+	}
+
+	/// Zombie spawns on random walkable tiles of every block, as many as its zone density asks for,
+	/// and a loot container in the middle of every building
+	pub fn markers(&mut self, blocks: &[Block], buildings: &[Building], tiles: &Vec2D<u32>) -> Vec<MapObject> {
+		let mut markers = Vec::new();
+		for block in blocks.iter() {
+			let (x1, y1) = (block.rect.x1, block.rect.y1);
+			let (x2, y2) = (block.rect.x2.min(tiles.width()), block.rect.y2.min(tiles.height()));
+			if x2 <= x1 || y2 <= y1 {
+				continue;
+			}
+			let count = block.zombie_count();
+			let mut placed = 0;
+			for _ in 0..(count * 10) {
+				if placed == count {
+					break;
+				}
+				let x = x1 + self.rng.gen::<usize>() % (x2 - x1);
+				let y = y1 + self.rng.gen::<usize>() % (y2 - y1);
+				if map_generator::is_walkable(tiles[(x, y)]) {
+					markers.push(MapObject::new(PropKind::ZombieSpawn, x as f32 + 0.5, y as f32 + 0.5));
+					placed += 1;
+				}
+			}
+		}
+		for building in buildings.iter() {
+			if let Some(table) = building.loot_table() {
+				let position = building.position();
+				let x = position.x as f32 + building.width() as f32 / 2.0;
+				let y = position.y as f32 + building.height() as f32 / 2.0;
+				markers.push(MapObject::new(PropKind::Loot(table), x, y));
+			}
+		}
+		markers
+	}
+
+	fn is_buildable(tiles: &Vec2D<u32>, x: usize, y: usize, width: usize, height: usize) -> bool {
+		if x + width > tiles.width() || y + height > tiles.height() {
+			return false;
+		}
+		for ty in y..(y + height) {
+			for tx in x..(x + width) {
+				match tiles[(tx, ty)] {
+					Road | ShallowWater | DeepWater | Snow | Swamp => return false,
+					_ => (),
+				}
+			}
+		}
+		true
+	}
+
+	fn building_size(&mut self, profile: &ZoneProfile, lot: usize) -> usize {
+		let max = profile.max_building_size.min(lot);
+		if max <= profile.min_building_size {
+			return max;
+		}
+		profile.min_building_size + self.rng.gen::<usize>() % (max - profile.min_building_size + 1)
+	}
+
+	/// Applies the zone ground tiles and the buildings to a tile layer
+	pub fn stamp(tiles: &mut Vec2D<u32>, blocks: &[Block], buildings: &[Building]) {
+		for block in blocks.iter() {
+			if let Some(ground) = block.zone.profile().ground {
+				for y in block.rect.y1..block.rect.y2.min(tiles.height()) {
+					for x in block.rect.x1..block.rect.x2.min(tiles.width()) {
+						if tiles[(x, y)] == Grass {
+							tiles[(x, y)] = ground;
+						}
+					}
+				}
+			}
+		}
+		for building in buildings.iter() {
+			building.stamp(tiles);
+		}
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	const ZONES: [Zone; 5] = [Zone::Residential, Zone::Commercial, Zone::Industrial, Zone::Park, Zone::Farmland];
+
+	fn grass(width: usize, height: usize) -> Vec2D<u32> {
+		Vec2D::from_vec(width, height, vec![Grass; width * height])
+	}
+
+	/// One 30 x 30 block per zone in a row, with a tile of road between them
+	fn blocks() -> Vec<Block> {
+		ZONES.iter().enumerate().map(|(i, &zone)| Block {
+			rect: Rect::new(i * 31, 0, i * 31 + 30, 30),
+			zone: zone,
+		}).collect()
+	}
+
+	fn summary(buildings: &[Building]) -> Vec<(u32, u32, usize, usize, Option<&'static str>)> {
+		buildings.iter().map(|b| (b.position().x, b.position().y, b.width(), b.height(), b.prefab())).collect()
+	}
+
+	#[test]
+	fn buildings_fit_their_block_and_profile() {
+		let tiles = grass(160, 30);
+		let mut generator = TownGenerator::new(&[3, 1, 4]);
+		let blocks = blocks();
+		for i in 0..blocks.len() {
+			let block = &blocks[i];
+			let profile = block.zone.profile();
+			let buildings = generator.generate(&blocks[i..(i + 1)], &tiles);
+			assert!(!buildings.is_empty() || block.zone == Zone::Park, "no buildings in {:?}", block.zone);
+			for building in buildings.iter() {
+				let (x, y) = (building.position().x as usize, building.position().y as usize);
+				assert!(x >= block.rect.x1 + 1 && y >= block.rect.y1 + 1);
+				assert!(x + building.width() <= block.rect.x2 - 1 && y + building.height() <= block.rect.y2 - 1);
+				assert!(building.width() >= profile.min_building_size && building.width() <= profile.max_building_size);
+				assert!(building.height() >= profile.min_building_size && building.height() <= profile.max_building_size);
+				assert_eq!(building.zone(), Some(block.zone));
+				assert!(profile.prefabs.contains(&building.prefab().unwrap()));
+			}
+		}
+	}
+
+	#[test]
+	fn buildings_avoid_unbuildable_tiles() {
+		let mut tiles = grass(160, 30);
+		for y in 0..30 {
+			for x in 0..160 {
+				let tile = match (x % 9, y % 11) {
+					(0, _) => Road,
+					(_, 0) => Swamp,
+					(4, 5) => ShallowWater,
+					(6, 7) => Snow,
+					(2, 3) => DeepWater,
+					_ => Grass,
+				};
+				tiles[(x, y)] = tile;
+			}
+		}
+		let buildings = TownGenerator::new(&[2, 7, 1]).generate(&blocks(), &tiles);
+		assert!(!buildings.is_empty());
+		for building in buildings.iter() {
+			let (x, y) = (building.position().x as usize, building.position().y as usize);
+			for ty in y..(y + building.height()) {
+				for tx in x..(x + building.width()) {
+					let tile = tiles[(tx, ty)];
+					assert!(tile == Grass, "building at ({}, {}) covers tile {} at ({}, {})", x, y, tile, tx, ty);
+				}
+			}
+		}
+	}
+
+	#[test]
+	fn markers_follow_zone_density() {
+		let tiles = grass(160, 30);
+		let blocks = blocks();
+		let mut generator = TownGenerator::new(&[5, 5, 5]);
+		let buildings = generator.generate(&blocks, &tiles);
+		let markers = generator.markers(&blocks, &buildings, &tiles);
+		for block in blocks.iter() {
+			let expected = (900.0 * block.zone.profile().zombie_density / 100.0) as usize;
+			assert_eq!(block.zombie_count(), expected);
+			let r = &block.rect;
+			let spawns = markers.iter().filter(|m| m.kind == PropKind::ZombieSpawn).filter(|m| {
+				m.x >= r.x1 as f32 && m.x < r.x2 as f32 && m.y >= r.y1 as f32 && m.y < r.y2 as f32
+			}).count();
+			assert_eq!(spawns, expected, "{:?}", block.zone);
+		}
+		let loot = markers.iter().filter(|m| match m.kind { PropKind::Loot(_) => true, _ => false }).count();
+		assert_eq!(loot, buildings.len());
+	}
+
+	#[test]
+	fn same_seed_same_town() {
+		let tiles = grass(160, 30);
+		let first = TownGenerator::new(&[9, 9]).generate(&blocks(), &tiles);
+		let second = TownGenerator::new(&[9, 9]).generate(&blocks(), &tiles);
+		let other = TownGenerator::new(&[9, 8]).generate(&blocks(), &tiles);
+		assert_eq!(summary(&first), summary(&second));
+		assert!(summary(&first) != summary(&other));
+	}
+
+	#[test]
+	fn zones_depend_on_the_distance_from_the_center() {
+		let zoning = ZoneGenerator::new(&[1, 2], 200, 200);
+		let again = ZoneGenerator::new(&[1, 2], 200, 200);
+		for &(x, y) in [(100, 100), (0, 0), (190, 10), (60, 140)].iter() {
+			let rect = Rect::new(x, y, x + 10, y + 10);
+			assert_eq!(zoning.classify(&rect), again.classify(&rect));
+		}
+		let center = zoning.classify(&Rect::new(95, 95, 105, 105));
+		assert!(center == Zone::Commercial || center == Zone::Park);
+		let corner = zoning.classify(&Rect::new(0, 0, 10, 10));
+		assert!(corner == Zone::Industrial || corner == Zone::Farmland);
+	}
+}
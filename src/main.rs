@@ -1,27 +1,29 @@
 extern crate sfml;
 extern crate rand;
 extern crate rustc_serialize;
+extern crate image;
 
 mod game;
 mod editor;
 mod util;
+mod cli;
+
+use std::env;
+use std::process;
 
 use game::Game;
 use editor::Editor;
+use cli::{GenerateOptions, PreviewOptions, ExportOptions, NoiseExportOptions, ImportOptions, ConvertOptions, SimulateOptions, RecordOptions, ReplayOptions};
 
 //use game::map::MapLoader;
 //use game::map_generator::MapGenerator;
-use game::map_generator::RoadGenerator;
 
 //use util::Vec2D;
 
 
 
 fn main() {
-
-	let mut x = RoadGenerator::new(&[1,2,3,4], 10, 10, 3);
-	x.generate();
-	//x.print_roads();
+	let args: Vec<String> = env::args().collect();
 
 	/*for c in CoordIterator::new(10,5) {
 		println!("{}, {}", c.0, c.1);
@@ -35,15 +37,30 @@ fn main() {
 
 	panic!("BANANA");*/
 
-	let start_editor = false;
-
-	if start_editor {
-		let mut editor = Editor::new(1600, 900);
-		editor.run();
-	}
-	else {
-    	let mut game = Game::new(1600,900, "Game", false);
-    	game.run();
+	match args.get(1).map(|s| s.as_str()) {
+		Some("generate") => cli::run(GenerateOptions::parse(&args[2..]), cli::generate),
+		Some("preview") => cli::run(PreviewOptions::parse(&args[2..]), cli::preview),
+		Some("export") => cli::run(ExportOptions::parse(&args[2..]), cli::export),
+		Some("export-noise") => cli::run(NoiseExportOptions::parse(&args[2..]), cli::export_noise),
+		Some("import") => cli::run(ImportOptions::parse(&args[2..]), cli::import),
+		Some("convert") => cli::run(ConvertOptions::parse(&args[2..]), cli::convert),
+		Some("tiled-export") => cli::run(ConvertOptions::parse(&args[2..]), cli::tiled_export),
+		Some("tiled-import") => cli::try_run(ConvertOptions::parse(&args[2..]), cli::tiled_import),
+		Some("simulate") => cli::run(SimulateOptions::parse(&args[2..]), cli::simulate),
+		Some("record") => cli::run(RecordOptions::parse(&args[2..]), cli::record),
+		Some("replay") => cli::try_run(ReplayOptions::parse(&args[2..]), cli::replay),
+		Some("editor") => {
+			let mut editor = Editor::new(1600, 900);
+			editor.run();
+		},
+		Some(_) => {
+			eprintln!("{}", cli::usage());
+			process::exit(1);
+		},
+		None => {
+    		let mut game = Game::new(1600,900, "Game", false);
+    		game.run();
+		},
 	}
     //MapLoader::test();
     //MapLoader::load();
@@ -72,6 +72,10 @@ impl<T> Vec2D<T> {
 	pub fn iter_mut(&mut self) -> IterMut<T> {
 		self.vec.iter_mut()
 	}
+
+	pub fn into_vec(self) -> Vec<T> {
+		self.vec
+	}
 }
 
 impl<T> Deref for Vec2D<T> {